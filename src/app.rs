@@ -1,17 +1,95 @@
 use egui::UiBuilder;
 use parking_lot::Mutex;
-use std::{borrow::Cow, sync::Arc};
-use tokio::runtime::Runtime;
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time;
+use tokio::{runtime::Runtime, task::JoinHandle};
+#[cfg(target_arch = "wasm32")]
+use tokio_with_wasm::alias::time;
 
 use crate::{
+    chat_settings::ChatSettingsDialog,
     clients::{
-        Chat, Client, LoginForm,
+        Chat, ChatKind, ChatTag, Client, Invite, LoginForm, Permissions, Presence, PresenceInfo,
+        Space, Topic,
         matrix::{self, MatrixClient},
         telegram::{self, TelegramClient},
     },
-    message::{MessageStyle, MessageWidget},
+    compose::{CreateChatDialog, InviteDialog},
+    composer::Composer,
+    directory::{DirectoryDialog, join_and_refresh, refresh_and_select},
+    ignored::{IgnoredUsersDialog, ignore_user},
+    members::MembersPanel,
+    message::{
+        MessageAction, MessageStyle, MessageWidget, paint_presence_dot, render_system_events,
+    },
+    moderation::{ModerationDialog, ModerationRequest},
+    notifications::NotificationSettingsDialog,
+    security::SecurityDialog,
+    spaces::SpaceBrowser,
+    threads::ThreadPanel,
 };
 
+/// Chat and event where the user stopped reading, once loaded
+type ReadMarker = Arc<Mutex<Option<(String, Option<String>)>>>;
+
+/// Delay before retrying a failed sync, doubled after each failure up to the maximum
+const SYNC_RETRY_MIN: Duration = Duration::from_secs(1);
+const SYNC_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Sidebar sections of the chat list, in display order
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ChatSection {
+    Favourites,
+    People,
+    Rooms,
+    LowPriority,
+}
+
+impl ChatSection {
+    const ALL: [Self; 4] = [
+        Self::Favourites,
+        Self::People,
+        Self::Rooms,
+        Self::LowPriority,
+    ];
+
+    fn of(chat: &Chat) -> Self {
+        match (chat.tag, chat.kind) {
+            (Some(ChatTag::Favourite), _) => Self::Favourites,
+            (Some(ChatTag::LowPriority), _) => Self::LowPriority,
+            (None, ChatKind::Direct | ChatKind::Bot) => Self::People,
+            (None, ChatKind::Group | ChatKind::Channel) => Self::Rooms,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Favourites => "Favourites",
+            Self::People => "People",
+            Self::Rooms => "Rooms",
+            Self::LowPriority => "Low priority",
+        }
+    }
+
+    /// Tag given to a chat dropped on the section
+    fn tag(self) -> Option<ChatTag> {
+        match self {
+            Self::Favourites => Some(ChatTag::Favourite),
+            Self::People | Self::Rooms => None,
+            Self::LowPriority => Some(ChatTag::LowPriority),
+        }
+    }
+}
+
+/// Response to a pending invite
+#[derive(Clone, Copy)]
+enum InviteAction {
+    Accept,
+    Decline,
+    Block,
+}
+
 /// Main application state for the EChat app
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -24,6 +102,64 @@ pub struct EChat {
     clients: Arc<Mutex<Vec<Arc<dyn Client>>>>,
     client_keys: Vec<String>,
     chats: Arc<Mutex<Vec<Chat>>>,
+    spaces: Arc<Mutex<Vec<Space>>>,
+    selected_space: Option<String>,
+    #[serde(skip)]
+    selected_chat: Arc<Mutex<Option<String>>>,
+    #[serde(skip)]
+    invites: Arc<Mutex<Vec<Invite>>>,
+    /// Why the last response to an invite failed
+    #[serde(skip)]
+    invite_error: Arc<Mutex<Option<String>>>,
+    #[serde(skip)]
+    space_browser: Option<SpaceBrowser>,
+    #[serde(skip)]
+    directory: Option<DirectoryDialog>,
+    #[serde(skip)]
+    create_dialog: Option<CreateChatDialog>,
+    #[serde(skip)]
+    invite_dialog: Option<InviteDialog>,
+    #[serde(skip)]
+    settings_dialog: Option<ChatSettingsDialog>,
+    #[serde(skip)]
+    members_panel: Option<MembersPanel>,
+    #[serde(skip)]
+    thread_panel: Option<ThreadPanel>,
+    #[serde(skip)]
+    permissions: Arc<Mutex<Permissions>>,
+    #[serde(skip)]
+    permissions_chat: Option<String>,
+    #[serde(skip)]
+    moderation_dialog: Option<ModerationDialog>,
+    #[serde(skip)]
+    security_dialog: Option<SecurityDialog>,
+    #[serde(skip)]
+    notification_dialog: Option<NotificationSettingsDialog>,
+    #[serde(skip)]
+    ignored_dialog: Option<IgnoredUsersDialog>,
+    #[serde(skip)]
+    read_marker: ReadMarker,
+    #[serde(skip)]
+    read_marker_chat: Option<String>,
+    /// Newest event a read receipt was sent for in the selected chat
+    #[serde(skip)]
+    read_sent: Option<String>,
+    #[serde(skip)]
+    composer: Composer,
+    /// Mention the "next mention" button jumped to last
+    #[serde(skip)]
+    mention_cursor: Option<String>,
+    /// Whether older messages of the selected chat are being fetched
+    #[serde(skip)]
+    loading_older: Arc<Mutex<bool>>,
+    /// Whether paging back reached the start of the selected chat
+    #[serde(skip)]
+    history_start: Arc<Mutex<bool>>,
+    /// Background sync loops, by client key
+    #[serde(skip)]
+    sync_tasks: HashMap<String, JoinHandle<()>>,
+    /// Whether other users may see when we are online
+    share_presence: bool,
     active_client_index: Option<usize>,
 }
 
@@ -47,6 +183,33 @@ impl Default for EChat {
             clients: Default::default(),
             client_keys: Vec::new(),
             chats: Arc::new(Mutex::new(Vec::new())),
+            spaces: Arc::new(Mutex::new(Vec::new())),
+            selected_space: None,
+            selected_chat: Arc::default(),
+            invites: Arc::default(),
+            invite_error: Arc::default(),
+            space_browser: None,
+            directory: None,
+            create_dialog: None,
+            invite_dialog: None,
+            settings_dialog: None,
+            members_panel: None,
+            thread_panel: None,
+            permissions: Arc::default(),
+            permissions_chat: None,
+            moderation_dialog: None,
+            security_dialog: None,
+            notification_dialog: None,
+            ignored_dialog: None,
+            read_marker: Arc::default(),
+            read_marker_chat: None,
+            read_sent: None,
+            composer: Composer::default(),
+            mention_cursor: None,
+            loading_older: Arc::default(),
+            history_start: Arc::default(),
+            sync_tasks: HashMap::new(),
+            share_presence: true,
             active_client_index: None,
         }
     }
@@ -85,7 +248,7 @@ impl EChat {
                     // Set first loaded client as active
                     if echat.active_client_index.is_none() {
                         echat.active_client_index = Some(0);
-                        echat.sync_client_chats(&client);
+                        echat.load_spaces(&client);
                     }
                 } else {
                     log::error!("Failed to load client with key: {}", key);
@@ -106,19 +269,21 @@ impl EChat {
         }
     }
 
-    fn sync_client_chats(&self, client: &Arc<dyn Client>) {
+    /// Fetch the space tree and invites for a client, e.g. right after login
+    fn load_spaces(&self, client: &Arc<dyn Client>) {
         let client_clone = client.clone();
-        let chats = self.chats.clone();
+        let spaces = self.spaces.clone();
+        let invites = self.invites.clone();
 
         self.rt.spawn(async move {
-            if let Err(e) = client_clone.sync().await {
-                log::error!("Failed to sync with server: {}", e);
-                return;
+            match client_clone.spaces().await {
+                Ok(client_spaces) => *spaces.lock() = client_spaces,
+                Err(e) => log::error!("Failed to fetch spaces: {}", e),
             }
 
-            match client_clone.chats().await {
-                Ok(client_chats) => *chats.lock() = client_chats,
-                Err(e) => log::error!("Failed to fetch chats: {}", e),
+            match client_clone.invites().await {
+                Ok(client_invites) => *invites.lock() = client_invites,
+                Err(e) => log::error!("Failed to fetch invites: {}", e),
             }
         });
     }
@@ -189,6 +354,7 @@ impl EChat {
 
                             self.active_client_index = Some(0);
                             self.logins.clear();
+                            self.load_spaces(&self.clients.lock()[0].clone());
                             break;
                         }
                     }
@@ -197,7 +363,7 @@ impl EChat {
         });
     }
 
-    fn show_chat_interface(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn show_chat_interface(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         let active_client_index = match self.active_client_index {
             Some(idx) if idx < self.clients.lock().len() => idx,
             _ => return,
@@ -205,30 +371,769 @@ impl EChat {
 
         let client = self.clients.lock()[active_client_index].clone();
 
+        let key = format!("{}-{}", client.client_name(), client.self_id());
+        if !self.sync_tasks.contains_key(&key) {
+            let task = self.spawn_sync_loop(ctx, &client);
+            self.sync_tasks.insert(key, task);
+        }
+
+        // Moderation capabilities and the read marker follow the selected chat
+        self.load_permissions(ctx, &client);
+        self.load_read_marker(ctx, &client);
+
         // Side panel for chat list
         self.show_chat_list(ctx, &client);
 
+        // Optional side panel for members of the selected chat
+        self.show_members_panel(ctx, &client);
+
+        // Optional side panel for threads of the selected chat
+        self.show_thread_panel(ctx, &client);
+
         // Main panel for chat messages
         self.show_message_panel(ctx, &client);
+
+        self.show_moderation_dialog(ctx, &client);
+        self.show_security_dialog(ctx, frame, &client);
+
+        if let Some(dialog) = &mut self.notification_dialog
+            && !dialog.show(ctx, &self.rt, &client)
+        {
+            self.notification_dialog = None;
+        }
+
+        if let Some(dialog) = &mut self.ignored_dialog
+            && !dialog.show(ctx, &self.rt, &client)
+        {
+            self.ignored_dialog = None;
+        }
+    }
+
+    fn show_security_dialog(
+        &mut self,
+        ctx: &egui::Context,
+        frame: &mut eframe::Frame,
+        client: &Arc<dyn Client>,
+    ) {
+        if let Some(dialog) = &mut self.security_dialog {
+            let open = dialog.show(ctx, &self.rt, client);
+            if dialog.logged_out() {
+                self.security_dialog = None;
+                self.remove_client(frame, client);
+            } else if !open {
+                self.security_dialog = None;
+            }
+        }
+    }
+
+    /// Forget a logged out account, wiping its session from storage
+    fn remove_client(&mut self, frame: &mut eframe::Frame, client: &Arc<dyn Client>) {
+        let key = format!("{}-{}", client.client_name(), client.self_id());
+        self.clients.lock().retain(|c| !Arc::ptr_eq(c, client));
+        self.client_keys.retain(|k| *k != key);
+
+        // Chat state belonged to the removed account
+        self.chats.lock().clear();
+        self.spaces.lock().clear();
+        self.invites.lock().clear();
+        *self.selected_chat.lock() = None;
+        self.selected_space = None;
+        self.members_panel = None;
+        self.thread_panel = None;
+        self.settings_dialog = None;
+        if let Some(task) = self.sync_tasks.remove(&key) {
+            task.abort();
+        }
+
+        if self.clients.lock().is_empty() {
+            self.active_client_index = None;
+            self.logins = vec![
+                Box::new(matrix::Login::default()),
+                Box::new(telegram::Login::default()),
+            ];
+        } else {
+            self.active_client_index = Some(0);
+            let next = self.clients.lock()[0].clone();
+            self.load_spaces(&next);
+        }
+
+        if let Some(storage) = frame.storage_mut() {
+            // Storage can't delete keys, so overwrite the session instead, along
+            // with the copy written right after login
+            storage.set_string(&key, String::new());
+            storage.set_string(&format!("{}_session", client.client_name()), String::new());
+            eframe::set_value(storage, eframe::APP_KEY, self);
+            storage.flush();
+        }
+    }
+
+    /// Fetch moderation capabilities whenever the selected chat changes
+    fn load_permissions(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let selected_chat = self.selected_chat.lock().clone();
+        if selected_chat == self.permissions_chat {
+            return;
+        }
+
+        self.permissions_chat = selected_chat.clone();
+        *self.permissions.lock() = Permissions::default();
+
+        if let Some(chat_id) = selected_chat {
+            let client_clone = client.clone();
+            let permissions = self.permissions.clone();
+            let ctx_clone = ctx.clone();
+
+            self.rt.spawn(async move {
+                match client_clone.permissions(&chat_id).await {
+                    Ok(chat_permissions) => *permissions.lock() = chat_permissions,
+                    Err(e) => log::error!("Failed to fetch permissions: {}", e),
+                }
+                ctx_clone.request_repaint();
+            });
+        }
+    }
+
+    /// Keep syncing in the background so new messages, typing and chat list changes show up live
+    fn spawn_sync_loop(&self, ctx: &egui::Context, client: &Arc<dyn Client>) -> JoinHandle<()> {
+        let client_clone = client.clone();
+        let chats = self.chats.clone();
+        let invites = self.invites.clone();
+        let ctx_clone = ctx.clone();
+        let share_presence = self.share_presence;
+
+        self.rt.spawn(async move {
+            // Only the status; the privacy settings change when the user asks
+            if let Err(e) = client_clone.set_online(share_presence).await {
+                log::error!("Failed to apply presence setting: {}", e);
+            }
+
+            // The chat list is loaded after the first successful sync
+            let mut loaded = false;
+            let mut backoff = SYNC_RETRY_MIN;
+            loop {
+                match client_clone.sync().await {
+                    Ok(changed) => {
+                        backoff = SYNC_RETRY_MIN;
+
+                        // Photos downloaded since are filled in without listing the chats again
+                        if let Ok(avatars) = client_clone.chat_avatars() {
+                            let avatars = avatars.lock();
+                            for chat in chats.lock().iter_mut().filter(|c| c.avatar.is_none()) {
+                                chat.avatar = avatars.get(&chat.id).cloned();
+                            }
+                        }
+
+                        if changed || !loaded {
+                            loaded = true;
+                            match client_clone.chats().await {
+                                Ok(client_chats) => *chats.lock() = client_chats,
+                                Err(e) => log::error!("Failed to fetch chats: {}", e),
+                            }
+                            match client_clone.invites().await {
+                                Ok(client_invites) => *invites.lock() = client_invites,
+                                Err(e) => log::error!("Failed to fetch invites: {}", e),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        // Keep retrying, a network blip shouldn't stop live updates
+                        log::error!("Sync failed, retrying in {:?}: {}", backoff, e);
+                        time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(SYNC_RETRY_MAX);
+                    }
+                }
+                ctx_clone.request_repaint();
+            }
+        })
+    }
+
+    /// Fetch the page of messages before the oldest one shown
+    fn load_older_messages(&self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        {
+            let mut loading = self.loading_older.lock();
+            if *loading || *self.history_start.lock() {
+                return;
+            }
+            *loading = true;
+        }
+
+        let client_clone = client.clone();
+        let loading = self.loading_older.clone();
+        let history_start = self.history_start.clone();
+        let ctx_clone = ctx.clone();
+
+        self.rt.spawn(async move {
+            let count = || {
+                client_clone.event_groups().map_or(0, |groups| {
+                    groups.lock().iter().map(|group| group.events.len()).sum()
+                })
+            };
+            let before = count();
+
+            // A page that adds nothing means the start was reached
+            match client_clone.load_more_events().await {
+                Ok(()) => *history_start.lock() = count() == before,
+                Err(e) => {
+                    log::error!("Failed to load older messages: {}", e);
+                    *history_start.lock() = true;
+                }
+            }
+            *loading.lock() = false;
+            ctx_clone.request_repaint();
+        });
+    }
+
+    /// Fetch where the user stopped reading once another chat gets selected
+    fn load_read_marker(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let selected_chat = self.selected_chat.lock().clone();
+        if selected_chat == self.read_marker_chat {
+            return;
+        }
+
+        self.read_marker_chat = selected_chat.clone();
+        self.read_sent = None;
+        self.mention_cursor = None;
+        *self.read_marker.lock() = None;
+        *self.history_start.lock() = false;
+
+        if let Some(chat_id) = selected_chat {
+            let client_clone = client.clone();
+            let read_marker = self.read_marker.clone();
+            let ctx_clone = ctx.clone();
+
+            self.rt.spawn(async move {
+                let marker = client_clone
+                    .read_marker(&chat_id)
+                    .await
+                    .inspect_err(|e| log::error!("Failed to fetch read marker: {}", e))
+                    .ok()
+                    .flatten();
+                *read_marker.lock() = Some((chat_id, marker));
+                ctx_clone.request_repaint();
+            });
+        }
+    }
+
+    /// Whether `event_id` comes after the last event a receipt was sent for,
+    /// so scrolling back up never moves the receipt backwards
+    fn is_newer_than_read(&self, client: &Arc<dyn Client>, event_id: &str) -> bool {
+        let Some(read_sent) = &self.read_sent else {
+            return true;
+        };
+        if read_sent == event_id {
+            return false;
+        }
+
+        let Ok(event_groups) = client.event_groups() else {
+            return false;
+        };
+        let event_groups = event_groups.lock();
+        let mut ids = event_groups
+            .iter()
+            .flat_map(|group| group.events.iter().map(|event| event.id.as_str()));
+        // Whichever of the two shows up first is the older one
+        ids.find(|id| *id == event_id || *id == read_sent) == Some(read_sent.as_str())
+    }
+
+    /// Mark a chat as read or unread from the chat list and refresh the list
+    fn set_chat_unread(
+        &self,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        unread: bool,
+    ) {
+        let client_clone = client.clone();
+        let chat_id = chat_id.to_owned();
+        let chats = self.chats.clone();
+        let ctx_clone = ctx.clone();
+
+        self.rt.spawn(async move {
+            let result = if unread {
+                client_clone.set_unread(&chat_id, true).await
+            } else {
+                client_clone.mark_read(&chat_id, None).await
+            };
+            if let Err(e) = result {
+                log::error!("Failed to update unread state: {}", e);
+                return;
+            }
+
+            match client_clone.chats().await {
+                Ok(client_chats) => *chats.lock() = client_chats,
+                Err(e) => log::error!("Failed to fetch chats: {}", e),
+            }
+            ctx_clone.request_repaint();
+        });
+    }
+
+    /// Retag a chat right away in the list and on the server in the background
+    fn set_chat_tag(
+        &self,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        tag: Option<ChatTag>,
+    ) {
+        if let Some(chat) = self.chats.lock().iter_mut().find(|chat| chat.id == chat_id) {
+            chat.tag = tag;
+        }
+
+        let client_clone = client.clone();
+        let chat_id = chat_id.to_owned();
+        let chats = self.chats.clone();
+        let ctx_clone = ctx.clone();
+
+        self.rt.spawn(async move {
+            if let Err(e) = client_clone.set_chat_tag(&chat_id, tag).await {
+                log::error!("Failed to update chat tag: {}", e);
+            }
+
+            match client_clone.chats().await {
+                Ok(client_chats) => *chats.lock() = client_chats,
+                Err(e) => log::error!("Failed to fetch chats: {}", e),
+            }
+            ctx_clone.request_repaint();
+        });
+    }
+
+    /// Open a confirmation dialog for a moderation request in the selected chat
+    fn request_moderation(&mut self, request: ModerationRequest) {
+        if let Some(chat_id) = self.selected_chat.lock().as_deref() {
+            self.moderation_dialog = Some(ModerationDialog::new(chat_id, request));
+        }
+    }
+
+    fn show_moderation_dialog(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let permissions = *self.permissions.lock();
+
+        if let Some(dialog) = &mut self.moderation_dialog
+            && !dialog.show(ctx, &self.rt, client, &permissions)
+        {
+            self.moderation_dialog = None;
+        }
     }
 
     fn show_chat_list(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let mut browse_space = None;
+
         egui::SidePanel::left("left_panel")
             .resizable(true)
             .default_width(250.0)
             .width_range(200.0..=350.0)
             .show(ctx, |ui| {
-                ui.heading("Chats");
+                ui.horizontal(|ui| {
+                    ui.heading("Chats");
+                    if ui.button("✏ New").clicked() {
+                        self.create_dialog = Some(CreateChatDialog::default());
+                    }
+                    if ui.button("➕ Join / Explore").clicked() {
+                        self.directory = Some(DirectoryDialog::default());
+                    }
+                    ui.menu_button("⚙", |ui| {
+                        if ui
+                            .checkbox(&mut self.share_presence, "Show when I'm online")
+                            .changed()
+                        {
+                            let client_clone = client.clone();
+                            let share = self.share_presence;
+                            self.rt.spawn(async move {
+                                if let Err(e) = client_clone.share_presence(share).await {
+                                    log::error!("Failed to change presence setting: {}", e);
+                                }
+                            });
+                        }
+                        if ui.button("🔔 Notifications").clicked() {
+                            let chat_id = self.selected_chat.lock().clone();
+                            self.notification_dialog = Some(NotificationSettingsDialog::new(
+                                &self.rt,
+                                ctx,
+                                client,
+                                chat_id.as_deref(),
+                            ));
+                            ui.close_menu();
+                        }
+                        if ui.button("🚫 Ignored users").clicked() {
+                            self.ignored_dialog =
+                                Some(IgnoredUsersDialog::new(&self.rt, ctx, client));
+                            ui.close_menu();
+                        }
+                        if ui.button("🔒 Sessions and security").clicked() {
+                            self.security_dialog = Some(SecurityDialog::new(&self.rt, ctx, client));
+                            ui.close_menu();
+                        }
+                    });
+                });
+
+                let spaces = self.spaces.lock().clone();
+                let selected = self
+                    .selected_space
+                    .as_deref()
+                    .and_then(|id| spaces.iter().find_map(|space| space.find(id)));
+
+                if !spaces.is_empty() {
+                    ui.horizontal(|ui| {
+                        self.show_space_selector(ui, &spaces, selected);
+
+                        if let Some(space) = selected
+                            && ui.button("Browse").clicked()
+                        {
+                            browse_space = Some(space.clone());
+                        }
+                    });
+                }
 
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    for chat in self.chats.lock().iter() {
-                        ui.add_space(4.0);
-                        self.render_chat_item(ui, ctx, client, chat);
-                        ui.add_space(4.0);
-                        ui.separator();
+                    self.render_invites(ui, ctx, client);
+
+                    match selected {
+                        Some(space) => self.render_space_chats(ui, ctx, client, space),
+                        None => self.render_chat_sections(ui, ctx, client),
                     }
                 });
             });
+
+        if let Some(space) = browse_space {
+            let title = space.name.as_deref().unwrap_or("Unnamed Space");
+            self.space_browser = Some(SpaceBrowser::new(&self.rt, ctx, client, &space.id, title));
+        }
+
+        if let Some(browser) = &mut self.space_browser
+            && !browser.show(
+                ctx,
+                &self.rt,
+                client,
+                &self.chats,
+                &self.spaces,
+                &self.selected_chat,
+            )
+        {
+            self.space_browser = None;
+        }
+
+        if let Some(directory) = &mut self.directory
+            && !directory.show(
+                ctx,
+                &self.rt,
+                client,
+                &self.chats,
+                &self.spaces,
+                &self.selected_chat,
+            )
+        {
+            self.directory = None;
+        }
+
+        if let Some(dialog) = &mut self.create_dialog
+            && !dialog.show(
+                ctx,
+                &self.rt,
+                client,
+                &self.chats,
+                &self.spaces,
+                &self.selected_chat,
+            )
+        {
+            self.create_dialog = None;
+        }
+    }
+
+    fn show_members_panel(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let selected_chat = self.selected_chat.lock().clone();
+
+        // Close the panel when another chat gets selected
+        if let Some(panel) = &self.members_panel
+            && selected_chat.as_deref() != Some(panel.chat_id())
+        {
+            self.members_panel = None;
+        }
+
+        let Some(panel) = &mut self.members_panel else {
+            return;
+        };
+
+        panel.set_permissions(*self.permissions.lock());
+        if let Some(request) = panel.show(
+            ctx,
+            &self.rt,
+            client,
+            &self.chats,
+            &self.spaces,
+            &self.selected_chat,
+        ) {
+            self.request_moderation(request);
+        }
+    }
+
+    fn show_thread_panel(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let selected_chat = self.selected_chat.lock().clone();
+
+        // Close the panel when another chat gets selected
+        if let Some(panel) = &self.thread_panel
+            && selected_chat.as_deref() != Some(panel.chat_id())
+        {
+            self.thread_panel = None;
+        }
+
+        if let Some(panel) = &mut self.thread_panel
+            && !panel.show(ctx, &self.rt, client)
+        {
+            self.thread_panel = None;
+        }
+    }
+
+    fn show_space_selector(
+        &mut self,
+        ui: &mut egui::Ui,
+        spaces: &[Space],
+        selected: Option<&Space>,
+    ) {
+        let selected_text = selected
+            .and_then(|space| space.name.as_deref())
+            .unwrap_or("All chats");
+
+        egui::ComboBox::from_id_salt("space_selector")
+            .selected_text(selected_text)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.selected_space, None, "All chats");
+                for space in spaces {
+                    let name = space.name.as_deref().unwrap_or("Unnamed Space");
+                    ui.selectable_value(&mut self.selected_space, Some(space.id.clone()), name);
+                }
+            });
+    }
+
+    /// Render the chats of a space, with sub-spaces as collapsible sections
+    fn render_space_chats(
+        &self,
+        ui: &mut egui::Ui,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        space: &Space,
+    ) {
+        // Cloned, as retagging from the context menu locks the list
+        let chats = self.chats.lock().clone();
+        for chat in chats.iter().filter(|c| space.chats.contains(&c.id)) {
+            ui.add_space(4.0);
+            self.render_chat_item(ui, ctx, client, chat);
+            ui.add_space(4.0);
+            ui.separator();
+        }
+
+        for subspace in &space.subspaces {
+            let name = subspace.name.as_deref().unwrap_or("Unnamed Space");
+            egui::CollapsingHeader::new(name)
+                .id_salt(&subspace.id)
+                .default_open(true)
+                .show(ui, |ui| self.render_space_chats(ui, ctx, client, subspace));
+        }
+    }
+
+    /// Render all chats grouped into sections, dropping a chat on a section retags it
+    fn render_chat_sections(
+        &self,
+        ui: &mut egui::Ui,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+    ) {
+        let chats = self.chats.lock().clone();
+        let dragging = egui::DragAndDrop::has_payload_of_type::<String>(ctx);
+        let mut retag = None;
+
+        for section in ChatSection::ALL {
+            let section_chats: Vec<&Chat> = chats
+                .iter()
+                .filter(|chat| ChatSection::of(chat) == section)
+                .collect();
+            // Empty sections only show up as drop targets
+            if section_chats.is_empty() && !dragging {
+                continue;
+            }
+
+            let frame = egui::Frame::new().inner_margin(2);
+            let (_, dropped) = ui.dnd_drop_zone::<String, _>(frame, |ui| {
+                ui.set_min_width(ui.available_width());
+                egui::CollapsingHeader::new(section.title())
+                    .id_salt(section.title())
+                    .default_open(section != ChatSection::LowPriority)
+                    .show(ui, |ui| {
+                        for chat in section_chats {
+                            ui.add_space(4.0);
+                            self.render_chat_item(ui, ctx, client, chat);
+                            ui.add_space(4.0);
+                            ui.separator();
+                        }
+                    });
+            });
+
+            if let Some(chat_id) = dropped {
+                let moved = chats
+                    .iter()
+                    .find(|chat| chat.id == *chat_id)
+                    .is_some_and(|chat| ChatSection::of(chat) != section);
+                if moved {
+                    retag = Some((chat_id.to_string(), section.tag()));
+                }
+            }
+        }
+
+        if let Some((chat_id, tag)) = retag {
+            self.set_chat_tag(ctx, client, &chat_id, tag);
+        }
+    }
+
+    /// Render pending invites above the chat list
+    fn render_invites(&self, ui: &mut egui::Ui, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let invites = self.invites.lock().clone();
+        let error = self.invite_error.lock().clone();
+        if invites.is_empty() && error.is_none() {
+            return;
+        }
+
+        egui::CollapsingHeader::new(format!("Invites ({})", invites.len()))
+            .default_open(true)
+            .show(ui, |ui| {
+                if let Some(error) = &error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                for invite in &invites {
+                    let action = self.render_invite_item(ui, invite);
+                    ui.separator();
+
+                    if let Some(action) = action {
+                        self.respond_to_invite(ctx, client, &invite.chat.id, action);
+                    }
+                }
+            });
+        ui.separator();
+    }
+
+    /// Render one invite, returning the action if a button was clicked
+    fn render_invite_item(&self, ui: &mut egui::Ui, invite: &Invite) -> Option<InviteAction> {
+        let mut action = None;
+
+        ui.horizontal(|ui| {
+            if let Some(avatar) = &invite.chat.avatar {
+                ui.add(
+                    egui::Image::new((
+                        Cow::Owned("chat-avatar-".to_owned() + invite.chat.id.as_str()),
+                        avatar.clone(),
+                    ))
+                    .fit_to_exact_size(egui::Vec2::new(40.0, 40.0))
+                    .corner_radius(5.0),
+                );
+            } else {
+                ui.add(egui::Label::new("✉").selectable(false));
+            }
+
+            ui.vertical(|ui| {
+                let chat_name = invite.chat.name.as_deref().unwrap_or("Unnamed Chat");
+                ui.label(egui::RichText::new(chat_name).strong());
+
+                if let Some(topic) = &invite.chat.topic {
+                    ui.label(egui::RichText::new(topic).weak().size(12.0));
+                }
+
+                if let Some(inviter) = &invite.inviter {
+                    ui.horizontal(|ui| {
+                        if let Some(avatar) = &inviter.avatar {
+                            ui.add(
+                                egui::Image::new((
+                                    Cow::Owned("user-avatar-".to_owned() + inviter.id.as_str()),
+                                    avatar.clone(),
+                                ))
+                                .fit_to_exact_size(egui::Vec2::splat(16.0))
+                                .corner_radius(8.0),
+                            );
+                        }
+                        let name = inviter.display_name.as_deref().unwrap_or(&inviter.id);
+                        ui.label(
+                            egui::RichText::new(format!("Invited by {}", name))
+                                .weak()
+                                .size(12.0),
+                        );
+                    });
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Accept").clicked() {
+                        action = Some(InviteAction::Accept);
+                    }
+                    if ui.button("Decline").clicked() {
+                        action = Some(InviteAction::Decline);
+                    }
+                    if invite.inviter.is_some() && ui.button("Block").clicked() {
+                        action = Some(InviteAction::Block);
+                    }
+                });
+            });
+        });
+
+        action
+    }
+
+    /// Join the chat that replaced an upgraded one and switch to it
+    fn follow_upgrade(&self, ctx: &egui::Context, client: &Arc<dyn Client>, target: String) {
+        let client_clone = client.clone();
+        let chats = self.chats.clone();
+        let spaces = self.spaces.clone();
+        let selected_chat = self.selected_chat.clone();
+        let ctx_clone = ctx.clone();
+
+        self.rt.spawn(async move {
+            if let Err(e) =
+                join_and_refresh(&client_clone, &target, &[], &chats, &spaces, &selected_chat).await
+            {
+                log::error!("Failed to join the new room: {}", e);
+            }
+            ctx_clone.request_repaint();
+        });
+    }
+
+    /// Accept or decline an invite in the background and refresh the sidebar
+    fn respond_to_invite(
+        &self,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        action: InviteAction,
+    ) {
+        let client_clone = client.clone();
+        let chat_id = chat_id.to_owned();
+        let chats = self.chats.clone();
+        let spaces = self.spaces.clone();
+        let invites = self.invites.clone();
+        let invite_error = self.invite_error.clone();
+        let selected_chat = self.selected_chat.clone();
+        let ctx_clone = ctx.clone();
+
+        // Hide the invite right away, it is re-fetched below
+        invites.lock().retain(|invite| invite.chat.id != chat_id);
+
+        self.rt.spawn(async move {
+            let result = match action {
+                InviteAction::Accept => match client_clone.accept_invite(&chat_id).await {
+                    Ok(()) => {
+                        refresh_and_select(&client_clone, &chat_id, &chats, &spaces, &selected_chat)
+                            .await
+                    }
+                    Err(e) => Err(e),
+                },
+                InviteAction::Decline => client_clone.decline_invite(&chat_id, false).await,
+                InviteAction::Block => client_clone.decline_invite(&chat_id, true).await,
+            };
+
+            if let Err(e) = &result {
+                log::error!("Failed to respond to invite: {}", e);
+            }
+            *invite_error.lock() = result.err().map(|e| format!("Failed to respond: {}", e));
+
+            match client_clone.invites().await {
+                Ok(client_invites) => *invites.lock() = client_invites,
+                Err(e) => log::error!("Failed to fetch invites: {}", e),
+            }
+            ctx_clone.request_repaint();
+        });
     }
 
     fn render_chat_item(
@@ -245,23 +1150,54 @@ impl EChat {
 
         container.show(ui, |ui| {
             // Make the entire row interactive
-            let (rect, response) = ui
-                .allocate_exact_size(egui::vec2(ui.available_width(), 50.0), egui::Sense::click());
+            let (rect, response) = ui.allocate_exact_size(
+                egui::vec2(ui.available_width(), 50.0),
+                egui::Sense::click_and_drag(),
+            );
+            // Dragged onto a sidebar section to retag the chat
+            response.dnd_set_drag_payload(chat.id.clone());
 
             if response.clicked() {
-                let client_clone = client.clone();
-                let chat_id = chat.id.clone();
-                let ctx_clone = ctx.clone();
+                self.open_chat(ctx, client, chat.id.clone());
+            }
 
-                self.rt.spawn(async move {
-                    if let Err(e) = client_clone.select_chat(&chat_id).await {
-                        log::error!("Failed to select chat: {}", e);
+            response.context_menu(|ui| {
+                if chat.unread {
+                    if ui.button("Mark as read").clicked() {
+                        self.set_chat_unread(ctx, client, &chat.id, false);
+                        ui.close_menu();
                     }
-                    ctx_clone.request_repaint();
-                });
-            }
+                } else if ui.button("Mark as unread").clicked() {
+                    self.set_chat_unread(ctx, client, &chat.id, true);
+                    ui.close_menu();
+                }
 
-            if response.hovered() {
+                ui.separator();
+                let (favourite, low_priority) = match chat.tag {
+                    Some(ChatTag::Favourite) => ("Remove from favourites", "Move to low priority"),
+                    Some(ChatTag::LowPriority) => ("Add to favourites", "Remove from low priority"),
+                    None => ("Add to favourites", "Move to low priority"),
+                };
+                if ui.button(favourite).clicked() {
+                    let tag = (chat.tag != Some(ChatTag::Favourite)).then_some(ChatTag::Favourite);
+                    self.set_chat_tag(ctx, client, &chat.id, tag);
+                    ui.close_menu();
+                }
+                if ui.button(low_priority).clicked() {
+                    let tag =
+                        (chat.tag != Some(ChatTag::LowPriority)).then_some(ChatTag::LowPriority);
+                    self.set_chat_tag(ctx, client, &chat.id, tag);
+                    ui.close_menu();
+                }
+            });
+
+            if self.selected_chat.lock().as_deref() == Some(chat.id.as_str()) {
+                ui.painter().rect_filled(
+                    rect,
+                    4.0,
+                    egui::Color32::from_rgba_premultiplied(100, 100, 100, 30),
+                );
+            } else if response.hovered() {
                 ui.painter().rect_filled(
                     rect,
                     4.0,
@@ -273,7 +1209,7 @@ impl EChat {
                 ui.horizontal(|ui| {
                     let avatar_size = egui::Vec2::new(40.0, 40.0);
 
-                    if let Some(avatar) = &chat.avatar {
+                    let avatar_response = if let Some(avatar) = &chat.avatar {
                         ui.add(
                             egui::Image::new((
                                 Cow::Owned("chat-avatar-".to_owned() + chat.id.as_str()),
@@ -281,45 +1217,441 @@ impl EChat {
                             ))
                             .fit_to_exact_size(avatar_size)
                             .corner_radius(5.0),
-                        );
+                        )
                     } else {
-                        ui.add(egui::Label::new("📝").selectable(false));
+                        ui.add(egui::Label::new("📝").selectable(false))
+                    };
+                    if let Some(user_id) = &chat.direct_user {
+                        paint_presence_dot(
+                            ui,
+                            avatar_response.rect,
+                            user_presence(client, user_id),
+                        );
                     }
 
                     ui.add_space(8.0);
 
                     ui.vertical(|ui| {
                         let chat_name = chat.name.as_deref().unwrap_or("Unnamed Chat");
-                        ui.add(
-                            egui::Label::new(egui::RichText::new(chat_name).strong().size(16.0))
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Label::new(
+                                    egui::RichText::new(chat_name).strong().size(16.0),
+                                )
                                 .selectable(false),
-                        );
+                            );
+                            if chat.unread {
+                                ui.add(
+                                    egui::Label::new(
+                                        egui::RichText::new("●")
+                                            .color(ui.visuals().hyperlink_color),
+                                    )
+                                    .selectable(false),
+                                );
+                            }
+                            if chat.mentions > 0 {
+                                ui.add(
+                                    egui::Label::new(
+                                        egui::RichText::new(format!("@{}", chat.mentions))
+                                            .color(ui.visuals().warn_fg_color)
+                                            .strong(),
+                                    )
+                                    .selectable(false),
+                                );
+                            }
+                        });
 
-                        ui.add(
-                            egui::Label::new(
-                                egui::RichText::new("Tap to view messages")
-                                    .weak()
-                                    .size(14.0),
-                            )
-                            .selectable(false),
-                        );
+                        let subtitle = match typing_text(client, &chat.id) {
+                            Some(typing) => egui::RichText::new(typing).italics(),
+                            None => egui::RichText::new("Tap to view messages"),
+                        };
+                        ui.add(egui::Label::new(subtitle.weak().size(14.0)).selectable(false));
                     });
                 });
             });
+
+            for topic in &chat.topics {
+                self.render_topic_item(ui, ctx, client, topic);
+            }
         });
     }
 
-    fn show_message_panel(&self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+    /// Render a forum topic as a compact row indented under its group
+    fn render_topic_item(
+        &self,
+        ui: &mut egui::Ui,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        topic: &Topic,
+    ) {
+        let selected = self.selected_chat.lock().as_deref() == Some(topic.id.as_str());
+
+        let response = ui
+            .horizontal(|ui| {
+                ui.add_space(48.0);
+                // Telegram's default topic color when none is set
+                let color = topic.icon_color.unwrap_or(0x6FB9F0);
+                let color =
+                    egui::Color32::from_rgb((color >> 16) as u8, (color >> 8) as u8, color as u8);
+                ui.add(
+                    egui::Label::new(egui::RichText::new("#").strong().color(color))
+                        .selectable(false),
+                );
+                let response = ui.selectable_label(selected, &topic.name);
+                if topic.unread > 0 {
+                    ui.add(
+                        egui::Label::new(
+                            egui::RichText::new(topic.unread.to_string())
+                                .color(ui.visuals().hyperlink_color)
+                                .strong(),
+                        )
+                        .selectable(false),
+                    );
+                }
+                response
+            })
+            .inner;
+
+        if response.clicked() {
+            self.open_chat(ctx, client, topic.id.clone());
+        }
+    }
+
+    /// Select a chat or topic in the background, showing it once its messages are loaded
+    fn open_chat(&self, ctx: &egui::Context, client: &Arc<dyn Client>, chat_id: String) {
+        let client_clone = client.clone();
+        let selected_chat = self.selected_chat.clone();
+        let ctx_clone = ctx.clone();
+
+        self.rt.spawn(async move {
+            match client_clone.select_chat(&chat_id).await {
+                Ok(()) => *selected_chat.lock() = Some(chat_id),
+                Err(e) => log::error!("Failed to select chat: {}", e),
+            }
+            ctx_clone.request_repaint();
+        });
+    }
+
+    fn show_message_panel(&mut self, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let selected_chat = self.selected_chat.lock().clone();
+        let permissions = *self.permissions.lock();
+        let mut action = None;
+        let mut newest_visible = None;
+
+        // The divider stays where the user stopped reading when the chat was opened
+        let read_marker = self
+            .read_marker
+            .lock()
+            .clone()
+            .filter(|(chat_id, _)| Some(chat_id) == selected_chat.as_ref());
+
+        let mut submitted = None;
+
+        // Messages mentioning us, in timeline order
+        let self_id = client.self_id();
+        let mention_ids: Vec<String> = client
+            .event_groups()
+            .map(|groups| {
+                groups
+                    .lock()
+                    .iter()
+                    .filter(|group| !group.from_self)
+                    .flat_map(|group| &group.events)
+                    .filter(|event| event.mentions_user(&self_id))
+                    .map(|event| event.id.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut jump_to = None;
+        let mut load_older = false;
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(chat_id) = &selected_chat {
+                self.show_chat_header(ui, ctx, client, chat_id);
+                ui.separator();
+
+                let replaced_by = self
+                    .chats
+                    .lock()
+                    .iter()
+                    .find(|chat| chat.id == *chat_id)
+                    .and_then(|chat| chat.replaced_by.clone());
+                if let Some(target) = replaced_by {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            "This room has been replaced and is no longer active",
+                        );
+                        if ui.button("Go to the new room").clicked() {
+                            self.follow_upgrade(ctx, client, target);
+                        }
+                    });
+                    ui.separator();
+                }
+
+                egui::TopBottomPanel::bottom("composer").show_inside(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        // Keep the line even when empty so the timeline doesn't jump
+                        let typing = typing_text(client, chat_id).unwrap_or_default();
+                        ui.label(egui::RichText::new(typing).italics().weak().size(12.0));
+
+                        if mention_ids.is_empty() {
+                            return;
+                        }
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            let button = ui
+                                .small_button(format!("@ {}", mention_ids.len()))
+                                .on_hover_text("Jump to next mention");
+                            if button.clicked() {
+                                // Cycle through mentions, starting after the last one shown
+                                let next = self
+                                    .mention_cursor
+                                    .as_ref()
+                                    .and_then(|cursor| {
+                                        mention_ids.iter().position(|id| id == cursor)
+                                    })
+                                    .map_or(0, |idx| (idx + 1) % mention_ids.len());
+                                self.mention_cursor = Some(mention_ids[next].clone());
+                                jump_to = self.mention_cursor.clone();
+                            }
+                        });
+                    });
+
+                    submitted =
+                        self.composer
+                            .show(ui, &self.rt, client, chat_id, "Write a message");
+                });
+            }
+
             egui::ScrollArea::vertical()
                 .stick_to_bottom(true)
                 .show(ui, |ui| {
                     if let Ok(event_groups) = client.event_groups() {
-                        for group in event_groups.lock().iter() {
-                            MessageWidget::new(MessageStyle::default(), group.clone()).show(ui);
+                        let event_groups = event_groups.lock();
+                        let presence = client.presence().ok();
+
+                        if *self.loading_older.lock() {
+                            ui.spinner();
+                        } else if !event_groups.is_empty() && !*self.history_start.lock() {
+                            // Load older messages once the top scrolls into view
+                            let response = ui.button("Load earlier messages");
+                            if response.clicked() || ui.is_rect_visible(response.rect) {
+                                load_older = true;
+                            }
+                        }
+
+                        // Nothing is new when the marker is on the latest message
+                        let newest = event_groups
+                            .last()
+                            .and_then(|group| group.events.last())
+                            .map(|event| event.id.as_str());
+                        let divider = read_marker
+                            .as_ref()
+                            .and_then(|(_, marker)| marker.as_deref())
+                            .filter(|marker| Some(*marker) != newest);
+
+                        let mut system_run = Vec::new();
+                        for group in event_groups.iter() {
+                            if group.is_system() {
+                                system_run.extend(
+                                    group
+                                        .events
+                                        .iter()
+                                        .map(|event| (group.display_name.as_str(), event)),
+                                );
+                                continue;
+                            }
+                            newest_visible = render_system_events(ui, &system_run, divider)
+                                .or(newest_visible.take());
+                            system_run.clear();
+
+                            let widget = MessageWidget::new(MessageStyle::default(), group.clone())
+                                .permissions(permissions)
+                                .divider_after(divider)
+                                .highlight_mentions(&self_id)
+                                .scroll_to(jump_to.as_deref())
+                                .presence(presence.as_ref().and_then(|presence| {
+                                    presence.lock().get(&group.user_id).copied()
+                                }));
+                            let picked = widget.show(ui);
+                            if action.is_none() {
+                                action = picked;
+                            }
+                            newest_visible = widget.last_visible().or(newest_visible.take());
                         }
+                        newest_visible = render_system_events(ui, &system_run, divider)
+                            .or(newest_visible.take());
                     }
                 });
         });
+
+        if load_older {
+            self.load_older_messages(ctx, client);
+        }
+
+        // Only acknowledge what was actually on screen while the window had focus
+        if let (Some(chat_id), Some(_), Some(event_id)) =
+            (&selected_chat, &read_marker, newest_visible)
+            && ctx.input(|i| i.focused)
+            && self.is_newer_than_read(client, &event_id)
+        {
+            self.read_sent = Some(event_id.clone());
+
+            let client_clone = client.clone();
+            let chat_id = chat_id.clone();
+            self.rt.spawn(async move {
+                if let Err(e) = client_clone.mark_read(&chat_id, Some(&event_id)).await {
+                    log::error!("Failed to send read receipt: {}", e);
+                }
+            });
+        }
+
+        if let (Some(text), Some(chat_id)) = (submitted, &selected_chat) {
+            let client_clone = client.clone();
+            let chat_id = chat_id.clone();
+            self.rt.spawn(async move {
+                if let Err(e) = client_clone.send_message(&chat_id, &text).await {
+                    log::error!("Failed to send message: {}", e);
+                }
+            });
+        }
+
+        match (action, &selected_chat) {
+            (Some(MessageAction::Moderate(request)), _) => self.request_moderation(request),
+            (Some(MessageAction::OpenThread(root)), Some(chat_id)) => {
+                self.thread_panel = Some(ThreadPanel::thread(&self.rt, ctx, client, chat_id, root));
+            }
+            (Some(MessageAction::Ignore(user_id)), chat_id) => {
+                ignore_user(&self.rt, ctx, client, user_id, chat_id.clone());
+            }
+            _ => {}
+        }
+
+        if let Some(dialog) = &mut self.invite_dialog
+            && !dialog.show(ctx, &self.rt, client)
+        {
+            self.invite_dialog = None;
+        }
+
+        // Settings belong to the chat they were opened for
+        if self
+            .settings_dialog
+            .as_ref()
+            .is_some_and(|dialog| selected_chat.as_deref() != Some(dialog.chat_id()))
+        {
+            self.settings_dialog = None;
+        }
+        if let Some(dialog) = &mut self.settings_dialog
+            && !dialog.show(ctx, &self.rt, client, &self.chats)
+        {
+            self.settings_dialog = None;
+        }
+    }
+
+    /// Render the selected chat's name and actions above the timeline
+    fn show_chat_header(
+        &mut self,
+        ui: &mut egui::Ui,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+    ) {
+        let (name, direct_user) = self
+            .chats
+            .lock()
+            .iter()
+            .find_map(|chat| {
+                let name = chat
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| "Unnamed Chat".to_owned());
+                if chat.id == chat_id {
+                    Some((name, chat.direct_user.clone()))
+                } else {
+                    // Topics are titled with their group
+                    let topic = chat.topic(chat_id)?;
+                    Some((format!("{} › {}", name, topic.name), None))
+                }
+            })
+            .unwrap_or_else(|| ("Unnamed Chat".to_owned(), None));
+
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                ui.heading(name);
+                if let Some(user_id) = &direct_user {
+                    let status = user_presence(client, user_id).unwrap_or_default();
+                    ui.label(
+                        egui::RichText::new(last_seen_text(&status))
+                            .weak()
+                            .size(12.0),
+                    );
+                }
+            });
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let members_open = self.members_panel.is_some();
+                if ui.selectable_label(members_open, "👥 Members").clicked() {
+                    self.members_panel = if members_open {
+                        None
+                    } else {
+                        Some(MembersPanel::new(&self.rt, ctx, client, chat_id))
+                    };
+                }
+                if ui.button("Invite").clicked() {
+                    self.invite_dialog = Some(InviteDialog::new(chat_id));
+                }
+                let threads_open = self.thread_panel.is_some();
+                if ui.selectable_label(threads_open, "🧵 Threads").clicked() {
+                    self.thread_panel = if threads_open {
+                        None
+                    } else {
+                        Some(ThreadPanel::list(&self.rt, ctx, client, chat_id))
+                    };
+                }
+                if ui.button("⚙ Settings").clicked() {
+                    self.settings_dialog =
+                        Some(ChatSettingsDialog::new(&self.rt, ctx, client, chat_id));
+                }
+            });
+        });
+    }
+}
+
+/// "Alice is typing…" line for a chat, if anyone is typing there
+fn typing_text(client: &Arc<dyn Client>, chat_id: &str) -> Option<String> {
+    let typing = client.typing().ok()?;
+    let typing = typing.lock();
+
+    match typing.get(chat_id)?.as_slice() {
+        [] => None,
+        [name] => Some(format!("{} is typing…", name)),
+        [first, second] => Some(format!("{} and {} are typing…", first, second)),
+        [first, rest @ ..] => Some(format!("{} and {} others are typing…", first, rest.len())),
+    }
+}
+
+/// Last known presence of a user
+fn user_presence(client: &Arc<dyn Client>, user_id: &str) -> Option<PresenceInfo> {
+    client.presence().ok()?.lock().get(user_id).copied()
+}
+
+/// "online" or "last seen …" line for the other user of a direct chat
+fn last_seen_text(info: &PresenceInfo) -> String {
+    if info.presence == Presence::Online {
+        return "online".to_owned();
+    }
+    let Some(last_seen) = info.last_seen else {
+        return "last seen recently".to_owned();
+    };
+
+    let ago = chrono::Utc::now().timestamp() - last_seen as i64;
+    match ago {
+        ..60 => "last seen just now".to_owned(),
+        60..3600 => format!("last seen {} min ago", ago / 60),
+        3600..86400 => format!("last seen {} h ago", ago / 3600),
+        _ => match chrono::DateTime::from_timestamp(last_seen as i64, 0) {
+            Some(time) => format!("last seen {}", time.format("%Y-%m-%d")),
+            None => "last seen a long time ago".to_owned(),
+        },
     }
 }
@@ -0,0 +1,344 @@
+use std::{borrow::Cow, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::clients::{Chat, ChatSettings, Client, HistoryVisibility, JoinRule};
+
+/// Progress of loading and saving the settings
+#[derive(Default)]
+struct SettingsState {
+    /// Settings as loaded from the server
+    loaded: Option<ChatSettings>,
+    busy: bool,
+    error: Option<String>,
+    saved: bool,
+}
+
+/// Dialog for editing a chat's name, topic, avatar and access settings
+pub struct ChatSettingsDialog {
+    chat_id: String,
+    state: Arc<Mutex<SettingsState>>,
+    /// Edited copy of the loaded settings
+    draft: Option<ChatSettings>,
+    avatar_path: String,
+    open: bool,
+}
+
+impl ChatSettingsDialog {
+    /// Create the dialog for the given chat and load its current settings
+    pub fn new(rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>, chat_id: &str) -> Self {
+        let dialog = Self {
+            chat_id: chat_id.to_owned(),
+            state: Arc::new(Mutex::new(SettingsState {
+                busy: true,
+                ..Default::default()
+            })),
+            draft: None,
+            avatar_path: String::new(),
+            open: true,
+        };
+
+        let client = client.clone();
+        let state = dialog.state.clone();
+        let chat_id = dialog.chat_id.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.chat_settings(&chat_id).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(settings) => state.loaded = Some(settings),
+                Err(e) => state.error = Some(format!("Failed to load settings: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+
+        dialog
+    }
+
+    /// ID of the chat being edited
+    pub fn chat_id(&self) -> &str {
+        &self.chat_id
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+    ) -> bool {
+        let mut open = self.open;
+        let mut load_avatar = false;
+        let mut save = false;
+
+        if self.draft.is_none() {
+            self.draft = self.state.lock().loaded.clone();
+        }
+
+        // Accept an image dropped anywhere on the window as the new avatar
+        if let Some(file) = ctx.input(|i| i.raw.dropped_files.first().cloned()) {
+            match (file.bytes, file.path) {
+                (Some(bytes), _) => self.set_avatar(bytes),
+                (None, Some(path)) => {
+                    self.avatar_path = path.display().to_string();
+                    load_avatar = true;
+                }
+                (None, None) => {}
+            }
+        }
+
+        egui::Window::new("Chat settings")
+            .open(&mut open)
+            .default_width(340.0)
+            .show(ctx, |ui| {
+                let state = self.state.lock();
+                let Some(draft) = &mut self.draft else {
+                    if state.busy {
+                        ui.spinner();
+                    }
+                    if let Some(error) = &state.error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    return;
+                };
+
+                ui.horizontal(|ui| {
+                    if let Some(avatar) = &draft.avatar {
+                        // Key the image by its buffer so a replaced avatar isn't served from cache
+                        let uri = format!("chat-settings-avatar-{:p}", avatar.as_ptr());
+                        ui.add(
+                            egui::Image::new((Cow::Owned(uri), avatar.clone()))
+                                .fit_to_exact_size(egui::Vec2::splat(64.0))
+                                .corner_radius(32.0),
+                        );
+                    }
+
+                    ui.vertical(|ui| {
+                        ui.label("Avatar: drop an image here or enter a path");
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.avatar_path)
+                                    .hint_text("/path/to/image.png")
+                                    .desired_width(180.0),
+                            );
+                            if ui.button("Load").clicked() {
+                                load_avatar = true;
+                            }
+                        });
+                    });
+                });
+
+                ui.label("Name:");
+                ui.text_edit_singleline(&mut draft.name);
+                ui.label("Topic:");
+                ui.text_edit_multiline(&mut draft.topic);
+
+                if let Some(rule) = &mut draft.join_rule {
+                    egui::ComboBox::from_label("Who can join")
+                        .selected_text(join_rule_label(*rule))
+                        .show_ui(ui, |ui| {
+                            for option in [JoinRule::Public, JoinRule::Knock, JoinRule::Invite] {
+                                ui.selectable_value(rule, option, join_rule_label(option));
+                            }
+                        });
+                }
+
+                if let Some(visibility) = &mut draft.history_visibility {
+                    egui::ComboBox::from_label("Who can read history")
+                        .selected_text(history_visibility_label(*visibility))
+                        .show_ui(ui, |ui| {
+                            for option in [
+                                HistoryVisibility::WorldReadable,
+                                HistoryVisibility::Shared,
+                                HistoryVisibility::Invited,
+                                HistoryVisibility::Joined,
+                            ] {
+                                ui.selectable_value(
+                                    visibility,
+                                    option,
+                                    history_visibility_label(option),
+                                );
+                            }
+                        });
+                }
+
+                let already_encrypted = state
+                    .loaded
+                    .as_ref()
+                    .is_some_and(|loaded| loaded.encrypted == Some(true));
+                if let Some(encrypted) = &mut draft.encrypted {
+                    if already_encrypted {
+                        ui.label("🔒 End-to-end encryption is enabled");
+                    } else {
+                        // Encryption can't be turned off again, so only offer enabling it
+                        ui.checkbox(encrypted, "Enable end-to-end encryption")
+                            .on_hover_text("This can't be undone");
+                    }
+                }
+
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    let valid = !draft.name.trim().is_empty();
+                    if ui
+                        .add_enabled(valid && !state.busy, egui::Button::new("Save"))
+                        .clicked()
+                    {
+                        save = true;
+                    }
+                    if state.busy {
+                        ui.spinner();
+                    } else if state.saved {
+                        ui.label(egui::RichText::new("✔ Saved").weak());
+                    }
+                });
+                if let Some(error) = &state.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+        if load_avatar {
+            self.load_avatar_file();
+        }
+        if save {
+            self.save(ctx, rt, client, chats);
+        }
+
+        self.open = open;
+        self.open
+    }
+
+    /// Replace the avatar in the draft
+    fn set_avatar(&mut self, image: Arc<[u8]>) {
+        if let Some(draft) = &mut self.draft {
+            draft.avatar = Some(image);
+            self.state.lock().saved = false;
+        }
+    }
+
+    /// Read the avatar from the entered path
+    fn load_avatar_file(&mut self) {
+        match std::fs::read(self.avatar_path.trim()) {
+            Ok(bytes) => {
+                self.set_avatar(bytes.into());
+                self.state.lock().error = None;
+            }
+            Err(e) => self.state.lock().error = Some(format!("Failed to read image: {}", e)),
+        }
+    }
+
+    /// Send the changed settings to the server and refresh the chat list
+    fn save(
+        &self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+    ) {
+        let Some(draft) = self.draft.clone() else {
+            return;
+        };
+        let Some(loaded) = self.state.lock().loaded.clone() else {
+            return;
+        };
+
+        let client = client.clone();
+        let chat_id = self.chat_id.clone();
+        let chats = chats.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        {
+            let mut state = state.lock();
+            state.busy = true;
+            state.saved = false;
+            state.error = None;
+        }
+        rt.spawn(async move {
+            let result = apply_changes(&client, &chat_id, &loaded, &draft).await;
+
+            // Refresh the list so a new name or avatar shows up right away
+            match client.chats().await {
+                Ok(client_chats) => *chats.lock() = client_chats,
+                Err(e) => log::error!("Failed to fetch chats: {}", e),
+            }
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => {
+                    state.loaded = Some(draft);
+                    state.saved = true;
+                }
+                Err(e) => state.error = Some(format!("Failed to save settings: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+}
+
+/// Call the setters for every setting that differs from what was loaded
+async fn apply_changes(
+    client: &Arc<dyn Client>,
+    chat_id: &str,
+    loaded: &ChatSettings,
+    draft: &ChatSettings,
+) -> Result<()> {
+    if draft.name != loaded.name {
+        client.set_chat_name(chat_id, draft.name.trim()).await?;
+    }
+    if draft.topic != loaded.topic {
+        client.set_chat_topic(chat_id, draft.topic.trim()).await?;
+    }
+
+    let avatar_changed = match (&draft.avatar, &loaded.avatar) {
+        (Some(new), Some(old)) => !Arc::ptr_eq(new, old),
+        (Some(_), None) => true,
+        _ => false,
+    };
+    if let Some(avatar) = draft.avatar.as_ref().filter(|_| avatar_changed) {
+        client.set_chat_avatar(chat_id, avatar.to_vec()).await?;
+    }
+
+    if let Some(rule) = draft
+        .join_rule
+        .filter(|rule| Some(*rule) != loaded.join_rule)
+    {
+        client.set_join_rule(chat_id, rule).await?;
+    }
+    if let Some(visibility) = draft
+        .history_visibility
+        .filter(|visibility| Some(*visibility) != loaded.history_visibility)
+    {
+        client.set_history_visibility(chat_id, visibility).await?;
+    }
+    if draft.encrypted == Some(true) && loaded.encrypted == Some(false) {
+        client.enable_encryption(chat_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Human readable join rule
+fn join_rule_label(rule: JoinRule) -> &'static str {
+    match rule {
+        JoinRule::Public => "Anyone",
+        JoinRule::Knock => "Anyone can ask to join",
+        JoinRule::Invite => "Only invited users",
+    }
+}
+
+/// Human readable history visibility
+fn history_visibility_label(visibility: HistoryVisibility) -> &'static str {
+    match visibility {
+        HistoryVisibility::WorldReadable => "Anyone, even without joining",
+        HistoryVisibility::Shared => "Members, including past messages",
+        HistoryVisibility::Invited => "Members, since they were invited",
+        HistoryVisibility::Joined => "Members, since they joined",
+    }
+}
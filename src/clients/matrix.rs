@@ -1,28 +1,118 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use egui::ahash::HashSet;
+use futures_util::{StreamExt, pin_mut};
 use matrix_sdk::{
+    RoomMemberships, RoomState,
     authentication::matrix::MatrixSession,
     config::SyncSettings,
-    media::MediaFormat,
-    room::{Messages, MessagesOptions, Room},
+    deserialized_responses::{SyncOrStrippedState, TimelineEvent},
+    media::{MediaFormat, MediaRequestParameters},
+    notification_settings::RoomNotificationMode,
+    room::{MessagesOptions, Receipts, Room, RoomMember},
     ruma::{
-        RoomId, UInt,
-        api::client::filter::FilterDefinition,
-        events::{AnyMessageLikeEventContent, AnySyncTimelineEvent},
+        EventId, Int, MatrixToUri, MatrixUri, MxcUri, OwnedDeviceId, OwnedMxcUri, OwnedRoomId,
+        OwnedRoomOrAliasId, OwnedServerName, OwnedUserId, RoomId, RoomOrAliasId, UInt, UserId,
+        api::{
+            Direction,
+            client::{
+                directory::get_public_rooms_filtered,
+                filter::FilterDefinition,
+                presence::{get_presence, set_presence},
+                relations::get_relating_events_with_rel_type,
+                room::{
+                    Visibility,
+                    create_room::{self, v3::RoomPreset},
+                },
+                space::get_hierarchy,
+                threads::get_threads,
+                uiaa::{self, AuthData, UserIdentifier},
+            },
+        },
+        directory::Filter,
+        events::{
+            AnyMessageLikeEventContent, AnySyncEphemeralRoomEvent, AnySyncMessageLikeEvent,
+            AnySyncStateEvent, AnySyncTimelineEvent, InitialStateEvent, StateEventType,
+            SyncMessageLikeEvent, SyncStateEvent,
+            fully_read::FullyReadEventContent,
+            ignored_user_list::IgnoredUserListEventContent,
+            presence::PresenceEventContent,
+            receipt::{ReceiptThread, ReceiptType},
+            relation::{RelationType, Thread},
+            room::{
+                MediaSource,
+                encryption::RoomEncryptionEventContent,
+                history_visibility::{
+                    HistoryVisibility as RoomHistoryVisibility, RoomHistoryVisibilityEventContent,
+                },
+                join_rules::{JoinRule as RoomJoinRule, RoomJoinRulesEventContent},
+                member::MembershipChange,
+                message::{MessageFormat, MessageType, Relation, RoomMessageEventContent},
+                tombstone::RoomTombstoneEventContent,
+            },
+            space::child::SpaceChildEventContent,
+            typing::SyncTypingEvent,
+        },
+        matrix_uri::MatrixId,
+        presence::PresenceState,
+        push::Action,
+        room::RoomType,
+        serde::Raw,
     },
+    sliding_sync::{SlidingSync, SlidingSyncList, SlidingSyncMode, UpdateSummary, Version, http},
+    sync::{JoinedRoomUpdate, RoomUpdates},
 };
 use parking_lot::Mutex;
 use rand::{Rng, distr::Alphanumeric};
 use serde::{Deserialize, Serialize};
-use tokio::runtime::Runtime;
+use tokio::{
+    runtime::Runtime,
+    sync::{
+        OnceCell,
+        broadcast::{self, error::TryRecvError},
+        mpsc,
+    },
+};
 
-use super::{Chat, Client, Event, EventGroup, EventKind, LoginForm};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task;
+#[cfg(target_arch = "wasm32")]
+use tokio_with_wasm::alias::task;
+
+use super::{
+    Chat, ChatAvatars, ChatKind, ChatSettings, ChatTag, Client, DirectoryPage, Event, EventGroup,
+    EventKind, HistoryVisibility, Invite, JoinRule, LoginApproval, LoginForm, Member, MemberPage,
+    MemberRole, NewChat, Notification, NotifyMode, PasswordRequired, Permissions, Presence,
+    PresenceInfo, RoomPreview, Session, Space, SystemEvent, ThreadPage, ThreadRoot, ThreadSummary,
+    TypingUsers, UserProfile, device_name, rich_text::RichText,
+};
 
 /// Tokio mutex type alias for better readability
 type AsyncMutex<T> = tokio::sync::Mutex<T>;
 
+/// Number of rooms requested per public directory page
+const DIRECTORY_PAGE_SIZE: u32 = 20;
+
+/// Maximum number of users returned by a user directory search
+const USER_SEARCH_LIMIT: u64 = 10;
+
+/// Rooms with more joined members than this don't show who read each message
+const READ_RECEIPT_ROOM_SIZE: u64 = 20;
+
+/// Name of the sliding sync list backing the chat list
+const CHAT_LIST: &str = "all_rooms";
+
+/// Rooms loaded per sliding sync request, the first batch fills the chat list
+const SLIDING_SYNC_BATCH: u32 = 20;
+
+/// Seconds between presence requests for direct chat partners, which sliding
+/// sync doesn't deliver
+const PRESENCE_POLL_INTERVAL: i64 = 60;
+
+/// Maximum number of direct chat partners whose presence is requested at once
+const PRESENCE_POLL_LIMIT: usize = 20;
+
 /// Stores Matrix client session information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientSession {
@@ -32,6 +122,112 @@ pub struct ClientSession {
     db_path: PathBuf,
 }
 
+/// A joined space as read from the store, before it is placed in the tree
+struct SpaceInfo {
+    name: Option<String>,
+    avatar: Option<Arc<[u8]>>,
+    /// Rooms and subspaces listed in `m.space.child` state
+    children: Vec<String>,
+}
+
+/// Sorted members matching a search, kept while the member list pages through them
+struct MemberList {
+    chat_id: String,
+    query: String,
+    members: Arc<Vec<RoomMember>>,
+}
+
+/// Sliding sync session with the updates of its one long-lived sync stream
+struct SlidingSyncSession {
+    sliding_sync: SlidingSync,
+    updates: AsyncMutex<mpsc::Receiver<SlidingSyncUpdate>>,
+}
+
+impl SlidingSyncSession {
+    /// Whether the task driving the stream has ended and its updates were all taken
+    fn stopped(&self) -> bool {
+        self.updates
+            .try_lock()
+            .is_ok_and(|updates| updates.is_closed() && updates.is_empty())
+    }
+}
+
+/// One sliding sync response: which lists and rooms changed, and the room updates
+/// it brought
+struct SlidingSyncUpdate {
+    summary: matrix_sdk::Result<UpdateSummary>,
+    rooms: Vec<RoomUpdates>,
+}
+
+/// What the chat list shows of a room, to tell whether an update changed it
+#[derive(PartialEq)]
+struct ListedRoom {
+    state: RoomState,
+    name: Option<String>,
+    avatar_url: Option<OwnedMxcUri>,
+    unread: bool,
+    mentions: u64,
+    tag: Option<ChatTag>,
+    tombstoned: bool,
+}
+
+impl ListedRoom {
+    fn new(room: &Room) -> Self {
+        Self {
+            state: room.state(),
+            name: room.name(),
+            avatar_url: room.avatar_url(),
+            unread: room.num_unread_messages() > 0 || room.is_marked_unread(),
+            mentions: room.num_unread_mentions(),
+            tag: chat_tag(room),
+            tombstoned: room.is_tombstoned(),
+        }
+    }
+}
+
+impl ClientSession {
+    /// Pick a new store location and encryption passphrase for a login
+    fn generate(homeserver: &str) -> Self {
+        // Generate random storage path for desktop platforms
+        #[cfg(not(target_arch = "wasm32"))]
+        let db_path = {
+            let data_dir = dirs::data_dir().unwrap().join("echat");
+            let db_subfolder: String = rand::rng()
+                .sample_iter(Alphanumeric)
+                .take(7)
+                .map(char::from)
+                .collect();
+            data_dir.join(db_subfolder)
+        };
+
+        // Generate secure random passphrase for database encryption
+        let passphrase: String = rand::rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        Self {
+            homeserver: homeserver.to_owned(),
+            passphrase,
+            #[cfg(not(target_arch = "wasm32"))]
+            db_path,
+        }
+    }
+
+    /// Create the platform-specific client for this session's store
+    async fn build(&self) -> Result<matrix_sdk::Client> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            MatrixClient::build_client(&self.homeserver, &self.passphrase).await
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            MatrixClient::build_client(&self.homeserver, &self.passphrase, &self.db_path).await
+        }
+    }
+}
+
 /// Complete session information including client config and authentication
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FullSession {
@@ -49,7 +245,27 @@ pub struct MatrixClient {
     event_groups: Arc<Mutex<Vec<EventGroup>>>,
     selected_room: AsyncMutex<Option<Room>>,
     pagination_token: Mutex<Option<String>>,
+    /// Room `load_more_events` pages through, the selected room or one it
+    /// was upgraded from once its start is reached
+    history_room: Mutex<Option<Room>>,
     processed_events: AsyncMutex<HashSet<String>>,
+    /// Root of the thread shown in the thread panel, if any
+    thread_root: Mutex<Option<String>>,
+    thread_groups: Arc<Mutex<Vec<EventGroup>>>,
+    thread_pagination_token: Mutex<Option<String>>,
+    member_list: Mutex<Option<MemberList>>,
+    typing: TypingUsers,
+    presence: Arc<Mutex<HashMap<String, PresenceInfo>>>,
+    /// Whether syncing marks us online
+    share_presence: Mutex<bool>,
+    /// Sliding sync session, `None` when the homeserver only supports classic sync
+    sliding_sync: OnceCell<Option<SlidingSyncSession>>,
+    /// Rooms as of the last chat listing, to relist only when one changed
+    listed_rooms: Mutex<HashMap<OwnedRoomId, ListedRoom>>,
+    /// When presence was last requested under sliding sync, as a Unix timestamp
+    presence_polled_at: Mutex<i64>,
+    /// Member avatars by their `mxc://` URI, `None` when the member has none
+    member_avatars: Mutex<HashMap<OwnedMxcUri, Option<Arc<[u8]>>>>,
 }
 
 impl MatrixClient {
@@ -60,39 +276,15 @@ impl MatrixClient {
         password: &str,
         homeserver: &str,
     ) -> Result<Arc<Self>> {
-        // Generate random storage path for desktop platforms
-        #[cfg(not(target_arch = "wasm32"))]
-        let db_path = {
-            let data_dir = dirs::data_dir().unwrap().join("echat");
-            let db_subfolder: String = rand::rng()
-                .sample_iter(Alphanumeric)
-                .take(7)
-                .map(char::from)
-                .collect();
-            data_dir.join(db_subfolder)
-        };
-
-        // Generate secure random passphrase for database encryption
-        let passphrase: String = rand::rng()
-            .sample_iter(Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
-
         // Create platform-specific client
-        let client = Self::build_client(
-            homeserver,
-            &passphrase,
-            #[cfg(not(target_arch = "wasm32"))]
-            &db_path,
-        )
-        .await?;
+        let client_session = ClientSession::generate(homeserver);
+        let client = client_session.build().await?;
 
         // Perform login
         client
             .matrix_auth()
             .login_username(username, password)
-            .initial_device_display_name("matrix-client")
+            .initial_device_display_name(&device_name())
             .await?;
 
         // Get session information
@@ -102,14 +294,6 @@ impl MatrixClient {
             .ok_or_else(|| anyhow!("Не удалось получить сессию после входа"))?
             .clone();
 
-        // Create client session object
-        let client_session = ClientSession {
-            homeserver: homeserver.to_owned(),
-            passphrase,
-            #[cfg(not(target_arch = "wasm32"))]
-            db_path,
-        };
-
         // Store session information
         let full_session = FullSession {
             client_session: client_session.clone(),
@@ -122,15 +306,36 @@ impl MatrixClient {
         log::info!("Matrix client session created");
 
         // Create and return the client
-        Ok(Arc::new(Self {
+        Ok(Self::with_client(client, client_session, None))
+    }
+
+    /// Wrap a signed in SDK client
+    fn with_client(
+        client: matrix_sdk::Client,
+        client_session: ClientSession,
+        sync_token: Option<String>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
             client,
-            sync_token: Mutex::new(None),
+            sync_token: Mutex::new(sync_token),
             client_session,
             event_groups: Arc::default(),
             selected_room: AsyncMutex::default(),
             pagination_token: Mutex::default(),
+            history_room: Mutex::default(),
             processed_events: AsyncMutex::default(),
-        }))
+            thread_root: Mutex::default(),
+            thread_groups: Arc::default(),
+            thread_pagination_token: Mutex::default(),
+            member_list: Mutex::default(),
+            typing: Arc::default(),
+            presence: Arc::default(),
+            share_presence: Mutex::new(true),
+            sliding_sync: OnceCell::new(),
+            listed_rooms: Mutex::default(),
+            presence_polled_at: Mutex::default(),
+            member_avatars: Mutex::default(),
+        })
     }
 
     /// Build platform-specific Matrix client
@@ -177,48 +382,180 @@ impl MatrixClient {
             let full_session: FullSession = serde_json::from_str(&serialized)?;
 
             // Create platform-specific client
-            let client = {
-                #[cfg(target_arch = "wasm32")]
-                {
-                    Self::build_client(
-                        &full_session.client_session.homeserver,
-                        &full_session.client_session.passphrase,
-                    )
-                    .await?
-                }
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    Self::build_client(
-                        &full_session.client_session.homeserver,
-                        &full_session.client_session.passphrase,
-                        &full_session.client_session.db_path,
-                    )
-                    .await?
-                }
-            };
+            let client = full_session.client_session.build().await?;
 
             // Restore session
             client.restore_session(full_session.user_session).await?;
 
             // Create and return the client
-            Ok(Arc::new(Self {
+            Ok(Self::with_client(
                 client,
-                sync_token: Mutex::new(full_session.sync_token),
-                client_session: full_session.client_session,
-                event_groups: Arc::default(),
-                selected_room: AsyncMutex::default(),
-                pagination_token: Mutex::default(),
-                processed_events: AsyncMutex::default(),
-            }))
+                full_session.client_session,
+                full_session.sync_token,
+            ))
         })
     }
 
-    /// Process timeline events into event groups for display
+    /// Look up a room by its ID string
+    fn room(&self, chat_id: &str) -> Result<Room> {
+        let room_id = RoomId::parse(chat_id)?;
+        self.client
+            .get_room(&room_id)
+            .ok_or_else(|| anyhow!("Комната не найдена: {}", chat_id))
+    }
+
+    /// Download media by its `mxc://` URI
+    async fn fetch_mxc(&self, uri: &MxcUri) -> Result<Arc<[u8]>> {
+        let request = MediaRequestParameters {
+            source: MediaSource::Plain(uri.to_owned()),
+            format: MediaFormat::File,
+        };
+        let content = self
+            .client
+            .media()
+            .get_media_content(&request, true)
+            .await?;
+        Ok(Arc::from(content))
+    }
+
+    /// Parse a room ID, alias, `matrix.to` link or `matrix:` URI into a join target
+    fn parse_target(
+        target: &str,
+        via: &[String],
+    ) -> Result<(OwnedRoomOrAliasId, Vec<OwnedServerName>)> {
+        let target = target.trim();
+        let mut servers = via
+            .iter()
+            .map(|s| OwnedServerName::try_from(s.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (id, link_via) = if let Ok(uri) = MatrixToUri::parse(target) {
+            (uri.id().clone(), uri.via().to_vec())
+        } else if let Ok(uri) = MatrixUri::parse(target) {
+            (uri.id().clone(), uri.via().to_vec())
+        } else {
+            return Ok((RoomOrAliasId::parse(target)?, servers));
+        };
+        servers.extend(link_via);
+
+        let room = match id {
+            MatrixId::Room(room_id) => room_id.into(),
+            MatrixId::RoomAlias(alias) => alias.into(),
+            MatrixId::Event(room, _) => room,
+            _ => return Err(anyhow!("Ссылка не указывает на комнату: {}", target)),
+        };
+
+        Ok((room, servers))
+    }
+
+    /// Collect the room IDs listed in a space's `m.space.child` state
+    async fn space_child_ids(&self, space: &Room) -> Result<Vec<String>> {
+        let mut children = Vec::new();
+
+        for raw in space
+            .get_state_events_static::<SpaceChildEventContent>()
+            .await?
+        {
+            // Children with an empty `via` list have been removed from the space
+            if let SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) = raw.deserialize()?
+                && !ev.content.via.is_empty()
+            {
+                children.push(ev.state_key.to_string());
+            }
+        }
+
+        Ok(children)
+    }
+
+    /// Users in the account's `m.ignored_user_list`
+    async fn ignored_user_ids(&self) -> Result<HashSet<OwnedUserId>> {
+        let content = self
+            .client
+            .account()
+            .account_data::<IgnoredUserListEventContent>()
+            .await?
+            .map(|raw| raw.deserialize())
+            .transpose()?;
+        Ok(content
+            .map(|content| content.ignored_users.into_keys().collect())
+            .unwrap_or_default())
+    }
+
+    /// Link to the room replacing an upgraded room, routed through the server
+    /// of whoever upgraded it
+    async fn replacement_link(&self, room: &Room) -> Result<Option<String>> {
+        let Some(raw) = room
+            .get_state_event_static::<RoomTombstoneEventContent>()
+            .await?
+        else {
+            return Ok(None);
+        };
+        let SyncOrStrippedState::Sync(SyncStateEvent::Original(ev)) = raw.deserialize()? else {
+            return Ok(None);
+        };
+
+        let via = ev.sender.server_name().to_owned();
+        let uri = ev.content.replacement_room.matrix_to_uri_via([via]);
+        Ok(Some(uri.to_string()))
+    }
+
+    /// Remember where history continues after a page of `room`, following
+    /// the upgrade chain back once the room's creation is reached
+    fn continue_history(&self, room: &Room, end: Option<String>) {
+        let predecessor = end.is_none().then(|| {
+            let predecessor = room.create_content()?.predecessor?;
+            self.client.get_room(&predecessor.room_id)
+        });
+
+        if let Some(Some(predecessor)) = predecessor {
+            *self.history_room.lock() = Some(predecessor);
+        }
+        *self.pagination_token.lock() = end;
+    }
+
+    /// Build a space tree node, guarding against cyclic space graphs
+    fn build_space(
+        id: &str,
+        info: &HashMap<String, SpaceInfo>,
+        visited: &mut Vec<String>,
+    ) -> Option<Space> {
+        if visited.iter().any(|v| v == id) {
+            return None;
+        }
+        let space_info = info.get(id)?;
+        visited.push(id.to_owned());
+
+        let mut space = Space {
+            id: id.to_owned(),
+            name: space_info.name.clone(),
+            avatar: space_info.avatar.clone(),
+            chats: Vec::new(),
+            subspaces: Vec::new(),
+        };
+
+        for child in &space_info.children {
+            if info.contains_key(child) {
+                if let Some(subspace) = Self::build_space(child, info, visited) {
+                    space.subspaces.push(subspace);
+                }
+            } else {
+                space.chats.push(child.clone());
+            }
+        }
+
+        visited.pop();
+        Some(space)
+    }
+
+    /// Process timeline events into event groups for display.
+    /// With `thread` set, only replies in that thread are kept and go to the thread
+    /// panel; otherwise thread replies are left out of the main timeline.
     async fn process_timeline_events(
         &self,
-        timeline: &Messages,
+        chunk: &[TimelineEvent],
         room: &Room,
         prepend: bool,
+        thread: Option<&str>,
     ) -> Result<()> {
         let mut new_groups = Vec::new();
         let mut current_group: Option<EventGroup> = None;
@@ -227,21 +564,64 @@ impl MatrixClient {
             .client
             .user_id()
             .ok_or_else(|| anyhow!("Не авторизован"))?;
+        let target = match thread {
+            Some(_) => &self.thread_groups,
+            None => &self.event_groups,
+        };
+        let ignored = self.ignored_user_ids().await?;
+        let readers = self.read_receipts(room).await?;
 
         // Process events in reverse chronological order
-        for event in timeline.chunk.iter().rev() {
-            // Try to deserialize as a message-like event
-            if let AnySyncTimelineEvent::MessageLike(msg) = event.raw().deserialize()? {
-                let event_id = msg.event_id().to_string();
+        for event in chunk.iter().rev() {
+            // Messages and chat state changes are shown, everything else is skipped
+            let timeline_event = event.raw().deserialize()?;
+            if ignored.contains(timeline_event.sender()) {
+                continue;
+            }
+            if matches!(
+                timeline_event,
+                AnySyncTimelineEvent::MessageLike(_) | AnySyncTimelineEvent::State(_)
+            ) {
+                let event_id = timeline_event.event_id().to_string();
+                let reply_to = thread_root_of(event.raw());
 
                 // Skip already processed events
-                if processed_events.contains(&event_id) {
+                let processed = match thread {
+                    Some(_) => target
+                        .lock()
+                        .iter()
+                        .any(|group| group.events.iter().any(|e| e.id == event_id)),
+                    None => !processed_events.insert(event_id.clone()),
+                };
+                if processed {
                     continue;
                 }
-                processed_events.insert(event_id.clone());
 
-                let timestamp = msg.origin_server_ts().0.into();
-                let sender = msg.sender();
+                // Extract message content or the state change
+                let event_kind = match &timeline_event {
+                    AnySyncTimelineEvent::MessageLike(msg) => message_kind(msg.original_content()),
+                    AnySyncTimelineEvent::State(state) => {
+                        system_event(state).map(EventKind::System)
+                    }
+                };
+                let Some(event_kind) = event_kind else {
+                    continue; // Skip events that aren't displayed
+                };
+
+                match (thread, &reply_to) {
+                    (None, Some(root)) => {
+                        // New replies only update the root's summary
+                        if let (false, EventKind::Message(text)) = (prepend, &event_kind) {
+                            bump_thread_summary(&self.event_groups, root, text);
+                        }
+                        continue;
+                    }
+                    (Some(open), _) if reply_to.as_deref() != Some(open) => continue,
+                    _ => {}
+                }
+
+                let timestamp = timeline_event.origin_server_ts().0.into();
+                let sender = timeline_event.sender();
 
                 // Get sender profile information
                 let member = room.get_member(sender).await?;
@@ -251,35 +631,48 @@ impl MatrixClient {
                     .unwrap_or_else(|| sender.to_string());
 
                 // Get avatar if available
-                let avatar = if let Some(m) = &member {
-                    m.avatar(MediaFormat::File).await?.map(Arc::<[u8]>::from)
-                } else {
-                    None
+                let avatar = match &member {
+                    Some(m) => self.member_avatar(m).await?,
+                    None => None,
                 };
 
-                // Extract message content
-                let event_kind = match msg.original_content() {
-                    Some(AnyMessageLikeEventContent::Message(message)) => {
-                        let text = message.text.iter().map(|t| t.body.clone()).collect();
-                        EventKind::Message(text)
-                    }
-                    Some(AnyMessageLikeEventContent::RoomMessage(message)) => {
-                        EventKind::Message(message.body().to_owned())
+                let read_by = readers.get(&event_id).cloned().unwrap_or_default();
+                let (formatted, (mentions, mentions_everyone)) = match &timeline_event {
+                    AnySyncTimelineEvent::MessageLike(msg) => {
+                        let formatted = formatted_body(msg.original_content());
+                        let mentions = message_mentions(msg.original_content(), formatted.as_ref());
+                        (formatted, mentions)
                     }
-                    _ => continue, // Skip non-message events
+                    _ => (None, (Vec::new(), false)),
                 };
 
+                // Evaluate the account's push rules locally
+                let notification = room
+                    .event_push_actions(event.raw())
+                    .await?
+                    .map(|actions| notification(&actions))
+                    .unwrap_or_default();
+
                 // Create event object
                 let event = Event {
                     id: event_id,
                     timestamp,
                     kind: event_kind,
+                    thread: thread_summary(event.raw()),
+                    read_by,
+                    formatted,
+                    mentions,
+                    mentions_everyone,
+                    notification,
+                    post: None,
                 };
 
                 // Either add to existing group or create a new one
                 match &mut current_group {
-                    Some(group) if group.user_id == *sender => {
-                        // Add to existing group if sender matches
+                    Some(group)
+                        if group.user_id == *sender && group.is_system() == event.is_system() =>
+                    {
+                        // Add to existing group if sender and kind of event match
                         group.events.push(event);
                     }
                     _ => {
@@ -294,6 +687,7 @@ impl MatrixClient {
                             avatar,
                             from_self: sender == self_user_id,
                             events: vec![event],
+                            power_level: member.as_ref().map(|m| m.power_level()),
                         });
                     }
                 }
@@ -306,7 +700,7 @@ impl MatrixClient {
         }
 
         // Update event groups in proper order
-        let mut event_groups = self.event_groups.lock();
+        let mut event_groups = target.lock();
         if prepend {
             // Add at beginning for historical messages (reversed for correct order)
             new_groups.reverse();
@@ -320,150 +714,1754 @@ impl MatrixClient {
 
         Ok(())
     }
-}
 
-#[async_trait::async_trait]
-impl Client for MatrixClient {
-    fn client_name(&self) -> &str {
-        "matrix"
-    }
+    /// Start a sliding sync session if the homeserver supports it natively
+    async fn start_sliding_sync(&self) -> Result<Option<SlidingSyncSession>> {
+        let versions = self.client.available_sliding_sync_versions().await;
+        if !versions
+            .iter()
+            .any(|version| matches!(version, Version::Native))
+        {
+            log::info!("Sliding sync isn't supported, using classic sync");
+            return Ok(None);
+        }
 
-    /// Synchronize with the Matrix server to get latest messages
-    async fn sync(&self) -> Result<()> {
-        // Set up lazy loading filter for optimization
-        let filter = FilterDefinition::with_lazy_loading();
-        let mut sync_settings = SyncSettings::default().filter(filter.into());
+        // Start with the visible window and keep growing until every room is known
+        let chat_list = SlidingSyncList::builder(CHAT_LIST)
+            .sync_mode(SlidingSyncMode::new_growing(SLIDING_SYNC_BATCH))
+            .timeline_limit(1)
+            .required_state(vec![
+                (StateEventType::RoomName, String::new()),
+                (StateEventType::RoomAvatar, String::new()),
+                (StateEventType::RoomCreate, String::new()),
+                (StateEventType::RoomEncryption, String::new()),
+                (StateEventType::SpaceChild, "*".to_owned()),
+                (StateEventType::RoomMember, "$ME".to_owned()),
+            ]);
+
+        let mut to_device = http::request::ToDevice::default();
+        to_device.enabled = Some(true);
+        let mut e2ee = http::request::E2EE::default();
+        e2ee.enabled = Some(true);
+        let mut receipts = http::request::Receipts::default();
+        receipts.enabled = Some(true);
+        let mut typing = http::request::Typing::default();
+        typing.enabled = Some(true);
+        let mut account_data = http::request::AccountData::default();
+        account_data.enabled = Some(true);
+
+        let sliding_sync = self
+            .client
+            .sliding_sync("echat")?
+            .version(Version::Native)
+            .add_list(chat_list)
+            .with_to_device_extension(to_device)
+            .with_e2ee_extension(e2ee)
+            .with_receipt_extension(receipts)
+            .with_typing_extension(typing)
+            .with_account_data_extension(account_data)
+            .build()
+            .await?;
 
-        // Use existing sync token if available
-        if let Some(token) = &*self.sync_token.lock() {
-            sync_settings = sync_settings.token(token);
+        // Typing notices only reach event handlers, the summary just names the rooms
+        let typing = self.typing.clone();
+        self.client
+            .add_event_handler(move |event: SyncTypingEvent, room: Room| {
+                let typing = typing.clone();
+                async move {
+                    if let Err(e) = update_typing(&typing, &room, &event.content.user_ids).await {
+                        log::warn!("Failed to update typing in {}: {}", room.room_id(), e);
+                    }
+                }
+            });
+
+        // One stream keeps the connection's position between requests; it ends after
+        // an error and is started again once `sync` has seen that error
+        let (sender, updates) = mpsc::channel(1);
+        let driver = sliding_sync.clone();
+        let mut room_updates = self.client.subscribe_to_all_room_updates();
+        task::spawn(async move {
+            loop {
+                let stream = driver.sync();
+                pin_mut!(stream);
+                while let Some(summary) = stream.next().await {
+                    let update = SlidingSyncUpdate {
+                        summary,
+                        rooms: take_room_updates(&mut room_updates),
+                    };
+                    if sender.send(update).await.is_err() {
+                        // The client was dropped
+                        return;
+                    }
+                }
+            }
+        });
+
+        log::info!("Using sliding sync");
+        Ok(Some(SlidingSyncSession {
+            sliding_sync,
+            updates: AsyncMutex::new(updates),
+        }))
+    }
+
+    /// Wait for the next sliding sync update, returning whether the chat list changed
+    async fn sliding_sync_once(&self, session: &SlidingSyncSession) -> Result<bool> {
+        let Some(update) = session.updates.lock().await.recv().await else {
+            return Err(anyhow!("Sliding sync остановлена"));
+        };
+        let summary = update.summary?;
+
+        // The selected room is subscribed to, so its updates carry the new events
+        let selected = self.selected_room.lock().await.clone();
+        if let Some(room) = selected {
+            for rooms in &update.rooms {
+                if let Some(room_info) = rooms.join.get(room.room_id()) {
+                    self.update_selected_room(&room, room_info).await?;
+                }
+            }
         }
 
-        // Perform sync
-        let response = self.client.sync_once(sync_settings).await?;
-        *self.sync_token.lock() = Some(response.next_batch);
+        self.poll_presence().await;
 
-        // Process new messages for each joined room
-        for (room_id, room_info) in response.rooms.join {
-            let room = self
-                .client
-                .get_room(&room_id)
-                .ok_or_else(|| anyhow!("Комната не найдена: {}", room_id))?;
+        // Every response updates the lists' counts and most bring receipts or typing,
+        // so only changes to what the chat list shows count
+        let listed = self.listed_rooms.lock();
+        let changed = summary.rooms.iter().any(|room_id| {
+            match (self.client.get_room(room_id), listed.get(room_id)) {
+                (Some(room), Some(listed)) => ListedRoom::new(&room) != *listed,
+                (Some(room), None) => room.state() != RoomState::Left,
+                (None, listed) => listed.is_some(),
+            }
+        });
+        Ok(changed)
+    }
 
-            // Create Messages object from timeline info
-            let timeline = room_info.timeline;
-            let messages = Messages {
-                chunk: timeline.events,
-                start: timeline.prev_batch.clone().unwrap_or_default(),
-                end: timeline.prev_batch,
-                state: Vec::new(),
-            };
+    /// Add new events of the selected room from a sync, and move read receipts
+    async fn update_selected_room(&self, room: &Room, room_info: &JoinedRoomUpdate) -> Result<()> {
+        let events = &room_info.timeline.events;
+        self.process_timeline_events(events, room, false, None)
+            .await?;
 
-            // Process events
-            self.process_timeline_events(&messages, &room, false)
+        // Replies to the open thread also go to the thread panel
+        let thread_root = self.thread_root.lock().clone();
+        if let Some(root) = thread_root {
+            self.process_timeline_events(events, room, false, Some(&root))
                 .await?;
         }
 
+        // Ephemeral events carry the receipts of other members
+        if !room_info.ephemeral.is_empty() {
+            self.refresh_read_receipts(room).await?;
+        }
         Ok(())
     }
 
-    /// Save current session state to storage
-    fn save(&self, storage: &mut dyn eframe::Storage, key: &str) -> Result<()> {
-        let user_session = self
+    /// Request the presence of direct chat partners now and then, as sliding sync
+    /// has no presence extension
+    async fn poll_presence(&self) {
+        let now = chrono::Utc::now().timestamp();
+        {
+            let mut polled_at = self.presence_polled_at.lock();
+            if now - *polled_at < PRESENCE_POLL_INTERVAL {
+                return;
+            }
+            *polled_at = now;
+        }
+
+        let users: HashSet<OwnedUserId> = self
             .client
-            .matrix_auth()
-            .session()
-            .ok_or_else(|| anyhow!("Сессия истекла или недоступна"))?
-            .clone();
+            .joined_rooms()
+            .iter()
+            .flat_map(|room| room.direct_targets())
+            .filter_map(|target| target.as_user_id().map(ToOwned::to_owned))
+            .take(PRESENCE_POLL_LIMIT)
+            .collect();
+        for user_id in users {
+            let response = match self
+                .client
+                .send(get_presence::v3::Request::new(user_id.clone()))
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    log::debug!("Failed to get presence of {}: {}", user_id, e);
+                    continue;
+                }
+            };
 
-        let full_session = FullSession {
-            client_session: self.client_session.clone(),
-            user_session,
-            sync_token: self.sync_token.lock().clone(),
-        };
+            let mut content = PresenceEventContent::new(response.presence);
+            content.currently_active = response.currently_active;
+            content.last_active_ago = response
+                .last_active_ago
+                .and_then(|ago| u64::try_from(ago.as_millis()).ok())
+                .and_then(UInt::new);
+            self.presence
+                .lock()
+                .insert(user_id.to_string(), presence_info(&content));
+        }
+    }
 
-        storage.set_string(key, serde_json::to_string(&full_session)?);
-        Ok(())
+    /// Rooms the chat list is built from: the ones sliding sync has loaded so far,
+    /// or every joined room under classic sync
+    async fn list_rooms(&self) -> Vec<Room> {
+        let Some(Some(session)) = self.sliding_sync.get() else {
+            return self.client.joined_rooms();
+        };
+        session
+            .sliding_sync
+            .get_all_rooms()
+            .await
+            .iter()
+            .filter_map(|room| self.client.get_room(room.room_id()))
+            .filter(|room| room.state() == RoomState::Joined)
+            .collect()
     }
 
-    /// Select a chat room and load its messages
-    async fn select_chat(&self, chat_id: &str) -> Result<()> {
-        // Parse room ID and get room
-        let room_id = RoomId::parse(chat_id)?;
-        let room = self
-            .client
-            .get_room(&room_id)
-            .ok_or_else(|| anyhow!("Комната не найдена: {}", chat_id))?;
+    /// Members other than us by the event their latest read receipt is on.
+    /// Empty in large rooms, where the avatars would only be noise.
+    async fn read_receipts(&self, room: &Room) -> Result<HashMap<String, Vec<UserProfile>>> {
+        let mut readers: HashMap<String, Vec<UserProfile>> = HashMap::new();
+        if room.joined_members_count() > READ_RECEIPT_ROOM_SIZE {
+            return Ok(readers);
+        }
 
-        // Reset state
-        self.event_groups.lock().clear();
-        *self.pagination_token.lock() = None;
-        self.processed_events.lock().await.clear();
+        for member in room.members_no_sync(RoomMemberships::JOIN).await? {
+            if self.client.user_id() == Some(member.user_id()) {
+                continue;
+            }
+            let Some((event_id, _)) = room
+                .load_user_receipt(
+                    ReceiptType::Read,
+                    ReceiptThread::Unthreaded,
+                    member.user_id(),
+                )
+                .await?
+            else {
+                continue;
+            };
 
-        // Set up message loading options
-        let mut options = MessagesOptions::backward();
-        options.limit = UInt::new(20).unwrap_or_default();
+            readers
+                .entry(event_id.to_string())
+                .or_default()
+                .push(UserProfile {
+                    id: member.user_id().to_string(),
+                    display_name: member.display_name().map(ToString::to_string),
+                    avatar: self.member_avatar(&member).await?,
+                });
+        }
 
-        // Load initial messages
-        let timeline = room.messages(options).await?;
-        self.process_timeline_events(&timeline, &room, true).await?;
+        Ok(readers)
+    }
 
-        // Update state
-        *self.pagination_token.lock() = timeline.end.clone();
-        *self.selected_room.lock().await = Some(room);
+    /// Avatar of a member, downloaded once per `mxc://` URI and then kept
+    async fn member_avatar(&self, member: &RoomMember) -> Result<Option<Arc<[u8]>>> {
+        let Some(url) = member.avatar_url() else {
+            return Ok(None);
+        };
+        if let Some(image) = self.member_avatars.lock().get(url) {
+            return Ok(image.clone());
+        }
 
-        Ok(())
+        let image = member
+            .avatar(MediaFormat::File)
+            .await?
+            .map(Arc::<[u8]>::from);
+        self.member_avatars
+            .lock()
+            .insert(url.to_owned(), image.clone());
+        Ok(image)
     }
 
-    /// Load more historical events for the selected chat
+    /// Move read-by avatars after new receipts arrived for the selected room
+    async fn refresh_read_receipts(&self, room: &Room) -> Result<()> {
+        let mut readers = self.read_receipts(room).await?;
+
+        for event in self
+            .event_groups
+            .lock()
+            .iter_mut()
+            .flat_map(|group| group.events.iter_mut())
+        {
+            event.read_by = readers.remove(&event.id).unwrap_or_default();
+        }
+
+        Ok(())
+    }
+
+    /// Fetch a page of replies to the open thread, older than `from`
+    async fn load_thread_page(&self, room: &Room, root: &str, from: Option<String>) -> Result<()> {
+        let mut request = get_relating_events_with_rel_type::v1::Request::new(
+            room.room_id().to_owned(),
+            EventId::parse(root)?,
+            RelationType::Thread,
+        );
+        request.from = from;
+        request.dir = Direction::Backward;
+        request.limit = UInt::new(20);
+
+        let response = self.client.send(request).await?;
+        let chunk = decrypt_events(room, response.chunk).await;
+        self.process_timeline_events(&chunk, room, true, Some(root))
+            .await?;
+        *self.thread_pagination_token.lock() = response.next_batch;
+
+        Ok(())
+    }
+}
+
+/// Decrypt what can be of events fetched without `Room::messages`, which does it itself
+async fn decrypt_events<T>(room: &Room, chunk: Vec<Raw<T>>) -> Vec<TimelineEvent> {
+    let mut events = Vec::with_capacity(chunk.len());
+    for raw in chunk {
+        let raw = raw.cast::<AnySyncTimelineEvent>();
+        let encrypted = matches!(
+            raw.deserialize(),
+            Ok(AnySyncTimelineEvent::MessageLike(
+                AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(_))
+            ))
+        );
+        let event = match encrypted {
+            true => match room.decrypt_event(raw.cast_ref()).await {
+                Ok(event) => event,
+                Err(_) => TimelineEvent::new(raw),
+            },
+            false => TimelineEvent::new(raw),
+        };
+        events.push(event);
+    }
+    events
+}
+
+/// Replace who is typing in a room with the latest `m.typing` content
+async fn update_typing(
+    typing: &Mutex<HashMap<String, Vec<String>>>,
+    room: &Room,
+    user_ids: &[OwnedUserId],
+) -> Result<()> {
+    let mut names = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        if room.own_user_id() == &**user_id {
+            continue;
+        }
+        let name = room
+            .get_member(user_id)
+            .await?
+            .and_then(|m| m.display_name().map(ToString::to_string))
+            .unwrap_or_else(|| user_id.to_string());
+        names.push(name);
+    }
+
+    let mut typing = typing.lock();
+    if names.is_empty() {
+        typing.remove(room.room_id().as_str());
+    } else {
+        typing.insert(room.room_id().to_string(), names);
+    }
+    Ok(())
+}
+
+fn chat_tag(room: &Room) -> Option<ChatTag> {
+    if room.is_favourite() {
+        Some(ChatTag::Favourite)
+    } else if room.is_low_priority() {
+        Some(ChatTag::LowPriority)
+    } else {
+        None
+    }
+}
+
+/// Room updates broadcast while a sliding sync response was handled, which
+/// happens before the sync stream yields its summary
+fn take_room_updates(receiver: &mut broadcast::Receiver<RoomUpdates>) -> Vec<RoomUpdates> {
+    let mut updates = Vec::new();
+    loop {
+        match receiver.try_recv() {
+            Ok(rooms) => updates.push(rooms),
+            Err(TryRecvError::Lagged(skipped)) => {
+                log::warn!("Missed {} sliding sync room updates", skipped);
+            }
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return updates,
+        }
+    }
+}
+
+/// Convert `m.presence` content, turning "last active ago" into a timestamp
+fn presence_info(content: &PresenceEventContent) -> PresenceInfo {
+    let presence = match content.presence {
+        _ if content.currently_active == Some(true) => Presence::Online,
+        PresenceState::Online => Presence::Online,
+        PresenceState::Unavailable => Presence::Unavailable,
+        _ => Presence::Offline,
+    };
+    let now = chrono::Utc::now().timestamp() as u64;
+    let last_seen = content
+        .last_active_ago
+        .map(|ago| now.saturating_sub(u64::from(ago) / 1000));
+
+    PresenceInfo {
+        presence,
+        last_seen,
+    }
+}
+
+/// Extract the displayed text of a message event
+fn message_kind(content: Option<AnyMessageLikeEventContent>) -> Option<EventKind> {
+    match content? {
+        AnyMessageLikeEventContent::Message(message) => {
+            let text = message.text.iter().map(|t| t.body.clone()).collect();
+            Some(EventKind::Message(text))
+        }
+        AnyMessageLikeEventContent::RoomMessage(message) => {
+            Some(EventKind::Message(message.body().to_owned()))
+        }
+        _ => None,
+    }
+}
+
+/// Parse the HTML body of a formatted message
+fn formatted_body(content: Option<AnyMessageLikeEventContent>) -> Option<RichText> {
+    let AnyMessageLikeEventContent::RoomMessage(message) = content? else {
+        return None;
+    };
+    let formatted = match &message.msgtype {
+        MessageType::Text(text) => text.formatted.as_ref(),
+        MessageType::Notice(notice) => notice.formatted.as_ref(),
+        MessageType::Emote(emote) => emote.formatted.as_ref(),
+        _ => None,
+    }?;
+
+    (formatted.format == MessageFormat::Html)
+        .then(|| RichText::from_html(&formatted.body))
+        .filter(|text| !text.is_plain())
+}
+
+/// Summarize the actions push rules returned for an event
+fn notification(actions: &[Action]) -> Notification {
+    Notification {
+        notify: actions.iter().any(Action::should_notify),
+        highlight: actions.iter().any(Action::is_highlight),
+        sound: actions.iter().any(|action| action.sound().is_some()),
+    }
+}
+
+fn notify_mode(mode: RoomNotificationMode) -> NotifyMode {
+    match mode {
+        RoomNotificationMode::AllMessages => NotifyMode::AllMessages,
+        RoomNotificationMode::MentionsAndKeywordsOnly => NotifyMode::MentionsOnly,
+        RoomNotificationMode::Mute => NotifyMode::Mute,
+    }
+}
+
+/// Users a message mentions and whether it mentions the whole room. Pills in
+/// the formatted body count for clients that don't send `m.mentions` yet.
+fn message_mentions(
+    content: Option<AnyMessageLikeEventContent>,
+    formatted: Option<&RichText>,
+) -> (Vec<String>, bool) {
+    let mut users = formatted.map(RichText::mentions).unwrap_or_default();
+    let mut everyone = false;
+
+    if let Some(AnyMessageLikeEventContent::RoomMessage(message)) = content
+        && let Some(mentions) = &message.mentions
+    {
+        users.extend(mentions.user_ids.iter().map(ToString::to_string));
+        everyone = mentions.room;
+    }
+    users.sort_unstable();
+    users.dedup();
+
+    (users, everyone)
+}
+
+/// Describe a chat state change, if it is worth showing in the timeline
+fn system_event(state: &AnySyncStateEvent) -> Option<SystemEvent> {
+    let event = match state {
+        AnySyncStateEvent::RoomMember(member) => {
+            let member = member.as_original()?;
+            let target = || {
+                member
+                    .content
+                    .displayname
+                    .clone()
+                    .unwrap_or_else(|| member.state_key.to_string())
+            };
+
+            match member.membership_change() {
+                MembershipChange::Joined
+                | MembershipChange::InvitationAccepted
+                | MembershipChange::KnockAccepted => SystemEvent::Joined,
+                MembershipChange::Left
+                | MembershipChange::InvitationRejected
+                | MembershipChange::KnockRetracted => SystemEvent::Left,
+                MembershipChange::Invited => SystemEvent::Invited(target()),
+                MembershipChange::Kicked
+                | MembershipChange::InvitationRevoked
+                | MembershipChange::KnockDenied => SystemEvent::Removed(target()),
+                MembershipChange::Banned | MembershipChange::KickedAndBanned => {
+                    SystemEvent::Banned(target())
+                }
+                MembershipChange::ProfileChanged { .. } => SystemEvent::ProfileChanged,
+                _ => return None,
+            }
+        }
+        AnySyncStateEvent::RoomCreate(_) => SystemEvent::Created,
+        AnySyncStateEvent::RoomName(name) => {
+            SystemEvent::Renamed(name.as_original()?.content.name.clone())
+        }
+        AnySyncStateEvent::RoomTopic(topic) => {
+            SystemEvent::TopicChanged(topic.as_original()?.content.topic.clone())
+        }
+        AnySyncStateEvent::RoomAvatar(_) => SystemEvent::AvatarChanged,
+        AnySyncStateEvent::RoomPinnedEvents(_) => SystemEvent::Pinned,
+        AnySyncStateEvent::RoomTombstone(_) => SystemEvent::Upgraded,
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// ID of the thread root an event replies to, if it is part of a thread
+fn thread_root_of<T>(raw: &Raw<T>) -> Option<String> {
+    let content: serde_json::Value = raw.get_field("content").ok()??;
+    let relation = content.get("m.relates_to")?;
+    if relation.get("rel_type")?.as_str()? != "m.thread" {
+        return None;
+    }
+    relation.get("event_id")?.as_str().map(ToOwned::to_owned)
+}
+
+/// Reply summary the server bundles with thread roots
+fn thread_summary<T>(raw: &Raw<T>) -> Option<ThreadSummary> {
+    let unsigned: serde_json::Value = raw.get_field("unsigned").ok()??;
+    let thread = unsigned.get("m.relations")?.get("m.thread")?;
+
+    Some(ThreadSummary {
+        reply_count: thread.get("count")?.as_u64()?,
+        latest_reply: thread
+            .pointer("/latest_event/content/body")
+            .and_then(|body| body.as_str())
+            .map(ToOwned::to_owned),
+    })
+}
+
+/// Count a new reply on its root, if the root is loaded
+fn bump_thread_summary(groups: &Mutex<Vec<EventGroup>>, root: &str, text: &str) {
+    let mut groups = groups.lock();
+    let root = groups
+        .iter_mut()
+        .flat_map(|group| group.events.iter_mut())
+        .find(|event| event.id == root);
+
+    if let Some(event) = root {
+        let summary = event.thread.get_or_insert_with(ThreadSummary::default);
+        summary.reply_count += 1;
+        summary.latest_reply = Some(text.to_owned());
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for MatrixClient {
+    fn client_name(&self) -> &str {
+        "matrix"
+    }
+
+    /// Synchronize with the Matrix server to get latest messages
+    async fn sync(&self) -> Result<bool> {
+        // Prefer sliding sync, which only loads the rooms the chat list shows
+        let sliding_sync = self
+            .sliding_sync
+            .get_or_init(|| async {
+                self.start_sliding_sync().await.unwrap_or_else(|e| {
+                    log::warn!("Failed to start sliding sync: {}", e);
+                    None
+                })
+            })
+            .await;
+        // Once its driver has stopped, classic sync takes over
+        if let Some(session) = sliding_sync
+            && !session.stopped()
+        {
+            return self.sliding_sync_once(session).await;
+        }
+
+        // Set up lazy loading filter for optimization
+        let filter = FilterDefinition::with_lazy_loading();
+        let mut sync_settings = SyncSettings::default().filter(filter.into());
+
+        // Use existing sync token if available
+        if let Some(token) = &*self.sync_token.lock() {
+            sync_settings = sync_settings.token(token);
+        }
+
+        // Syncing as offline keeps the server from announcing us
+        let presence = if *self.share_presence.lock() {
+            PresenceState::Online
+        } else {
+            PresenceState::Offline
+        };
+        sync_settings = sync_settings.set_presence(presence);
+
+        // Perform sync
+        let response = self.client.sync_once(sync_settings).await?;
+        *self.sync_token.lock() = Some(response.next_batch);
+
+        for raw in &response.presence {
+            if let Ok(event) = raw.deserialize() {
+                self.presence
+                    .lock()
+                    .insert(event.sender.to_string(), presence_info(&event.content));
+            }
+        }
+
+        let rooms = &response.rooms;
+        let chats_changed =
+            !rooms.join.is_empty() || !rooms.invite.is_empty() || !rooms.leave.is_empty();
+
+        // Only the selected room's timeline is kept in memory, other rooms just
+        // change the chat list
+        let selected = self.selected_room.lock().await.clone();
+        for (room_id, room_info) in response.rooms.join {
+            let room = self
+                .client
+                .get_room(&room_id)
+                .ok_or_else(|| anyhow!("Комната не найдена: {}", room_id))?;
+
+            // Typing is shown in the chat list for every room
+            for raw in &room_info.ephemeral {
+                if let Ok(AnySyncEphemeralRoomEvent::Typing(typing)) = raw.deserialize() {
+                    update_typing(&self.typing, &room, &typing.content.user_ids).await?;
+                }
+            }
+
+            if selected.as_ref().map(|r| r.room_id()) == Some(&*room_id) {
+                self.update_selected_room(&room, &room_info).await?;
+            }
+        }
+
+        Ok(chats_changed)
+    }
+
+    /// Save current session state to storage
+    fn save(&self, storage: &mut dyn eframe::Storage, key: &str) -> Result<()> {
+        let user_session = self
+            .client
+            .matrix_auth()
+            .session()
+            .ok_or_else(|| anyhow!("Сессия истекла или недоступна"))?
+            .clone();
+
+        let full_session = FullSession {
+            client_session: self.client_session.clone(),
+            user_session,
+            sync_token: self.sync_token.lock().clone(),
+        };
+
+        storage.set_string(key, serde_json::to_string(&full_session)?);
+        Ok(())
+    }
+
+    /// Select a chat room and load its messages
+    async fn select_chat(&self, chat_id: &str) -> Result<()> {
+        // Parse room ID and get room
+        let room = self.room(chat_id)?;
+
+        // Reset state
+        self.event_groups.lock().clear();
+        *self.pagination_token.lock() = None;
+        self.processed_events.lock().await.clear();
+
+        // Set up message loading options
+        let mut options = MessagesOptions::backward();
+        options.limit = UInt::new(20).unwrap_or_default();
+
+        // Load initial messages
+        let timeline = room.messages(options).await?;
+        self.process_timeline_events(&timeline.chunk, &room, true, None)
+            .await?;
+
+        // Sliding sync only follows the selected room in full
+        if let Some(Some(session)) = self.sliding_sync.get() {
+            session
+                .sliding_sync
+                .subscribe_to_rooms(&[room.room_id()], None, true);
+        }
+
+        // Update state
+        *self.history_room.lock() = Some(room.clone());
+        self.continue_history(&room, timeline.end.clone());
+        *self.selected_room.lock().await = Some(room);
+
+        Ok(())
+    }
+
+    /// Load more historical events for the selected chat
     async fn load_more_events(&self) -> Result<()> {
-        // Get selected room
+        // Get the selected room, or the room it replaced once its history ran out
+        let room = self
+            .history_room
+            .lock()
+            .clone()
+            .ok_or_else(|| anyhow!("Комната не выбрана"))?;
+
+        // Set up options with pagination token
+        let mut options = MessagesOptions::backward();
+        options.limit = UInt::new(20).unwrap_or_default();
+        options.from = self.pagination_token.lock().clone();
+
+        // Load and process messages
+        let timeline = room.messages(options).await?;
+        self.process_timeline_events(&timeline.chunk, &room, true, None)
+            .await?;
+        self.continue_history(&room, timeline.end.clone());
+
+        Ok(())
+    }
+
+    /// Send a plain text message to a room
+    async fn send_message(&self, chat_id: &str, text: &str) -> Result<()> {
+        let room = self.room(chat_id)?;
+        room.send(RoomMessageEventContent::text_plain(text)).await?;
+        Ok(())
+    }
+
+    /// Delete an event (placeholder implementation)
+    async fn delete_event(&self, _message_id: &str) -> Result<()> {
+        // TODO: Implement event deletion
+        Ok(())
+    }
+
+    /// Start or stop the typing notice; the SDK keeps it alive while typing
+    async fn set_typing(&self, chat_id: &str, typing: bool) -> Result<()> {
+        self.room(chat_id)?.typing_notice(typing).await?;
+        Ok(())
+    }
+
+    /// Get who is typing, updated from `m.typing` on every sync
+    fn typing(&self) -> Result<TypingUsers> {
+        Ok(self.typing.clone())
+    }
+
+    /// Get presence received through sync
+    fn presence(&self) -> Result<Arc<Mutex<HashMap<String, PresenceInfo>>>> {
+        Ok(self.presence.clone())
+    }
+
+    /// Room avatars are downloaded while listing the chats, so none arrive later
+    fn chat_avatars(&self) -> Result<ChatAvatars> {
+        Ok(ChatAvatars::default())
+    }
+
+    /// Presence is not a stored setting, so this is the same as `set_online`
+    async fn share_presence(&self, share: bool) -> Result<()> {
+        self.set_online(share).await
+    }
+
+    /// Announce ourselves as offline, or online again, right away and on later syncs
+    async fn set_online(&self, online: bool) -> Result<()> {
+        *self.share_presence.lock() = online;
+
+        let user_id = self
+            .client
+            .user_id()
+            .ok_or_else(|| anyhow!("Не авторизован"))?;
+        let state = if online {
+            PresenceState::Online
+        } else {
+            PresenceState::Offline
+        };
+        self.client
+            .send(set_presence::v3::Request::new(user_id.to_owned(), state))
+            .await?;
+        Ok(())
+    }
+
+    /// Send a read receipt and move the fully-read marker
+    async fn mark_read(&self, chat_id: &str, event_id: Option<&str>) -> Result<()> {
+        let room = self.room(chat_id)?;
+
+        let event_id = match event_id {
+            Some(event_id) => EventId::parse(event_id)?,
+            None => {
+                // Everything is read up to the latest event in the room
+                let mut options = MessagesOptions::backward();
+                options.limit = UInt::new(1).unwrap_or_default();
+                let latest = room.messages(options).await?;
+                match latest.chunk.first().and_then(|event| event.event_id()) {
+                    Some(event_id) => event_id,
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        room.send_multiple_receipts(
+            Receipts::new()
+                .fully_read_marker(event_id.clone())
+                .public_read_receipt(event_id),
+        )
+        .await?;
+
+        if room.is_marked_unread() {
+            room.set_unread_flag(false).await?;
+        }
+        Ok(())
+    }
+
+    /// Set or clear the `m.marked_unread` flag
+    async fn set_unread(&self, chat_id: &str, unread: bool) -> Result<()> {
+        self.room(chat_id)?.set_unread_flag(unread).await?;
+        Ok(())
+    }
+
+    /// Update the `m.favourite` and `m.lowpriority` room tags
+    async fn set_chat_tag(&self, chat_id: &str, tag: Option<ChatTag>) -> Result<()> {
+        let room = self.room(chat_id)?;
+        room.set_is_favourite(tag == Some(ChatTag::Favourite), None)
+            .await?;
+        room.set_is_low_priority(tag == Some(ChatTag::LowPriority), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Read the `m.fully_read` marker from room account data
+    async fn read_marker(&self, chat_id: &str) -> Result<Option<String>> {
+        let room = self.room(chat_id)?;
+        let marker = room
+            .account_data_static::<FullyReadEventContent>()
+            .await?
+            .map(|raw| raw.deserialize())
+            .transpose()?;
+
+        Ok(marker.map(|event| event.content.event_id.to_string()))
+    }
+
+    /// Get current event groups
+    fn event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
+        Ok(self.event_groups.clone())
+    }
+
+    /// List every thread in a room, newest activity first
+    async fn threads(&self, chat_id: &str, from: Option<&str>) -> Result<ThreadPage> {
+        let room = self.room(chat_id)?;
+
+        let mut request = get_threads::v1::Request::new(room.room_id().to_owned());
+        request.from = from.map(ToOwned::to_owned);
+        request.limit = UInt::new(20);
+        let response = self.client.send(request).await?;
+
+        // Summaries are added by the server, so they stay on the encrypted original
+        let summaries: Vec<_> = response.chunk.iter().map(thread_summary).collect();
+        let events = decrypt_events(&room, response.chunk).await;
+
+        let mut threads = Vec::with_capacity(events.len());
+        for (event, thread) in events.iter().zip(summaries) {
+            // One malformed or undecryptable root shouldn't hide the other threads
+            let Ok(AnySyncTimelineEvent::MessageLike(msg)) = event.raw().deserialize() else {
+                continue;
+            };
+            let Some(kind) = message_kind(msg.original_content()) else {
+                continue;
+            };
+
+            let formatted = formatted_body(msg.original_content());
+            let (mentions, mentions_everyone) =
+                message_mentions(msg.original_content(), formatted.as_ref());
+
+            // Names come from the store, fetching every member isn't worth a thread list
+            let sender = room
+                .get_member_no_sync(msg.sender())
+                .await?
+                .and_then(|m| m.display_name().map(ToString::to_string))
+                .unwrap_or_else(|| msg.sender().to_string());
+
+            threads.push(ThreadRoot {
+                sender,
+                root: Event {
+                    id: msg.event_id().to_string(),
+                    timestamp: msg.origin_server_ts().0.into(),
+                    kind,
+                    thread,
+                    read_by: Vec::new(),
+                    formatted,
+                    mentions,
+                    mentions_everyone,
+                    notification: Notification::default(),
+                    post: None,
+                },
+            });
+        }
+
+        Ok(ThreadPage {
+            threads,
+            next_batch: response.next_batch,
+        })
+    }
+
+    /// Load the latest replies of a thread in the selected room
+    async fn open_thread(&self, root_id: &str) -> Result<()> {
+        let room = {
+            let lock = self.selected_room.lock().await;
+            lock.clone().ok_or_else(|| anyhow!("Комната не выбрана"))?
+        };
+
+        self.thread_groups.lock().clear();
+        *self.thread_pagination_token.lock() = None;
+        *self.thread_root.lock() = Some(root_id.to_owned());
+
+        self.load_thread_page(&room, root_id, None).await
+    }
+
+    /// Load older replies of the open thread
+    async fn load_more_thread_events(&self) -> Result<bool> {
+        let room = {
+            let lock = self.selected_room.lock().await;
+            lock.clone().ok_or_else(|| anyhow!("Комната не выбрана"))?
+        };
+        let root = self
+            .thread_root
+            .lock()
+            .clone()
+            .ok_or_else(|| anyhow!("Тред не открыт"))?;
+
+        // No token after the first page means the start of the thread was reached
+        let Some(from) = self.thread_pagination_token.lock().clone() else {
+            return Ok(false);
+        };
+
+        self.load_thread_page(&room, &root, Some(from)).await?;
+        Ok(self.thread_pagination_token.lock().is_some())
+    }
+
+    /// Get event groups of the open thread
+    fn thread_event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
+        Ok(self.thread_groups.clone())
+    }
+
+    /// Send a plain text reply into a thread of the selected room
+    async fn send_thread_reply(&self, root_id: &str, text: &str) -> Result<()> {
         let room = {
             let lock = self.selected_room.lock().await;
             lock.clone().ok_or_else(|| anyhow!("Комната не выбрана"))?
         };
 
-        // Set up options with pagination token
-        let mut options = MessagesOptions::backward();
-        options.limit = UInt::new(20).unwrap_or_default();
-        options.from = self.pagination_token.lock().clone();
+        // Clients without thread support show the reply as answering the latest message
+        let latest = self
+            .thread_groups
+            .lock()
+            .last()
+            .and_then(|group| group.events.last())
+            .map(|event| event.id.clone())
+            .filter(|_| self.thread_root.lock().as_deref() == Some(root_id))
+            .unwrap_or_else(|| root_id.to_owned());
+
+        let mut content = RoomMessageEventContent::text_plain(text);
+        content.relates_to = Some(Relation::Thread(Thread::plain(
+            EventId::parse(root_id)?,
+            EventId::parse(&latest)?,
+        )));
+        room.send(content).await?;
+
+        Ok(())
+    }
+
+    /// Get list of available chats
+    async fn chats(&self) -> Result<Vec<Chat>> {
+        // Invites are listed by `invites`, left and banned rooms not at all
+        let rooms = self.list_rooms().await;
+        let mut chats = Vec::with_capacity(rooms.len());
+        *self.listed_rooms.lock() = rooms
+            .iter()
+            .map(|room| (room.room_id().to_owned(), ListedRoom::new(room)))
+            .collect();
+
+        for room in rooms {
+            // Spaces are exposed separately through `spaces`
+            if room.is_space() {
+                continue;
+            }
+
+            // Upgraded rooms are reached through the history of their replacement
+            if let Some(tombstone) = room.tombstone() {
+                let joined = self
+                    .client
+                    .get_room(&tombstone.replacement_room)
+                    .is_some_and(|replacement| replacement.state() == RoomState::Joined);
+                if joined {
+                    continue;
+                }
+            }
+            let replaced_by = if room.is_tombstoned() {
+                self.replacement_link(&room).await?
+            } else {
+                None
+            };
+
+            // Get room avatar if available
+            let avatar = room.avatar(MediaFormat::File).await?.map(Arc::<[u8]>::from);
+
+            // Direct chats are the ones listed in `m.direct` account data
+            let is_direct = room.is_direct().await?;
+            let direct_user = if is_direct {
+                room.direct_targets()
+                    .iter()
+                    .find_map(|target| target.as_user_id().map(ToString::to_string))
+            } else {
+                None
+            };
+
+            chats.push(Chat {
+                id: room.room_id().to_string(),
+                name: room.name(),
+                avatar,
+                unread: room.num_unread_messages() > 0 || room.is_marked_unread(),
+                direct_user,
+                mentions: room.num_unread_mentions(),
+                replaced_by,
+                kind: if is_direct {
+                    ChatKind::Direct
+                } else {
+                    ChatKind::Group
+                },
+                tag: chat_tag(&room),
+                topics: Vec::new(),
+            });
+        }
+
+        Ok(chats)
+    }
+
+    /// Get the tree of joined spaces built from `m.space.child` state
+    async fn spaces(&self) -> Result<Vec<Space>> {
+        let mut info = HashMap::new();
+
+        for room in self.client.joined_rooms() {
+            if !room.is_space() {
+                continue;
+            }
+
+            let avatar = room.avatar(MediaFormat::File).await?.map(Arc::<[u8]>::from);
+            let children = self.space_child_ids(&room).await?;
+            info.insert(
+                room.room_id().to_string(),
+                SpaceInfo {
+                    name: room.name(),
+                    avatar,
+                    children,
+                },
+            );
+        }
+
+        // Top-level spaces are the ones not listed as a child of another space
+        let nested: HashSet<&String> = info.values().flat_map(|space| &space.children).collect();
+        let mut spaces = Vec::new();
+        for id in info.keys().filter(|id| !nested.contains(id)) {
+            if let Some(space) = Self::build_space(id, &info, &mut Vec::new()) {
+                spaces.push(space);
+            }
+        }
+
+        spaces.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(spaces)
+    }
+
+    /// Get direct children of a space from the hierarchy API, including unjoined ones
+    async fn space_children(&self, space_id: &str) -> Result<Vec<RoomPreview>> {
+        let room_id = RoomId::parse(space_id)?;
+        let mut children = Vec::new();
+        let mut via: HashMap<String, Vec<String>> = HashMap::new();
+        let mut from = None;
+
+        loop {
+            let mut request = get_hierarchy::v1::Request::new(room_id.clone());
+            request.max_depth = Some(UInt::from(1u32));
+            request.from = from.take();
+
+            let response = self.client.send(request).await?;
+
+            for chunk in response.rooms {
+                // Remember `via` servers announced by the parent for joining later
+                for raw in &chunk.children_state {
+                    if let Ok(ev) = raw.deserialize() {
+                        let servers = ev.content.via.iter().map(ToString::to_string).collect();
+                        via.insert(ev.state_key.to_string(), servers);
+                    }
+                }
+
+                // The first chunk is the space itself
+                if chunk.room_id == room_id {
+                    continue;
+                }
+
+                let avatar = match &chunk.avatar_url {
+                    Some(uri) => self.fetch_mxc(uri).await.ok(),
+                    None => None,
+                };
+                let joined = self
+                    .client
+                    .get_room(&chunk.room_id)
+                    .is_some_and(|room| room.state() == RoomState::Joined);
+
+                children.push(RoomPreview {
+                    id: chunk.room_id.to_string(),
+                    alias: chunk.canonical_alias.map(|a| a.to_string()),
+                    name: chunk.name,
+                    topic: chunk.topic,
+                    avatar,
+                    member_count: chunk.num_joined_members.into(),
+                    is_space: chunk.room_type == Some(RoomType::Space),
+                    joined,
+                    via: Vec::new(),
+                });
+            }
+
+            match response.next_batch {
+                Some(token) => from = Some(token),
+                None => break,
+            }
+        }
+
+        for child in &mut children {
+            child.via = via.remove(&child.id).unwrap_or_default();
+        }
+
+        Ok(children)
+    }
+
+    /// Join a room by ID, alias or link, returning the joined room ID
+    async fn join_chat(&self, target: &str, via: &[String]) -> Result<String> {
+        let (target, servers) = Self::parse_target(target, via)?;
+
+        let room = self
+            .client
+            .join_room_by_id_or_alias(&target, &servers)
+            .await?;
+
+        Ok(room.room_id().to_string())
+    }
+
+    /// Preview a room by ID, alias or link without joining it
+    async fn preview_chat(&self, target: &str) -> Result<RoomPreview> {
+        let (target, servers) = Self::parse_target(target, &[])?;
+        let preview = self
+            .client
+            .get_room_preview(&target, servers.clone())
+            .await?;
+
+        let avatar = match &preview.avatar_url {
+            Some(uri) => self.fetch_mxc(uri).await.ok(),
+            None => None,
+        };
+
+        Ok(RoomPreview {
+            id: preview.room_id.to_string(),
+            alias: preview.canonical_alias.map(|a| a.to_string()),
+            name: preview.name,
+            topic: preview.topic,
+            avatar,
+            member_count: preview.num_joined_members,
+            is_space: preview.room_type == Some(RoomType::Space),
+            joined: preview.state == Some(RoomState::Joined),
+            via: servers.iter().map(ToString::to_string).collect(),
+        })
+    }
 
-        // Load and process messages
-        let timeline = room.messages(options).await?;
-        self.process_timeline_events(&timeline, &room, true).await?;
-        *self.pagination_token.lock() = timeline.end.clone();
+    /// Create a new room with the given options
+    async fn create_chat(&self, options: &NewChat) -> Result<String> {
+        let mut request = create_room::v3::Request::new();
+
+        if !options.name.trim().is_empty() {
+            request.name = Some(options.name.trim().to_owned());
+        }
+        if !options.topic.trim().is_empty() {
+            request.topic = Some(options.topic.trim().to_owned());
+        }
 
+        if options.public {
+            request.visibility = Visibility::Public;
+            request.preset = Some(RoomPreset::PublicChat);
+            if !options.alias.trim().is_empty() {
+                request.room_alias_name =
+                    Some(options.alias.trim().trim_start_matches('#').to_owned());
+            }
+        } else {
+            request.preset = Some(RoomPreset::PrivateChat);
+        }
+
+        if options.encrypted {
+            request.initial_state = vec![
+                InitialStateEvent::new(RoomEncryptionEventContent::with_recommended_defaults())
+                    .to_raw_any(),
+            ];
+        }
+
+        let room = self.client.create_room(request).await?;
+        Ok(room.room_id().to_string())
+    }
+
+    /// Open the existing DM with a user or create one marked in `m.direct`
+    async fn create_direct_chat(&self, user: &str) -> Result<String> {
+        let user_id = UserId::parse(user.trim())?;
+
+        if let Some(room) = self.client.get_dm_room(&user_id) {
+            return Ok(room.room_id().to_string());
+        }
+
+        let room = self.client.create_dm(&user_id).await?;
+        Ok(room.room_id().to_string())
+    }
+
+    /// Invite a user to a room
+    async fn invite_user(&self, chat_id: &str, user_id: &str) -> Result<()> {
+        let room = self.room(chat_id)?;
+
+        room.invite_user_by_id(&UserId::parse(user_id)?).await?;
         Ok(())
     }
 
-    /// Delete an event (placeholder implementation)
-    async fn delete_event(&self, _message_id: &str) -> Result<()> {
-        // TODO: Implement event deletion
+    /// Read the room's editable state
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings> {
+        let room = self.room(chat_id)?;
+
+        let join_rule = match room.join_rule() {
+            RoomJoinRule::Public => JoinRule::Public,
+            RoomJoinRule::Knock | RoomJoinRule::KnockRestricted(_) => JoinRule::Knock,
+            _ => JoinRule::Invite,
+        };
+        let history_visibility = match room.history_visibility_or_default() {
+            RoomHistoryVisibility::WorldReadable => HistoryVisibility::WorldReadable,
+            RoomHistoryVisibility::Invited => HistoryVisibility::Invited,
+            RoomHistoryVisibility::Joined => HistoryVisibility::Joined,
+            _ => HistoryVisibility::Shared,
+        };
+
+        Ok(ChatSettings {
+            name: room.name().unwrap_or_default(),
+            topic: room.topic().unwrap_or_default(),
+            avatar: room.avatar(MediaFormat::File).await?.map(Arc::<[u8]>::from),
+            join_rule: Some(join_rule),
+            history_visibility: Some(history_visibility),
+            encrypted: Some(room.is_encrypted().await?),
+        })
+    }
+
+    /// Rename the room
+    async fn set_chat_name(&self, chat_id: &str, name: &str) -> Result<()> {
+        self.room(chat_id)?.set_name(name.to_owned()).await?;
         Ok(())
     }
 
-    /// Get current event groups
-    fn event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
-        Ok(self.event_groups.clone())
+    /// Change the room topic
+    async fn set_chat_topic(&self, chat_id: &str, topic: &str) -> Result<()> {
+        self.room(chat_id)?.set_room_topic(topic).await?;
+        Ok(())
     }
 
-    /// Get list of available chats
-    async fn chats(&self) -> Result<Vec<Chat>> {
-        let rooms = self.client.rooms();
-        let mut chats = Vec::with_capacity(rooms.len());
+    /// Upload a new room avatar
+    async fn set_chat_avatar(&self, chat_id: &str, image: Vec<u8>) -> Result<()> {
+        let room = self.room(chat_id)?;
+        let mime: mime::Mime = image::guess_format(&image)?.to_mime_type().parse()?;
+
+        room.upload_avatar(&mime, image, None).await?;
+        Ok(())
+    }
+
+    /// Change who may join the room
+    async fn set_join_rule(&self, chat_id: &str, rule: JoinRule) -> Result<()> {
+        let rule = match rule {
+            JoinRule::Public => RoomJoinRule::Public,
+            JoinRule::Knock => RoomJoinRule::Knock,
+            JoinRule::Invite => RoomJoinRule::Invite,
+        };
+
+        self.room(chat_id)?
+            .send_state_event(RoomJoinRulesEventContent::new(rule))
+            .await?;
+        Ok(())
+    }
+
+    /// Change who may read the room history
+    async fn set_history_visibility(
+        &self,
+        chat_id: &str,
+        visibility: HistoryVisibility,
+    ) -> Result<()> {
+        let visibility = match visibility {
+            HistoryVisibility::WorldReadable => RoomHistoryVisibility::WorldReadable,
+            HistoryVisibility::Shared => RoomHistoryVisibility::Shared,
+            HistoryVisibility::Invited => RoomHistoryVisibility::Invited,
+            HistoryVisibility::Joined => RoomHistoryVisibility::Joined,
+        };
+
+        self.room(chat_id)?
+            .send_state_event(RoomHistoryVisibilityEventContent::new(visibility))
+            .await?;
+        Ok(())
+    }
+
+    /// Turn on end-to-end encryption; this can't be undone
+    async fn enable_encryption(&self, chat_id: &str) -> Result<()> {
+        self.room(chat_id)?.enable_encryption().await?;
+        Ok(())
+    }
+
+    /// Get a page of joined members, highest power level first
+    async fn members(
+        &self,
+        chat_id: &str,
+        query: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<MemberPage> {
+        let room = self.room(chat_id)?;
+        let query = query.to_lowercase();
+
+        // The list is sorted once per search, later pages are cut from it
+        let cached = self
+            .member_list
+            .lock()
+            .as_ref()
+            .filter(|list| offset > 0 && list.chat_id == chat_id && list.query == query)
+            .map(|list| list.members.clone());
+        let members = match cached {
+            Some(members) => members,
+            None => {
+                // Fetches the member list from the server the first time (lazy loading)
+                let mut members: Vec<RoomMember> = room
+                    .members(RoomMemberships::JOIN)
+                    .await?
+                    .into_iter()
+                    .filter(|member| {
+                        member.user_id().as_str().to_lowercase().contains(&query)
+                            || member.name().to_lowercase().contains(&query)
+                    })
+                    .collect();
+                members.sort_by(|a, b| {
+                    b.power_level()
+                        .cmp(&a.power_level())
+                        .then_with(|| a.name().cmp(b.name()))
+                });
+
+                let members = Arc::new(members);
+                *self.member_list.lock() = Some(MemberList {
+                    chat_id: chat_id.to_owned(),
+                    query,
+                    members: members.clone(),
+                });
+                members
+            }
+        };
+
+        let total = members.len();
+        let mut page = Vec::with_capacity(limit);
+
+        // Only download avatars for the requested page
+        for member in members.iter().skip(offset).take(limit) {
+            let power_level = member.power_level();
+            let role = match power_level {
+                100.. => MemberRole::Admin,
+                50.. => MemberRole::Moderator,
+                _ => MemberRole::Member,
+            };
+
+            page.push(Member {
+                profile: UserProfile {
+                    id: member.user_id().to_string(),
+                    display_name: member.display_name().map(ToString::to_string),
+                    avatar: member
+                        .avatar(MediaFormat::File)
+                        .await?
+                        .map(Arc::<[u8]>::from),
+                },
+                role,
+                power_level: Some(power_level),
+            });
+        }
+
+        Ok(MemberPage {
+            members: page,
+            total: Some(total),
+            has_more: offset + limit < total,
+        })
+    }
+
+    /// Get moderation capabilities from the room's power levels
+    async fn permissions(&self, chat_id: &str) -> Result<Permissions> {
+        let room = self.room(chat_id)?;
+        let user_id = self
+            .client
+            .user_id()
+            .ok_or_else(|| anyhow!("Не авторизован"))?;
+        let power_levels = room.power_levels().await?;
+
+        let change_power_levels =
+            power_levels.user_can_send_state(user_id, StateEventType::RoomPowerLevels);
+
+        Ok(Permissions {
+            kick: power_levels.user_can_kick(user_id),
+            ban: power_levels.user_can_ban(user_id),
+            mute: change_power_levels,
+            change_power_levels,
+            power_level: Some(power_levels.for_user(user_id).into()),
+        })
+    }
+
+    /// Kick a user from a room
+    async fn kick_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()> {
+        let room = self.room(chat_id)?;
+        room.kick_user(&UserId::parse(user_id)?, reason).await?;
+        Ok(())
+    }
+
+    /// Ban a user from a room
+    async fn ban_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()> {
+        let room = self.room(chat_id)?;
+        room.ban_user(&UserId::parse(user_id)?, reason).await?;
+        Ok(())
+    }
+
+    /// Lift a ban
+    async fn unban_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()> {
+        let room = self.room(chat_id)?;
+        room.unban_user(&UserId::parse(user_id)?, reason).await?;
+        Ok(())
+    }
+
+    /// Mute by dropping the user's power level below the one needed to send messages
+    async fn mute_user(&self, chat_id: &str, user_id: &str, muted: bool) -> Result<()> {
+        let room = self.room(chat_id)?;
+        let user_id = UserId::parse(user_id)?;
+        let power_levels = room.power_levels().await?;
+
+        let level = if muted {
+            power_levels.events_default - Int::from(1)
+        } else {
+            power_levels.users_default
+        };
+
+        room.update_power_levels(vec![(&user_id, level)]).await?;
+        Ok(())
+    }
+
+    /// Change a user's power level
+    async fn set_power_level(&self, chat_id: &str, user_id: &str, level: i64) -> Result<()> {
+        let room = self.room(chat_id)?;
+        let user_id = UserId::parse(user_id)?;
+        let level = Int::new(level).ok_or_else(|| anyhow!("Недопустимый уровень: {}", level))?;
+
+        room.update_power_levels(vec![(&user_id, level)]).await?;
+        Ok(())
+    }
+
+    /// Get rooms we have been invited to, with the inviter's profile
+    async fn invites(&self) -> Result<Vec<Invite>> {
+        let rooms = self.client.invited_rooms();
+        let mut invites = Vec::with_capacity(rooms.len());
 
         for room in rooms {
-            // Get room avatar if available
             let avatar = room.avatar(MediaFormat::File).await?.map(Arc::<[u8]>::from);
 
-            chats.push(Chat {
-                id: room.room_id().to_string(),
-                name: room.name(),
+            let inviter = match room.invite_details().await?.inviter {
+                Some(member) => Some(UserProfile {
+                    id: member.user_id().to_string(),
+                    display_name: member.display_name().map(ToString::to_string),
+                    avatar: member
+                        .avatar(MediaFormat::File)
+                        .await?
+                        .map(Arc::<[u8]>::from),
+                }),
+                None => None,
+            };
+
+            invites.push(Invite {
+                chat: RoomPreview {
+                    id: room.room_id().to_string(),
+                    alias: room.canonical_alias().map(|a| a.to_string()),
+                    name: room.name(),
+                    topic: room.topic(),
+                    avatar,
+                    member_count: room.joined_members_count(),
+                    is_space: room.is_space(),
+                    joined: false,
+                    via: Vec::new(),
+                },
+                inviter,
+            });
+        }
+
+        Ok(invites)
+    }
+
+    /// Accept an invite by joining the room
+    async fn accept_invite(&self, chat_id: &str) -> Result<()> {
+        let room = self.room(chat_id)?;
+
+        room.join().await?;
+        Ok(())
+    }
+
+    /// Decline an invite, optionally ignoring the user who sent it
+    async fn decline_invite(&self, chat_id: &str, block_inviter: bool) -> Result<()> {
+        let room = self.room(chat_id)?;
+
+        let inviter = room.invite_details().await?.inviter;
+        room.leave().await?;
+
+        if block_inviter && let Some(inviter) = inviter {
+            self.client.account().ignore_user(inviter.user_id()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Search the homeserver's user directory
+    async fn search_users(&self, query: &str) -> Result<Vec<UserProfile>> {
+        let response = self.client.search_users(query, USER_SEARCH_LIMIT).await?;
+        let mut users = Vec::with_capacity(response.results.len());
+
+        for user in response.results {
+            let avatar = match &user.avatar_url {
+                Some(uri) => self.fetch_mxc(uri).await.ok(),
+                None => None,
+            };
+
+            users.push(UserProfile {
+                id: user.user_id.to_string(),
+                display_name: user.display_name,
                 avatar,
             });
         }
 
-        Ok(chats)
+        Ok(users)
+    }
+
+    /// Ignored users with their profiles, as far as they can still be fetched
+    async fn ignored_users(&self) -> Result<Vec<UserProfile>> {
+        let mut users = Vec::new();
+
+        for user_id in self.ignored_user_ids().await? {
+            let display_name = match self.client.account().fetch_user_profile_of(&user_id).await {
+                Ok(profile) => profile.displayname,
+                Err(e) => {
+                    log::warn!("Failed to fetch profile of {}: {}", user_id, e);
+                    None
+                }
+            };
+
+            users.push(UserProfile {
+                id: user_id.to_string(),
+                display_name,
+                avatar: None,
+            });
+        }
+
+        Ok(users)
+    }
+
+    /// Add the user to `m.ignored_user_list`; the server stops sending their events
+    async fn ignore_user(&self, user_id: &str) -> Result<()> {
+        let user_id = UserId::parse(user_id)?;
+        self.client.account().ignore_user(&user_id).await?;
+        Ok(())
+    }
+
+    async fn unignore_user(&self, user_id: &str) -> Result<()> {
+        let user_id = UserId::parse(user_id)?;
+        self.client.account().unignore_user(&user_id).await?;
+        Ok(())
+    }
+
+    /// Search the public room directory of the given (or our own) homeserver
+    async fn search_directory(
+        &self,
+        query: &str,
+        server: Option<&str>,
+        since: Option<&str>,
+    ) -> Result<DirectoryPage> {
+        let mut filter = Filter::new();
+        if !query.trim().is_empty() {
+            filter.generic_search_term = Some(query.trim().to_owned());
+        }
+
+        let mut request = get_public_rooms_filtered::v3::Request::new();
+        request.filter = filter;
+        request.limit = Some(UInt::from(DIRECTORY_PAGE_SIZE));
+        request.since = since.map(ToOwned::to_owned);
+        request.server = server
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| OwnedServerName::try_from(s.trim()))
+            .transpose()?;
+
+        let via: Vec<String> = request.server.iter().map(ToString::to_string).collect();
+        let response = self.client.public_rooms_filtered(request).await?;
+
+        let mut rooms = Vec::with_capacity(response.chunk.len());
+        for chunk in response.chunk {
+            let avatar = match &chunk.avatar_url {
+                Some(uri) => self.fetch_mxc(uri).await.ok(),
+                None => None,
+            };
+            let joined = self
+                .client
+                .get_room(&chunk.room_id)
+                .is_some_and(|room| room.state() == RoomState::Joined);
+
+            rooms.push(RoomPreview {
+                id: chunk.room_id.to_string(),
+                alias: chunk.canonical_alias.map(|a| a.to_string()),
+                name: chunk.name,
+                topic: chunk.topic,
+                avatar,
+                member_count: chunk.num_joined_members.into(),
+                is_space: chunk.room_type == Some(RoomType::Space),
+                joined,
+                via: via.clone(),
+            });
+        }
+
+        Ok(DirectoryPage {
+            rooms,
+            next_batch: response.next_batch,
+            total_estimate: response.total_room_count_estimate.map(Into::into),
+        })
+    }
+
+    /// Keywords from the account's content push rules
+    async fn keywords(&self) -> Result<Vec<String>> {
+        let settings = self.client.notification_settings().await;
+        Ok(settings.enabled_keywords().await.into_iter().collect())
+    }
+
+    async fn add_keyword(&self, keyword: &str) -> Result<()> {
+        let settings = self.client.notification_settings().await;
+        settings.add_keyword(keyword.to_owned()).await?;
+        Ok(())
+    }
+
+    async fn remove_keyword(&self, keyword: &str) -> Result<()> {
+        let settings = self.client.notification_settings().await;
+        settings.remove_keyword(keyword).await?;
+        Ok(())
+    }
+
+    /// Room-specific push rules set by the user, if any
+    async fn chat_notify_mode(&self, chat_id: &str) -> Result<Option<NotifyMode>> {
+        let room_id = RoomId::parse(chat_id)?;
+        let settings = self.client.notification_settings().await;
+        Ok(settings
+            .get_user_defined_room_notification_mode(&room_id)
+            .await
+            .map(notify_mode))
+    }
+
+    /// Write room push rules, or remove them to fall back to the defaults
+    async fn set_chat_notify_mode(&self, chat_id: &str, mode: Option<NotifyMode>) -> Result<()> {
+        let room_id = RoomId::parse(chat_id)?;
+        let settings = self.client.notification_settings().await;
+
+        match mode {
+            Some(mode) => {
+                let mode = match mode {
+                    NotifyMode::AllMessages => RoomNotificationMode::AllMessages,
+                    NotifyMode::MentionsOnly => RoomNotificationMode::MentionsAndKeywordsOnly,
+                    NotifyMode::Mute => RoomNotificationMode::Mute,
+                };
+                settings.set_room_notification_mode(&room_id, mode).await?;
+            }
+            None => settings.delete_user_defined_room_rules(&room_id).await?,
+        }
+        Ok(())
+    }
+
+    /// List the account's devices with their verification status
+    async fn sessions(&self) -> Result<Vec<Session>> {
+        let user_id = self
+            .client
+            .user_id()
+            .ok_or_else(|| anyhow!("Не авторизован"))?;
+        let devices = self.client.devices().await?.devices;
+        let crypto_devices = self.client.encryption().get_user_devices(user_id).await?;
+        let current = self.client.device_id();
+
+        Ok(devices
+            .into_iter()
+            .map(|device| Session {
+                name: device
+                    .display_name
+                    .unwrap_or_else(|| device.device_id.to_string()),
+                last_seen: device.last_seen_ts.map(|ts| ts.as_secs().into()),
+                last_ip: device.last_seen_ip,
+                current: current == Some(&*device.device_id),
+                verified: Some(
+                    crypto_devices
+                        .get(&device.device_id)
+                        .is_some_and(|device| device.is_verified()),
+                ),
+                id: device.device_id.to_string(),
+            })
+            .collect())
+    }
+
+    async fn rename_session(&self, session_id: &str, name: &str) -> Result<()> {
+        let device_id: OwnedDeviceId = session_id.into();
+        self.client.rename_device(&device_id, name).await?;
+        Ok(())
+    }
+
+    /// Delete other devices, authenticating with the password if the server asks
+    async fn sign_out_sessions(
+        &self,
+        session_ids: &[String],
+        password: Option<&str>,
+    ) -> Result<()> {
+        let devices: Vec<OwnedDeviceId> = session_ids.iter().map(|id| id.as_str().into()).collect();
+
+        let Err(e) = self.client.delete_devices(&devices, None).await else {
+            return Ok(());
+        };
+        let Some(info) = e.as_uiaa_response() else {
+            return Err(e.into());
+        };
+        let Some(password) = password else {
+            return Err(PasswordRequired.into());
+        };
+
+        let user_id = self
+            .client
+            .user_id()
+            .ok_or_else(|| anyhow!("Не авторизован"))?;
+        let mut auth = uiaa::Password::new(
+            UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+            password.to_owned(),
+        );
+        auth.session = info.session.clone();
+
+        self.client
+            .delete_devices(&devices, Some(AuthData::Password(auth)))
+            .await?;
+        Ok(())
+    }
+
+    /// Log out on the server and remove the local store
+    async fn logout(&self) -> Result<()> {
+        self.client.matrix_auth().logout().await?;
+
+        // The web store is reused by the next login, as IndexedDB can't be
+        // deleted from here
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Err(e) = std::fs::remove_dir_all(&self.client_session.db_path) {
+            log::warn!("Failed to delete the local store: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Not implemented: matrix-sdk 0.10 has no way to grant a login to another device
+    async fn approve_login(&self, _code: &str, _progress: Arc<Mutex<LoginApproval>>) -> Result<()> {
+        Err(anyhow!(
+            "Подтверждение входа с другого устройства не реализовано"
+        ))
+    }
+
+    fn approves_logins(&self) -> bool {
+        false
     }
 
     /// Get current user ID
@@ -499,7 +2497,7 @@ impl LoginForm for Login {
     fn show(
         &mut self,
         clients: &Arc<Mutex<Vec<Arc<dyn Client>>>>,
-        chats: &Arc<Mutex<Vec<Chat>>>,
+        _chats: &Arc<Mutex<Vec<Chat>>>,
         rt: &mut Runtime,
         frame: &mut eframe::Frame,
         ui: &mut egui::Ui,
@@ -561,29 +2559,8 @@ impl LoginForm for Login {
                         // Login successful, clear error message
                         self.error_message = None;
 
-                        // Add client to the clients list
-                        clients.lock().push(client.clone());
-
-                        // Clone for async tasks
-                        let client_clone = client.clone();
-                        let chats_clone = chats.clone();
-
-                        // Spawn an async task to handle sync and chat loading
-                        rt.spawn(async move {
-                            if let Err(e) = client_clone.sync().await {
-                                log::error!("Failed to sync with server: {}", e);
-                                return;
-                            }
-
-                            match client_clone.chats().await {
-                                Ok(client_chats) => {
-                                    *chats_clone.lock() = client_chats;
-                                }
-                                Err(e) => {
-                                    log::error!("Failed to fetch chats: {}", e);
-                                }
-                            }
-                        });
+                        // The app's sync loop loads the new client's chats
+                        clients.lock().push(client);
 
                         return Ok(());
                     }
@@ -598,3 +2575,61 @@ impl LoginForm for Login {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(json: serde_json::Value) -> Raw<serde_json::Value> {
+        Raw::from_json_string(json.to_string()).unwrap()
+    }
+
+    #[test]
+    fn thread_root_of_reads_thread_relations_only() {
+        let reply = raw(serde_json::json!({
+            "content": {
+                "body": "reply",
+                "m.relates_to": { "rel_type": "m.thread", "event_id": "$root" },
+            },
+        }));
+        assert_eq!(thread_root_of(&reply).as_deref(), Some("$root"));
+
+        let edit = raw(serde_json::json!({
+            "content": {
+                "m.relates_to": { "rel_type": "m.replace", "event_id": "$original" },
+            },
+        }));
+        assert_eq!(thread_root_of(&edit), None);
+
+        let plain = raw(serde_json::json!({ "content": { "body": "hello" } }));
+        assert_eq!(thread_root_of(&plain), None);
+    }
+
+    #[test]
+    fn thread_summary_reads_bundled_relation() {
+        let root = raw(serde_json::json!({
+            "content": { "body": "root" },
+            "unsigned": {
+                "m.relations": {
+                    "m.thread": {
+                        "count": 3,
+                        "latest_event": { "content": { "body": "latest" } },
+                    },
+                },
+            },
+        }));
+        let summary = thread_summary(&root).unwrap();
+        assert_eq!(summary.reply_count, 3);
+        assert_eq!(summary.latest_reply.as_deref(), Some("latest"));
+
+        let without_latest = raw(serde_json::json!({
+            "unsigned": { "m.relations": { "m.thread": { "count": 1 } } },
+        }));
+        let summary = thread_summary(&without_latest).unwrap();
+        assert_eq!(summary.reply_count, 1);
+        assert_eq!(summary.latest_reply, None);
+
+        let plain = raw(serde_json::json!({ "content": { "body": "hello" } }));
+        assert!(thread_summary(&plain).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -6,7 +6,10 @@ use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
+use rich_text::RichText;
+
 pub mod matrix;
+pub mod rich_text;
 pub mod telegram;
 
 pub trait LoginForm: Send + Sync {
@@ -20,28 +23,385 @@ pub trait LoginForm: Send + Sync {
     ) -> Result<()>;
 }
 
+/// Display names of other users currently typing, by chat ID
+pub type TypingUsers = Arc<Mutex<HashMap<String, Vec<String>>>>;
+
+/// Downloaded chat photos, by chat ID
+pub type ChatAvatars = Arc<Mutex<HashMap<String, Arc<[u8]>>>>;
+
 #[async_trait]
 pub trait Client: Send + Sync {
     fn client_name(&self) -> &str;
 
-    async fn sync(&self) -> Result<()>;
+    /// Fetch new events, returning whether the chat list may have changed
+    async fn sync(&self) -> Result<bool>;
     fn save(&self, storage: &mut dyn eframe::Storage, key: &str) -> Result<()>;
 
     async fn chats(&self) -> Result<Vec<Chat>>;
+    async fn spaces(&self) -> Result<Vec<Space>>;
+    async fn space_children(&self, space_id: &str) -> Result<Vec<RoomPreview>>;
+    async fn join_chat(&self, target: &str, via: &[String]) -> Result<String>;
+    async fn preview_chat(&self, target: &str) -> Result<RoomPreview>;
+    async fn create_chat(&self, options: &NewChat) -> Result<String>;
+    async fn create_direct_chat(&self, user: &str) -> Result<String>;
+    async fn invite_user(&self, chat_id: &str, user_id: &str) -> Result<()>;
+    async fn search_users(&self, query: &str) -> Result<Vec<UserProfile>>;
+
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings>;
+    async fn set_chat_name(&self, chat_id: &str, name: &str) -> Result<()>;
+    async fn set_chat_topic(&self, chat_id: &str, topic: &str) -> Result<()>;
+    async fn set_chat_avatar(&self, chat_id: &str, image: Vec<u8>) -> Result<()>;
+    async fn set_join_rule(&self, chat_id: &str, rule: JoinRule) -> Result<()>;
+    async fn set_history_visibility(
+        &self,
+        chat_id: &str,
+        visibility: HistoryVisibility,
+    ) -> Result<()>;
+    async fn enable_encryption(&self, chat_id: &str) -> Result<()>;
+
+    /// A page of members whose name or ID contains `query`, which may be empty
+    async fn members(
+        &self,
+        chat_id: &str,
+        query: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<MemberPage>;
+    async fn permissions(&self, chat_id: &str) -> Result<Permissions>;
+
+    async fn kick_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()>;
+    async fn ban_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()>;
+    async fn unban_user(&self, chat_id: &str, user_id: &str, reason: Option<&str>) -> Result<()>;
+    async fn mute_user(&self, chat_id: &str, user_id: &str, muted: bool) -> Result<()>;
+    async fn set_power_level(&self, chat_id: &str, user_id: &str, level: i64) -> Result<()>;
+
+    async fn invites(&self) -> Result<Vec<Invite>>;
+    async fn accept_invite(&self, chat_id: &str) -> Result<()>;
+    async fn decline_invite(&self, chat_id: &str, block_inviter: bool) -> Result<()>;
+    async fn search_directory(
+        &self,
+        query: &str,
+        server: Option<&str>,
+        since: Option<&str>,
+    ) -> Result<DirectoryPage>;
     async fn select_chat(&self, chat_id: &str) -> Result<()>;
     async fn load_more_events(&self) -> Result<()>;
     fn event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>>;
 
+    /// A page of the chat's threads, newest first, continuing from `from`
+    async fn threads(&self, chat_id: &str, from: Option<&str>) -> Result<ThreadPage>;
+    async fn open_thread(&self, root_id: &str) -> Result<()>;
+    /// Load older replies of the open thread; `false` once the start was reached
+    async fn load_more_thread_events(&self) -> Result<bool>;
+    fn thread_event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>>;
+    async fn send_thread_reply(&self, root_id: &str, text: &str) -> Result<()>;
+
+    async fn send_message(&self, chat_id: &str, text: &str) -> Result<()>;
     async fn delete_event(&self, message_id: &str) -> Result<()>;
 
+    async fn set_typing(&self, chat_id: &str, typing: bool) -> Result<()>;
+    fn typing(&self) -> Result<TypingUsers>;
+
+    /// Last known presence of other users, by user ID
+    fn presence(&self) -> Result<Arc<Mutex<HashMap<String, PresenceInfo>>>>;
+    /// Chat photos that arrived after the chats were listed, by chat ID
+    fn chat_avatars(&self) -> Result<ChatAvatars>;
+    /// Whether others may see when we are online, changing the account's settings
+    async fn share_presence(&self, share: bool) -> Result<()>;
+    /// Appear online or offline for this session, leaving the settings alone
+    async fn set_online(&self, online: bool) -> Result<()>;
+
+    /// Mark the chat as read up to `event_id`, or entirely when `None`
+    async fn mark_read(&self, chat_id: &str, event_id: Option<&str>) -> Result<()>;
+    async fn set_unread(&self, chat_id: &str, unread: bool) -> Result<()>;
+    /// Move the chat to favourites or low priority, or back with `None`
+    async fn set_chat_tag(&self, chat_id: &str, tag: Option<ChatTag>) -> Result<()>;
+    /// ID of the last event the user has read in the chat
+    async fn read_marker(&self, chat_id: &str) -> Result<Option<String>>;
+
+    /// Users whose messages are hidden, `m.ignored_user_list` or Telegram's block list
+    async fn ignored_users(&self) -> Result<Vec<UserProfile>>;
+    async fn ignore_user(&self, user_id: &str) -> Result<()>;
+    async fn unignore_user(&self, user_id: &str) -> Result<()>;
+
+    /// Words that notify like a mention
+    async fn keywords(&self) -> Result<Vec<String>>;
+    async fn add_keyword(&self, keyword: &str) -> Result<()>;
+    async fn remove_keyword(&self, keyword: &str) -> Result<()>;
+    /// Notification override for a chat; `None` follows the account's defaults
+    async fn chat_notify_mode(&self, chat_id: &str) -> Result<Option<NotifyMode>>;
+    async fn set_chat_notify_mode(&self, chat_id: &str, mode: Option<NotifyMode>) -> Result<()>;
+
+    /// Devices or authorizations logged in to the account, including this one
+    async fn sessions(&self) -> Result<Vec<Session>>;
+    async fn rename_session(&self, session_id: &str, name: &str) -> Result<()>;
+    /// Sign out other sessions; `password` confirms the request when the server asks for it
+    async fn sign_out_sessions(&self, session_ids: &[String], password: Option<&str>)
+    -> Result<()>;
+    /// Invalidate this session on the server and delete its local data
+    async fn logout(&self) -> Result<()>;
+    /// Let a new device sign in to this account using the code it shows,
+    /// reporting each step through `progress`
+    async fn approve_login(&self, code: &str, progress: Arc<Mutex<LoginApproval>>) -> Result<()>;
+    /// Whether `approve_login` is available for this account
+    fn approves_logins(&self) -> bool;
+
     fn self_id(&self) -> Arc<String>;
 }
 
+/// Name this app registers new sessions under
+pub fn device_name() -> String {
+    if cfg!(target_arch = "wasm32") {
+        "EChat Web".to_owned()
+    } else {
+        format!("EChat on {}", std::env::consts::OS)
+    }
+}
+
+/// Returned by `sign_out_sessions` when the server wants the account password
+#[derive(Debug)]
+pub struct PasswordRequired;
+
+impl std::fmt::Display for PasswordRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password required")
+    }
+}
+
+impl std::error::Error for PasswordRequired {}
+
+/// Steps of letting another device sign in from this session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LoginApproval {
+    #[default]
+    Connecting,
+    Done,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chat {
     pub id: String,
     pub name: Option<String>,
     pub avatar: Option<Arc<[u8]>>,
+    #[serde(default)]
+    pub unread: bool,
+    /// The other user, for direct chats
+    #[serde(default)]
+    pub direct_user: Option<String>,
+    /// Unread messages mentioning the current user
+    #[serde(default)]
+    pub mentions: u64,
+    /// Chat that replaced this one, as a target for `join_chat`
+    #[serde(default)]
+    pub replaced_by: Option<String>,
+    #[serde(default)]
+    pub kind: ChatKind,
+    #[serde(default)]
+    pub tag: Option<ChatTag>,
+    /// Forum topics, listed under the chat and selected like chats
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+}
+
+impl Chat {
+    /// Find one of the chat's topics by its chat ID
+    pub fn topic(&self, topic_id: &str) -> Option<&Topic> {
+        self.topics.iter().find(|topic| topic.id == topic_id)
+    }
+}
+
+/// A topic of a forum group, with its own timeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Topic {
+    /// ID to select the topic with, distinct from the group's
+    pub id: String,
+    pub name: String,
+    /// RGB color of the topic icon
+    pub icon_color: Option<u32>,
+    /// Unread messages in the topic
+    #[serde(default)]
+    pub unread: u64,
+}
+
+/// Who is on the other side of a chat
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatKind {
+    /// One-to-one chat with another person
+    Direct,
+    #[default]
+    Group,
+    /// Broadcast chat where only admins post
+    Channel,
+    Bot,
+}
+
+/// User-assigned priority of a chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatTag {
+    Favourite,
+    LowPriority,
+}
+
+/// A space (or folder) grouping chats, with nested sub-spaces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Space {
+    pub id: String,
+    pub name: Option<String>,
+    pub avatar: Option<Arc<[u8]>>,
+    pub chats: Vec<String>,
+    pub subspaces: Vec<Space>,
+}
+
+impl Space {
+    /// Find a space by ID in this subtree
+    pub fn find(&self, space_id: &str) -> Option<&Space> {
+        if self.id == space_id {
+            return Some(self);
+        }
+        self.subspaces.iter().find_map(|space| space.find(space_id))
+    }
+}
+
+/// Public information about a chat that may not be joined yet
+#[derive(Debug, Clone)]
+pub struct RoomPreview {
+    pub id: String,
+    pub alias: Option<String>,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub avatar: Option<Arc<[u8]>>,
+    pub member_count: u64,
+    pub is_space: bool,
+    pub joined: bool,
+    pub via: Vec<String>,
+}
+
+/// Options for creating a new group chat
+#[derive(Debug, Clone, Default)]
+pub struct NewChat {
+    pub name: String,
+    pub topic: String,
+    pub alias: String,
+    pub encrypted: bool,
+    pub public: bool,
+}
+
+/// Public profile of a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub avatar: Option<Arc<[u8]>>,
+}
+
+/// Who may join a chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinRule {
+    Public,
+    Knock,
+    Invite,
+}
+
+/// Who may read a chat's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryVisibility {
+    WorldReadable,
+    Shared,
+    Invited,
+    Joined,
+}
+
+/// Editable chat metadata; `None` marks settings the network doesn't have
+#[derive(Debug, Clone, Default)]
+pub struct ChatSettings {
+    pub name: String,
+    pub topic: String,
+    pub avatar: Option<Arc<[u8]>>,
+    pub join_rule: Option<JoinRule>,
+    pub history_visibility: Option<HistoryVisibility>,
+    pub encrypted: Option<bool>,
+}
+
+/// Role of a member inside a chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum MemberRole {
+    Owner,
+    Admin,
+    Moderator,
+    #[default]
+    Member,
+}
+
+/// A member of a chat
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub profile: UserProfile,
+    pub role: MemberRole,
+    pub power_level: Option<i64>,
+}
+
+/// One page of chat members, sorted by role
+#[derive(Debug, Clone, Default)]
+pub struct MemberPage {
+    pub members: Vec<Member>,
+    pub total: Option<usize>,
+    pub has_more: bool,
+}
+
+/// Moderation capabilities of the current user in a chat
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Permissions {
+    pub kick: bool,
+    pub ban: bool,
+    pub mute: bool,
+    pub change_power_levels: bool,
+    pub power_level: Option<i64>,
+}
+
+/// Whether a user is around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Presence {
+    Online,
+    Unavailable,
+    #[default]
+    Offline,
+}
+
+/// Presence of a user with the time they were last active
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PresenceInfo {
+    pub presence: Presence,
+    /// Unix timestamp in seconds, when known
+    pub last_seen: Option<u64>,
+}
+
+/// A device or authorization logged in to an account
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    /// Unix timestamp in seconds, when known
+    pub last_seen: Option<u64>,
+    pub last_ip: Option<String>,
+    /// Whether this is the session the app is using
+    pub current: bool,
+    /// `None` when the network has no device verification
+    pub verified: Option<bool>,
+}
+
+/// A pending invitation to a chat
+#[derive(Debug, Clone)]
+pub struct Invite {
+    pub chat: RoomPreview,
+    pub inviter: Option<UserProfile>,
+}
+
+/// One page of public chat directory results
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryPage {
+    pub rooms: Vec<RoomPreview>,
+    pub next_batch: Option<String>,
+    pub total_estimate: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +411,9 @@ pub struct EventGroup {
     pub avatar: Option<Arc<[u8]>>,
     pub events: Vec<Event>,
     pub from_self: bool,
+    /// Sender's power level in the chat, on networks that have them
+    #[serde(default)]
+    pub power_level: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,10 +421,135 @@ pub struct Event {
     pub id: String,
     pub timestamp: u64,
     pub kind: EventKind,
+    /// Set when the event is the root of a thread
+    #[serde(default)]
+    pub thread: Option<ThreadSummary>,
+    /// Users whose latest read receipt is on this event
+    #[serde(default)]
+    pub read_by: Vec<UserProfile>,
+    /// Formatting of a message's text, when it has any
+    #[serde(default)]
+    pub formatted: Option<RichText>,
+    /// IDs of the users the message mentions
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    /// Whether the message mentions everyone in the chat
+    #[serde(default)]
+    pub mentions_everyone: bool,
+    #[serde(default)]
+    pub notification: Notification,
+    /// Set for posts in a broadcast channel
+    #[serde(default)]
+    pub post: Option<PostInfo>,
+}
+
+/// Details shown under a broadcast channel post instead of a sender
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PostInfo {
+    /// Name of the admin who wrote the post, when the channel signs posts
+    pub signature: Option<String>,
+    pub views: Option<u64>,
+}
+
+/// How an event should notify, as decided by the account's rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub notify: bool,
+    /// Highlighted like a mention, e.g. for a keyword
+    pub highlight: bool,
+    pub sound: bool,
+}
+
+/// Which messages of a chat notify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyMode {
+    AllMessages,
+    MentionsOnly,
+    Mute,
+}
+
+/// Replies to a thread root, shown under it in the main timeline
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    pub reply_count: u64,
+    pub latest_reply: Option<String>,
+}
+
+/// A thread listed in a chat's thread overview
+#[derive(Debug, Clone)]
+pub struct ThreadRoot {
+    pub sender: String,
+    pub root: Event,
+}
+
+/// One page of a chat's threads
+#[derive(Debug, Clone, Default)]
+pub struct ThreadPage {
+    pub threads: Vec<ThreadRoot>,
+    pub next_batch: Option<String>,
+}
+
+impl EventGroup {
+    /// Whether the group holds system events rather than messages
+    pub fn is_system(&self) -> bool {
+        self.events.first().is_some_and(Event::is_system)
+    }
+}
+
+impl Event {
+    /// Whether the message mentions the given user, directly, as part of everyone or
+    /// through a keyword that push rules highlight
+    pub fn mentions_user(&self, user_id: &str) -> bool {
+        self.notification.highlight
+            || self.mentions_everyone
+            || self.mentions.iter().any(|id| id == user_id)
+    }
+
+    /// Whether the event is a chat or membership change rather than a message
+    pub fn is_system(&self) -> bool {
+        matches!(self.kind, EventKind::System(_))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EventKind {
     Message(String),
+    /// A change to the chat or its members, made by the group's user
+    System(SystemEvent),
     // Другие типы событий можно добавить здесь
 }
+
+/// Chat and membership changes shown as compact lines in the timeline.
+/// Targets are display names of the affected user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemEvent {
+    Joined,
+    Left,
+    Invited(String),
+    Added(String),
+    Removed(String),
+    Banned(String),
+    ProfileChanged,
+    Created,
+    Renamed(String),
+    TopicChanged(String),
+    AvatarChanged,
+    Pinned,
+    /// The chat was replaced by a new one
+    Upgraded,
+}
+
+impl SystemEvent {
+    /// Whether the event changes who is in the chat
+    pub fn is_membership(&self) -> bool {
+        matches!(
+            self,
+            Self::Joined
+                | Self::Left
+                | Self::Invited(_)
+                | Self::Added(_)
+                | Self::Removed(_)
+                | Self::Banned(_)
+        )
+    }
+}
@@ -0,0 +1,593 @@
+use serde::{Deserialize, Serialize};
+
+/// Formatted message body shared by all networks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RichText {
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Block {
+    Paragraph(Vec<Span>),
+    Code {
+        language: Option<String>,
+        code: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        ordered: bool,
+        start: u64,
+        items: Vec<Vec<Block>>,
+    },
+}
+
+/// A run of text sharing one style
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpanStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    pub spoiler: bool,
+    pub link: Option<String>,
+    /// ID of the user this span mentions
+    #[serde(default)]
+    pub mention: Option<String>,
+}
+
+impl RichText {
+    /// Parse the HTML subset allowed in Matrix `formatted_body`.
+    /// Unknown tags are dropped but their text is kept.
+    pub fn from_html(html: &str) -> Self {
+        let mut parser = HtmlParser {
+            tokens: tokenize(html),
+            pos: 0,
+            styles: Vec::new(),
+        };
+        Self {
+            blocks: parser.blocks(None),
+        }
+    }
+
+    /// IDs of all users mentioned in the text
+    pub fn mentions(&self) -> Vec<String> {
+        fn collect(blocks: &[Block], mentions: &mut Vec<String>) {
+            for block in blocks {
+                match block {
+                    Block::Paragraph(spans) => {
+                        mentions.extend(spans.iter().filter_map(|span| span.style.mention.clone()))
+                    }
+                    Block::Quote(blocks) => collect(blocks, mentions),
+                    Block::List { items, .. } => {
+                        items.iter().for_each(|item| collect(item, mentions))
+                    }
+                    Block::Code { .. } => {}
+                }
+            }
+        }
+
+        let mut mentions = Vec::new();
+        collect(&self.blocks, &mut mentions);
+        mentions.dedup();
+        mentions
+    }
+
+    /// Whether every block is a paragraph of unstyled text
+    pub fn is_plain(&self) -> bool {
+        self.blocks.iter().all(|block| match block {
+            Block::Paragraph(spans) => spans.iter().all(|span| span.style == SpanStyle::default()),
+            _ => false,
+        })
+    }
+}
+
+/// Whether a link may be opened: only web pages and mail addresses, never
+/// local files or other schemes a remote message could point at
+pub fn is_safe_link(url: &str) -> bool {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https" | "mailto"))
+}
+
+enum Token {
+    Text(String),
+    Open {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    Close(String),
+}
+
+impl Token {
+    fn attr(&self, key: &str) -> Option<&str> {
+        match self {
+            Token::Open { attrs, .. } => attrs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Split HTML into text and tags, decoding entities
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Token::Text(decode_entities(rest)));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..start])));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            // An unterminated tag is just text
+            tokens.push(Token::Text(decode_entities(rest)));
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::Close(name.trim().to_ascii_lowercase()));
+        } else {
+            let tag = tag.trim_end_matches('/');
+            let (name, attrs) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            tokens.push(Token::Open {
+                name: name.to_ascii_lowercase(),
+                attrs: parse_attrs(attrs),
+            });
+        }
+    }
+
+    tokens
+}
+
+/// Parse `key="value"` pairs of a tag; bare keys get an empty value
+fn parse_attrs(mut rest: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, remaining) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        (&value[..end], value.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        (&value[..end], &value[end..])
+                    }
+                };
+                rest = remaining;
+                decode_entities(value)
+            }
+            None => String::new(),
+        };
+        attrs.push((key, value));
+    }
+
+    attrs
+}
+
+/// Replace named and numeric character references
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+struct HtmlParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Open inline tags with the style inside them
+    styles: Vec<(String, SpanStyle)>,
+}
+
+impl HtmlParser {
+    fn style(&self) -> SpanStyle {
+        self.styles
+            .last()
+            .map(|(_, style)| style.clone())
+            .unwrap_or_default()
+    }
+
+    /// Parse blocks until the closing `end` tag or the end of input
+    fn blocks(&mut self, end: Option<&str>) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut spans = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            let token = &self.tokens[self.pos];
+            self.pos += 1;
+
+            match token {
+                Token::Text(text) => {
+                    let text = collapse_whitespace(text);
+                    if !text.is_empty() {
+                        spans.push(Span {
+                            text,
+                            style: self.style(),
+                        });
+                    }
+                }
+                Token::Close(name) if Some(name.as_str()) == end => break,
+                Token::Close(name) => {
+                    if matches!(
+                        name.as_str(),
+                        "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+                    ) {
+                        flush_paragraph(&mut blocks, &mut spans);
+                    }
+                    if let Some(idx) = self.styles.iter().rposition(|(tag, _)| tag == name) {
+                        self.styles.truncate(idx);
+                    }
+                }
+                Token::Open { name, .. } => {
+                    let name = name.clone();
+                    match name.as_str() {
+                        "br" => spans.push(Span {
+                            text: "\n".to_owned(),
+                            style: self.style(),
+                        }),
+                        "p" | "div" | "hr" => flush_paragraph(&mut blocks, &mut spans),
+                        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                            flush_paragraph(&mut blocks, &mut spans);
+                            self.push_style(&name, |style| style.bold = true);
+                        }
+                        "blockquote" => {
+                            flush_paragraph(&mut blocks, &mut spans);
+                            blocks.push(Block::Quote(self.blocks(Some("blockquote"))));
+                        }
+                        "ul" | "ol" => {
+                            flush_paragraph(&mut blocks, &mut spans);
+                            let start = token.attr("start").and_then(|s| s.parse().ok());
+                            blocks.push(self.list(&name, start.unwrap_or(1)));
+                        }
+                        "pre" => {
+                            flush_paragraph(&mut blocks, &mut spans);
+                            blocks.push(self.code_block());
+                        }
+                        "mx-reply" => self.skip_until("mx-reply"),
+                        "a" => {
+                            let href = token.attr("href");
+                            let mention = href.and_then(pill_user);
+                            let link = href
+                                .filter(|href| is_safe_link(href))
+                                .map(ToOwned::to_owned);
+                            self.push_style(&name, |style| match mention {
+                                Some(user_id) => style.mention = Some(user_id),
+                                None => style.link = link,
+                            });
+                        }
+                        "b" | "strong" => self.push_style(&name, |style| style.bold = true),
+                        "i" | "em" => self.push_style(&name, |style| style.italic = true),
+                        "u" => self.push_style(&name, |style| style.underline = true),
+                        "s" | "del" | "strike" => {
+                            self.push_style(&name, |style| style.strikethrough = true)
+                        }
+                        "code" => self.push_style(&name, |style| style.code = true),
+                        "span" if token.attr("data-mx-spoiler").is_some() => {
+                            self.push_style(&name, |style| style.spoiler = true)
+                        }
+                        "span" | "font" | "sup" | "sub" => self.push_style(&name, |_| {}),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        flush_paragraph(&mut blocks, &mut spans);
+        blocks
+    }
+
+    fn push_style(&mut self, tag: &str, change: impl FnOnce(&mut SpanStyle)) {
+        let mut style = self.style();
+        change(&mut style);
+        self.styles.push((tag.to_owned(), style));
+    }
+
+    /// Parse `<li>` items until the list's closing tag
+    fn list(&mut self, tag: &str, start: u64) -> Block {
+        let mut items = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            let token = &self.tokens[self.pos];
+            self.pos += 1;
+
+            match token {
+                Token::Close(name) if name == tag => break,
+                Token::Open { name, .. } if name == "li" => items.push(self.blocks(Some("li"))),
+                _ => {}
+            }
+        }
+
+        Block::List {
+            ordered: tag == "ol",
+            start,
+            items,
+        }
+    }
+
+    /// Collect the raw text of a `<pre>` block, keeping its whitespace
+    fn code_block(&mut self) -> Block {
+        let mut language = None;
+        let mut code = String::new();
+
+        while self.pos < self.tokens.len() {
+            let token = &self.tokens[self.pos];
+            self.pos += 1;
+
+            match token {
+                Token::Close(name) if name == "pre" => break,
+                Token::Text(text) => code.push_str(text),
+                Token::Open { name, .. } if name == "code" => {
+                    language = token
+                        .attr("class")
+                        .and_then(|class| class.strip_prefix("language-"))
+                        .map(ToOwned::to_owned);
+                }
+                Token::Open { name, .. } if name == "br" => code.push('\n'),
+                _ => {}
+            }
+        }
+
+        Block::Code {
+            language,
+            code: code.trim_end_matches('\n').to_owned(),
+        }
+    }
+
+    fn skip_until(&mut self, tag: &str) {
+        while self.pos < self.tokens.len() {
+            let token = &self.tokens[self.pos];
+            self.pos += 1;
+            if matches!(token, Token::Close(name) if name == tag) {
+                break;
+            }
+        }
+    }
+}
+
+/// User ID a pill link points to, from a `matrix.to` or `matrix:` URI
+fn pill_user(href: &str) -> Option<String> {
+    let target = match href.strip_prefix("https://matrix.to/#/") {
+        Some(target) => target
+            .split('?')
+            .next()?
+            .replace("%40", "@")
+            .replace("%3A", ":")
+            .replace("%3a", ":"),
+        None => format!("@{}", href.strip_prefix("matrix:u/")?.split('?').next()?),
+    };
+    target.starts_with('@').then_some(target)
+}
+
+/// Collapse runs of whitespace the way browsers do outside `<pre>`
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last_space = false;
+
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !last_space {
+                collapsed.push(' ');
+            }
+            last_space = true;
+        } else {
+            collapsed.push(c);
+            last_space = false;
+        }
+    }
+
+    collapsed
+}
+
+/// End the current paragraph, dropping it if it only holds whitespace
+fn flush_paragraph(blocks: &mut Vec<Block>, spans: &mut Vec<Span>) {
+    let mut paragraph = std::mem::take(spans);
+
+    if let Some(first) = paragraph.first_mut() {
+        first.text = first.text.trim_start_matches(' ').to_owned();
+    }
+    if let Some(last) = paragraph.last_mut() {
+        last.text = last.text.trim_end_matches(' ').to_owned();
+    }
+    paragraph.retain(|span| !span.text.is_empty());
+
+    if paragraph.iter().any(|span| !span.text.trim().is_empty()) {
+        blocks.push(Block::Paragraph(paragraph));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, change: impl FnOnce(&mut SpanStyle)) -> Span {
+        let mut style = SpanStyle::default();
+        change(&mut style);
+        Span {
+            text: text.to_owned(),
+            style,
+        }
+    }
+
+    fn plain(text: &str) -> Span {
+        span(text, |_| {})
+    }
+
+    #[test]
+    fn parses_paragraphs_and_nested_styles() {
+        let text = RichText::from_html("<p>Hello <b>bold <i>both</i></b></p><p>second</p>");
+        assert_eq!(
+            text.blocks,
+            vec![
+                Block::Paragraph(vec![
+                    plain("Hello "),
+                    span("bold ", |s| s.bold = true),
+                    span("both", |s| {
+                        s.bold = true;
+                        s.italic = true;
+                    }),
+                ]),
+                Block::Paragraph(vec![plain("second")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_entities_and_collapses_whitespace() {
+        let text = RichText::from_html("a  &amp;\n b &lt;c&gt;&#33;");
+        assert_eq!(
+            text.blocks,
+            vec![Block::Paragraph(vec![plain("a & b <c>!")])]
+        );
+    }
+
+    #[test]
+    fn keeps_code_block_whitespace_and_language() {
+        let text = RichText::from_html(
+            "<pre><code class=\"language-rust\">fn main() {\n    run();\n}\n</code></pre>",
+        );
+        assert_eq!(
+            text.blocks,
+            vec![Block::Code {
+                language: Some("rust".to_owned()),
+                code: "fn main() {\n    run();\n}".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_quotes_and_lists() {
+        let text = RichText::from_html(
+            "<blockquote>quoted</blockquote><ol start=\"3\"><li>one</li><li>two</li></ol>",
+        );
+        assert_eq!(
+            text.blocks,
+            vec![
+                Block::Quote(vec![Block::Paragraph(vec![plain("quoted")])]),
+                Block::List {
+                    ordered: true,
+                    start: 3,
+                    items: vec![
+                        vec![Block::Paragraph(vec![plain("one")])],
+                        vec![Block::Paragraph(vec![plain("two")])],
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_reply_fallback() {
+        let text = RichText::from_html(
+            "<mx-reply><blockquote>In reply to <b>someone</b></blockquote></mx-reply>answer",
+        );
+        assert_eq!(text.blocks, vec![Block::Paragraph(vec![plain("answer")])]);
+    }
+
+    #[test]
+    fn links_only_safe_schemes_and_detects_pills() {
+        let text = RichText::from_html(
+            "<a href=\"https://example.org\">web</a> \
+             <a href=\"javascript:alert(1)\">script</a> \
+             <a href=\"https://matrix.to/#/%40alice%3Aexample.org\">Alice</a>",
+        );
+        assert_eq!(
+            text.blocks,
+            vec![Block::Paragraph(vec![
+                span("web", |s| s.link = Some("https://example.org".to_owned())),
+                plain(" "),
+                plain("script"),
+                plain(" "),
+                span("Alice", |s| s.mention =
+                    Some("@alice:example.org".to_owned())),
+            ])]
+        );
+        assert_eq!(text.mentions(), vec!["@alice:example.org".to_owned()]);
+    }
+
+    #[test]
+    fn safe_links() {
+        assert!(is_safe_link("https://example.org"));
+        assert!(is_safe_link("HTTP://example.org"));
+        assert!(is_safe_link("mailto:someone@example.org"));
+        assert!(!is_safe_link("file:///etc/passwd"));
+        assert!(!is_safe_link("javascript:alert(1)"));
+        assert!(!is_safe_link("example.org"));
+    }
+
+    #[test]
+    fn plain_text_is_plain() {
+        assert!(RichText::from_html("just <span>text</span>").is_plain());
+        assert!(!RichText::from_html("<b>bold</b>").is_plain());
+    }
+}
@@ -1,20 +1,70 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use egui::ahash::HashSet;
-use grammers_client::session::Session as GrammersSession;
+use grammers_client::client::chats::ParticipantPermissions;
+use grammers_client::session::{PackedChat, PackedType, Session as GrammersSession};
 use grammers_client::{
-    Client as GrammersClient, Config, SignInError, Update,
-    types::{Chat as GrammersChat, Message as GrammersMessage},
+    Client as GrammersClient, Config, InitParams, InputMessage, InvocationError, SignInError,
+    Update, grammers_tl_types as tl,
+    types::{
+        Chat as GrammersChat, ChatMap, Message as GrammersMessage, PasswordToken, Role,
+        User as GrammersUser,
+    },
 };
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tokio::runtime::Runtime;
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{Mutex as AsyncMutex, oneshot};
+use tokio::task::JoinHandle;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::{task, time};
+#[cfg(target_arch = "wasm32")]
+use tokio_with_wasm::alias::{task, time};
+
+use crate::qr::show_qr_code;
+
+use super::{
+    Chat, ChatAvatars, ChatKind, ChatSettings, ChatTag, Client, DirectoryPage, Event, EventGroup,
+    EventKind, HistoryVisibility, Invite, JoinRule, LoginApproval, LoginForm, Member, MemberPage,
+    MemberRole, NewChat, Notification, NotifyMode, Permissions, PostInfo, Presence, PresenceInfo,
+    RoomPreview, Session, Space, SystemEvent, ThreadPage, ThreadRoot, ThreadSummary, Topic,
+    TypingUsers, UserProfile, device_name,
+    rich_text::{Block, RichText, Span, SpanStyle, is_safe_link},
+};
+
+/// How long `sync` waits for the first update before returning
+const UPDATE_WAIT: Duration = Duration::from_secs(1);
+
+/// Typing actions expire unless repeated, as Telegram clients resend them every 5 seconds
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Part size for `upload.saveFilePart`, the largest Telegram accepts
+const UPLOAD_PART_SIZE: usize = 512 * 1024;
+
+/// Folder Telegram clients show as the archive
+const ARCHIVE_FOLDER: i32 = 1;
+
+/// Session files, kept apart so signing in as a bot doesn't reuse a user's authorization
+#[cfg(not(target_arch = "wasm32"))]
+const USER_SESSION_FILE: &str = "telegram.session";
+#[cfg(not(target_arch = "wasm32"))]
+const BOT_SESSION_FILE: &str = "telegram-bot.session";
+
+/// Messages a bot keeps per chat, as it can't fetch history from the server
+const BOT_HISTORY_LIMIT: usize = 200;
+
+/// Messages loaded at a time when opening or scrolling a timeline or thread
+const HISTORY_PAGE: usize = 20;
 
-use super::{Chat, Client, Event, EventGroup, EventKind, LoginForm};
+/// Topics listed per forum, Telegram's maximum for one request
+const FORUM_TOPIC_LIMIT: i32 = 100;
+
+/// The General topic of a forum, whose messages are shown with the group itself
+const GENERAL_TOPIC: i32 = 1;
 
 /// Stores Telegram client session information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +75,82 @@ pub struct ClientSession {
     session_path: PathBuf,
 }
 
+impl ClientSession {
+    fn new(api_id: i32, api_hash: &str, bot: bool) -> Result<Self> {
+        // Set up session path for desktop platforms
+        #[cfg(not(target_arch = "wasm32"))]
+        let session_path = {
+            let data_dir = dirs::data_dir().unwrap().join("echat");
+            std::fs::create_dir_all(&data_dir)?;
+            data_dir.join(if bot {
+                BOT_SESSION_FILE
+            } else {
+                USER_SESSION_FILE
+            })
+        };
+        #[cfg(target_arch = "wasm32")]
+        let _ = bot;
+
+        Ok(Self {
+            api_id,
+            api_hash: api_hash.to_owned(),
+            #[cfg(not(target_arch = "wasm32"))]
+            session_path,
+        })
+    }
+
+    /// Session to connect with, reusing the session file on desktop
+    fn load(&self) -> Result<GrammersSession> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            Ok(GrammersSession::load_file_or_create(&self.session_path)?)
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            Ok(GrammersSession::new())
+        }
+    }
+}
+
+/// What a bot has seen, since bots can't list dialogs or read chat history
+#[derive(Default)]
+struct BotChats {
+    /// Chats in the order messages were first received in them
+    chats: Vec<Chat>,
+    /// Recent messages by chat ID, oldest first
+    history: HashMap<String, Vec<GrammersMessage>>,
+    /// Whether `chats` changed since the app last listed them
+    changed: bool,
+}
+
+/// Small profile photos of users and chats, downloaded lazily in the background
+#[derive(Default)]
+struct Avatars {
+    /// Photos by peer ID, `None` when there is none. Failed downloads aren't
+    /// kept, so the peer is queued again the next time it is shown.
+    photos: HashMap<i64, Option<Arc<[u8]>>>,
+    /// Peers waiting for their photo to be downloaded
+    queue: Vec<GrammersChat>,
+    /// Whether a task is working through the queue
+    downloading: bool,
+}
+
+/// Unread state of the chats in the last dialog listing, by chat ID
+#[derive(Default)]
+struct Dialogs {
+    unread: HashMap<String, bool>,
+    /// Whether an update added a chat or changed its unread state since
+    changed: bool,
+}
+
+/// Topics of forum groups, fetched once and dropped when the forum changes
+#[derive(Default)]
+struct ForumTopics {
+    by_forum: HashMap<i64, Vec<Topic>>,
+    /// Whether a forum changed since the app last listed the chats
+    changed: bool,
+}
+
 /// Complete session information including client config
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FullSession {
@@ -59,6 +185,24 @@ impl<'de> Deserialize<'de> for SerializableSession {
     }
 }
 
+/// Progress of signing in by scanning a QR code with a logged in app
+#[derive(Default)]
+pub enum QrLogin {
+    #[default]
+    Starting,
+    /// `tg://login` link to show, replaced before it expires
+    Ready(String),
+    /// Two-step verification is enabled, the account password is needed
+    Password {
+        hint: Option<String>,
+        /// Whether the previous attempt was wrong
+        wrong: bool,
+        reply: Option<oneshot::Sender<String>>,
+    },
+    Done(Arc<TelegramClient>),
+    Failed(String),
+}
+
 /// Login form for Telegram
 #[derive(Default)]
 pub struct Login {
@@ -66,18 +210,169 @@ pub struct Login {
     api_id: String,
     api_hash: String,
     error_message: Option<String>,
+    /// QR code login, while it is in progress
+    qr_login: Option<Arc<Mutex<QrLogin>>>,
+    /// Task waiting for the QR code to be scanned, stopped when going back
+    qr_task: Option<JoinHandle<()>>,
+    password: String,
+    /// Token from @BotFather, for signing in as a bot
+    bot_token: String,
+}
+
+impl Login {
+    /// API ID and hash from the `API_ID` and `API_HASH` environment variables
+    fn api_credentials(&mut self) -> Option<(i32, String)> {
+        let api_id = match std::env::var("API_ID") {
+            Ok(id_str) => match id_str.parse::<i32>() {
+                Ok(id) => id,
+                Err(_) => {
+                    self.error_message =
+                        Some("API_ID environment variable must be a valid number".to_string());
+                    return None;
+                }
+            },
+            Err(_) => {
+                self.error_message = Some("API_ID environment variable not set".to_string());
+                return None;
+            }
+        };
+
+        let api_hash = match std::env::var("API_HASH") {
+            Ok(hash) => hash,
+            Err(_) => {
+                self.error_message = Some("API_HASH environment variable not set".to_string());
+                return None;
+            }
+        };
+
+        Some((api_id, api_hash))
+    }
+
+    /// Start signing in by showing a QR code
+    fn start_qr_login(&mut self, rt: &Runtime, ctx: &egui::Context) {
+        let Some((api_id, api_hash)) = self.api_credentials() else {
+            return;
+        };
+
+        let state = Arc::new(Mutex::new(QrLogin::Starting));
+        self.qr_login = Some(state.clone());
+        self.password.clear();
+        let ctx = ctx.clone();
+
+        let task = rt.spawn(async move {
+            let result =
+                TelegramClient::login_with_qr_code(api_id, &api_hash, state.clone(), ctx.clone())
+                    .await;
+
+            *state.lock() = match result {
+                Ok(client) => QrLogin::Done(client),
+                Err(e) => {
+                    log::error!("QR code login failed: {}", e);
+                    QrLogin::Failed(format!("Login failed: {}", e))
+                }
+            };
+            ctx.request_repaint();
+        });
+        self.qr_task = Some(task);
+    }
+
+    /// QR code and password entry, returns the client once signed in
+    fn show_qr_login(
+        &mut self,
+        ui: &mut egui::Ui,
+        state: &Arc<Mutex<QrLogin>>,
+    ) -> Option<Arc<TelegramClient>> {
+        let mut cancel = false;
+        let mut client = None;
+
+        ui.vertical_centered(|ui| {
+            ui.heading("Log in by QR code");
+            ui.add_space(10.0);
+
+            match &mut *state.lock() {
+                QrLogin::Starting => {
+                    ui.spinner();
+                }
+                QrLogin::Ready(link) => {
+                    ui.label("Open Telegram on your phone, go to Settings > Devices > Link Desktop Device and scan this code");
+                    ui.add_space(5.0);
+                    show_qr_code(ui, link.as_bytes(), 240.0);
+                    ui.add_space(5.0);
+                    if ui
+                        .small_button("📋 Copy link")
+                        .on_hover_text("For apps that can't scan, paste it in Sessions and security")
+                        .clicked()
+                    {
+                        ui.ctx().copy_text(link.clone());
+                    }
+                }
+                QrLogin::Password { hint, wrong, reply } => {
+                    ui.label("Your account is protected with an additional password");
+                    if *wrong {
+                        ui.colored_label(egui::Color32::RED, "Wrong password");
+                    }
+                    let mut edit = egui::TextEdit::singleline(&mut self.password).password(true);
+                    if let Some(hint) = hint {
+                        edit = edit.hint_text(hint.as_str());
+                    }
+                    let response = ui.add(edit);
+                    let entered =
+                        response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Continue").clicked() || entered)
+                        && let Some(reply) = reply.take()
+                    {
+                        let _ = reply.send(std::mem::take(&mut self.password));
+                    }
+                }
+                QrLogin::Done(done) => client = Some(done.clone()),
+                QrLogin::Failed(error) => {
+                    ui.colored_label(egui::Color32::RED, error.as_str());
+                }
+            }
+
+            ui.add_space(10.0);
+            if ui.button("Back").clicked() {
+                cancel = true;
+            }
+        });
+
+        if cancel || client.is_some() {
+            self.qr_login = None;
+            if let Some(task) = self.qr_task.take() {
+                task.abort();
+            }
+        }
+
+        client
+    }
+}
+
+/// Add a signed in client, whose chats the app's sync loop loads
+fn start_client(clients: &Arc<Mutex<Vec<Arc<dyn Client>>>>, client: Arc<TelegramClient>) {
+    clients.lock().push(client);
 }
 
 impl LoginForm for Login {
     fn show(
         &mut self,
         clients: &Arc<Mutex<Vec<Arc<dyn Client>>>>,
-        chats: &Arc<Mutex<Vec<Chat>>>,
+        _chats: &Arc<Mutex<Vec<Chat>>>,
         rt: &mut Runtime,
         frame: &mut eframe::Frame,
         ui: &mut egui::Ui,
     ) -> Result<()> {
+        if let Some(state) = self.qr_login.clone() {
+            if let Some(client) = self.show_qr_login(ui, &state) {
+                if let Some(storage) = frame.storage_mut() {
+                    client.save(storage, "telegram_session")?;
+                }
+                start_client(clients, client);
+            }
+            return Ok(());
+        }
+
         let mut try_login = false;
+        let mut try_bot_login = false;
 
         ui.vertical_centered(|ui| {
             ui.heading("Login to Telegram");
@@ -91,6 +386,24 @@ impl LoginForm for Login {
             if ui.button("Login").clicked() {
                 try_login = true;
             }
+            if ui.button("Log in by QR code").clicked() {
+                self.start_qr_login(rt, ui.ctx());
+            }
+
+            ui.add_space(10.0);
+            ui.separator();
+
+            ui.label("Bot Token:");
+            ui.add(egui::TextEdit::singleline(&mut self.bot_token).password(true));
+            if ui
+                .add_enabled(
+                    !self.bot_token.trim().is_empty(),
+                    egui::Button::new("Log in as bot"),
+                )
+                .clicked()
+            {
+                try_bot_login = true;
+            }
 
             // Show error message if needed
             if let Some(error) = &self.error_message {
@@ -99,33 +412,37 @@ impl LoginForm for Login {
             }
         });
 
+        if try_bot_login {
+            let Some((api_id, api_hash)) = self.api_credentials() else {
+                return Ok(());
+            };
+
+            match rt.block_on(TelegramClient::bot_login(
+                api_id,
+                &api_hash,
+                self.bot_token.trim(),
+            )) {
+                Ok(client) => {
+                    self.error_message = None;
+                    self.bot_token.clear();
+                    if let Some(storage) = frame.storage_mut() {
+                        client.save(storage, "telegram_session")?;
+                    }
+                    start_client(clients, client);
+                }
+                Err(e) => {
+                    log::error!("Bot login failed: {}", e);
+                    self.error_message = Some(format!("Login failed: {}", e));
+                }
+            }
+            return Ok(());
+        }
+
         if try_login {
             if let Some(storage) = frame.storage_mut() {
                 // Get API ID and API Hash from environment variables
-                let api_id = match std::env::var("API_ID") {
-                    Ok(id_str) => match id_str.parse::<i32>() {
-                        Ok(id) => id,
-                        Err(_) => {
-                            self.error_message = Some(
-                                "API_ID environment variable must be a valid number".to_string(),
-                            );
-                            return Ok(());
-                        }
-                    },
-                    Err(_) => {
-                        self.error_message =
-                            Some("API_ID environment variable not set".to_string());
-                        return Ok(());
-                    }
-                };
-
-                let api_hash = match std::env::var("API_HASH") {
-                    Ok(hash) => hash,
-                    Err(_) => {
-                        self.error_message =
-                            Some("API_HASH environment variable not set".to_string());
-                        return Ok(());
-                    }
+                let Some((api_id, api_hash)) = self.api_credentials() else {
+                    return Ok(());
                 };
 
                 match rt.block_on(TelegramClient::login(
@@ -138,29 +455,7 @@ impl LoginForm for Login {
                         // Login successful, clear error message
                         self.error_message = None;
 
-                        // Add client to the clients list
-                        clients.lock().push(client.clone());
-
-                        // Clone for async tasks
-                        let client_clone = client.clone();
-                        let chats_clone = chats.clone();
-
-                        // Spawn an async task to handle sync and chat loading
-                        rt.spawn(async move {
-                            if let Err(e) = client_clone.sync().await {
-                                log::error!("Failed to sync with server: {}", e);
-                                return;
-                            }
-
-                            match client_clone.chats().await {
-                                Ok(client_chats) => {
-                                    *chats_clone.lock() = client_chats;
-                                }
-                                Err(e) => {
-                                    log::error!("Failed to fetch chats: {}", e);
-                                }
-                            }
-                        });
+                        start_client(clients, client);
 
                         return Ok(());
                     }
@@ -184,6 +479,27 @@ pub struct TelegramClient {
     selected_chat: AsyncMutex<Option<String>>,
     processed_events: AsyncMutex<HashSet<String>>,
     user_id: Mutex<Arc<String>>,
+    known_chats: Mutex<HashMap<String, PackedChat>>,
+    /// Names of users seen so far, for updates that only carry an ID
+    user_names: Mutex<HashMap<i64, String>>,
+    /// When each user last reported typing in Unix milliseconds, by chat ID.
+    /// `Instant` isn't available on the web.
+    typing_since: Mutex<HashMap<String, HashMap<i64, i64>>>,
+    typing: TypingUsers,
+    presence: Arc<Mutex<HashMap<String, PresenceInfo>>>,
+    /// Blocked users whose messages are hidden, refreshed when a chat is opened
+    blocked: Mutex<HashSet<i64>>,
+    /// Whether signed in with a bot token
+    is_bot: bool,
+    bot_chats: Mutex<BotChats>,
+    avatars: Arc<Mutex<Avatars>>,
+    /// Downloaded chat photos for the app to fill into its chat list, by chat ID
+    chat_avatars: ChatAvatars,
+    dialogs: Mutex<Dialogs>,
+    forum_topics: Mutex<ForumTopics>,
+    /// Comments on a post or replies to a message, and where they are
+    thread_groups: Arc<Mutex<Vec<EventGroup>>>,
+    open_thread: Mutex<Option<(PackedChat, i32)>>,
 }
 
 impl TelegramClient {
@@ -194,33 +510,12 @@ impl TelegramClient {
         api_id: i32,
         api_hash: &str,
     ) -> Result<Arc<Self>> {
-        // Set up session path for desktop platforms
+        let client_session = ClientSession::new(api_id, api_hash, false)?;
         #[cfg(not(target_arch = "wasm32"))]
-        let session_path = {
-            let data_dir = dirs::data_dir().unwrap().join("echat");
-            std::fs::create_dir_all(&data_dir)?;
-            data_dir.join("telegram.session")
-        };
-
-        // Create client session object
-        let client_session = ClientSession {
-            api_id,
-            api_hash: api_hash.to_owned(),
-            #[cfg(not(target_arch = "wasm32"))]
-            session_path: session_path.clone(),
-        };
+        let session_path = client_session.session_path.clone();
 
         // Connect to Telegram
-        let client = GrammersClient::connect(Config {
-            #[cfg(not(target_arch = "wasm32"))]
-            session: GrammersSession::load_file_or_create(&session_path)?,
-            #[cfg(target_arch = "wasm32")]
-            session: GrammersSession::new(),
-            api_id,
-            api_hash: api_hash.to_owned(),
-            params: Default::default(),
-        })
-        .await?;
+        let client = Self::connect(&client_session, client_session.load()?).await?;
 
         // If not authorized, perform login
         if !client.is_authorized().await? {
@@ -243,7 +538,7 @@ impl TelegramClient {
         }
 
         // Get user ID
-        let user_id = client.get_me().await?.id().to_string();
+        let me = client.get_me().await?;
 
         // Store session
         #[cfg(not(target_arch = "wasm32"))]
@@ -262,14 +557,215 @@ impl TelegramClient {
         storage.flush();
 
         // Create and return the client
-        Ok(Arc::new(Self {
+        Ok(Self::with_client(
+            client,
+            client_session,
+            me.id().to_string(),
+            me.is_bot(),
+        ))
+    }
+
+    /// Sign in as a bot with a token from @BotFather
+    pub async fn bot_login(api_id: i32, api_hash: &str, token: &str) -> Result<Arc<Self>> {
+        let client_session = ClientSession::new(api_id, api_hash, true)?;
+        let client = Self::connect(&client_session, client_session.load()?).await?;
+
+        if !client.is_authorized().await? {
+            client
+                .bot_sign_in(token)
+                .await
+                .map_err(|e| anyhow!("Failed to sign in: {}", e))?;
+        }
+
+        let me = client.get_me().await?;
+        if !me.is_bot() {
+            return Err(anyhow!("Session belongs to a user account, not a bot"));
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        client
+            .session()
+            .save_to_file(&client_session.session_path)?;
+
+        log::info!(
+            "Telegram bot {} signed in",
+            me.username().unwrap_or(me.first_name())
+        );
+
+        Ok(Self::with_client(
+            client,
+            client_session,
+            me.id().to_string(),
+            true,
+        ))
+    }
+
+    /// Sign in by scanning a `tg://login` QR code with a logged in app,
+    /// reporting each step through `state`
+    pub async fn login_with_qr_code(
+        api_id: i32,
+        api_hash: &str,
+        state: Arc<Mutex<QrLogin>>,
+        ctx: egui::Context,
+    ) -> Result<Arc<Self>> {
+        let client_session = ClientSession::new(api_id, api_hash, false)?;
+        let mut client = Self::connect(&client_session, client_session.load()?).await?;
+
+        if !client.is_authorized().await? {
+            let accepted =
+                Self::accept_login_token(&mut client, &client_session, &state, &ctx).await;
+            match accepted {
+                Ok(()) => {}
+                Err(e) if is_password_needed(&e) => {
+                    Self::check_qr_password(&client, &state, &ctx).await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let me = client.get_me().await?;
+
+        // A data center switch left a placeholder user in the session
+        if let Some(user) = client.session().get_user() {
+            client.session().set_user(me.id(), user.dc, me.is_bot());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        client
+            .session()
+            .save_to_file(&client_session.session_path)?;
+
+        log::info!("Telegram session created from QR code");
+
+        Ok(Self::with_client(
+            client,
+            client_session,
+            me.id().to_string(),
+            me.is_bot(),
+        ))
+    }
+
+    /// Show login tokens until one is accepted, exporting a new one whenever it expires
+    async fn accept_login_token(
+        client: &mut GrammersClient,
+        client_session: &ClientSession,
+        state: &Mutex<QrLogin>,
+        ctx: &egui::Context,
+    ) -> Result<()> {
+        let export = tl::functions::auth::ExportLoginToken {
+            api_id: client_session.api_id,
+            api_hash: client_session.api_hash.clone(),
+            except_ids: Vec::new(),
+        };
+
+        loop {
+            match client.invoke(&export).await? {
+                tl::enums::auth::LoginToken::Token(token) => {
+                    let link = format!("tg://login?token={}", URL_SAFE_NO_PAD.encode(&token.token));
+                    *state.lock() = QrLogin::Ready(link);
+                    ctx.request_repaint();
+
+                    // Export again once the app accepted the token or it expired
+                    let left = i64::from(token.expires) - chrono::Utc::now().timestamp();
+                    let expiry = Duration::from_secs(left.max(1) as u64);
+                    let _ = time::timeout(expiry, wait_for_login_token(client)).await;
+                }
+                tl::enums::auth::LoginToken::MigrateTo(migrate) => {
+                    // The account lives in another data center: reconnect there
+                    // and import the token, the real user is set once signed in
+                    client.session().set_user(0, migrate.dc_id, false);
+                    let session = GrammersSession::load(&client.session().save())?;
+                    *client = Self::connect(client_session, session).await?;
+
+                    client
+                        .invoke(&tl::functions::auth::ImportLoginToken {
+                            token: migrate.token,
+                        })
+                        .await?;
+                    return Ok(());
+                }
+                tl::enums::auth::LoginToken::Success(_) => return Ok(()),
+            }
+        }
+    }
+
+    /// Ask for the two-step verification password until it is right
+    async fn check_qr_password(
+        client: &GrammersClient,
+        state: &Mutex<QrLogin>,
+        ctx: &egui::Context,
+    ) -> Result<()> {
+        let mut wrong = false;
+
+        loop {
+            // The token from a QR login only says that a password is needed
+            let tl::enums::account::Password::Password(password) = client
+                .invoke(&tl::functions::account::GetPassword {})
+                .await?;
+            let token = PasswordToken::new(password);
+            let (reply, password) = oneshot::channel();
+            *state.lock() = QrLogin::Password {
+                hint: token.hint().map(ToOwned::to_owned),
+                wrong,
+                reply: Some(reply),
+            };
+            ctx.request_repaint();
+
+            let password = password.await.map_err(|_| anyhow!("Login cancelled"))?;
+            *state.lock() = QrLogin::Starting;
+            match client.check_password(token, password).await {
+                Ok(_) => return Ok(()),
+                Err(SignInError::InvalidPassword) => wrong = true,
+                Err(e) => return Err(anyhow!("Failed to sign in: {}", e)),
+            }
+        }
+    }
+
+    /// Connect to Telegram with the given session
+    async fn connect(
+        client_session: &ClientSession,
+        session: GrammersSession,
+    ) -> Result<GrammersClient> {
+        Ok(GrammersClient::connect(Config {
+            session,
+            api_id: client_session.api_id,
+            api_hash: client_session.api_hash.clone(),
+            params: InitParams {
+                device_model: device_name(),
+                ..Default::default()
+            },
+        })
+        .await?)
+    }
+
+    /// Wrap a signed in grammers client
+    fn with_client(
+        client: GrammersClient,
+        client_session: ClientSession,
+        user_id: String,
+        is_bot: bool,
+    ) -> Arc<Self> {
+        Arc::new(Self {
             client: Arc::new(AsyncMutex::new(client)),
             client_session,
             event_groups: Arc::default(),
             selected_chat: AsyncMutex::default(),
             processed_events: AsyncMutex::default(),
             user_id: Mutex::new(Arc::new(user_id)),
-        }))
+            known_chats: Mutex::default(),
+            user_names: Mutex::default(),
+            typing_since: Mutex::default(),
+            typing: Arc::default(),
+            presence: Arc::default(),
+            blocked: Mutex::default(),
+            is_bot,
+            bot_chats: Mutex::default(),
+            avatars: Arc::default(),
+            chat_avatars: Arc::default(),
+            dialogs: Mutex::default(),
+            forum_topics: Mutex::default(),
+            thread_groups: Arc::default(),
+            open_thread: Mutex::default(),
+        })
     }
 
     /// Load an existing client session from storage
@@ -297,26 +793,18 @@ impl TelegramClient {
             let session = GrammersSession::load(&session_data)?;
 
             // Create client
-            let client = GrammersClient::connect(Config {
-                session,
-                api_id: full_session.client_session.api_id,
-                api_hash: full_session.client_session.api_hash.clone(),
-                params: Default::default(),
-            })
-            .await?;
+            let client = Self::connect(&full_session.client_session, session).await?;
 
             // Get user ID
-            let user_id = client.get_me().await?.id().to_string();
+            let me = client.get_me().await?;
 
             // Create and return the client
-            Ok(Arc::new(Self {
-                client: Arc::new(AsyncMutex::new(client)),
-                client_session: full_session.client_session,
-                event_groups: Arc::default(),
-                selected_chat: AsyncMutex::default(),
-                processed_events: AsyncMutex::default(),
-                user_id: Mutex::new(Arc::new(user_id)),
-            }))
+            Ok(Self::with_client(
+                client,
+                full_session.client_session,
+                me.id().to_string(),
+                me.is_bot(),
+            ))
         })
     }
 
@@ -329,115 +817,230 @@ impl TelegramClient {
         if processed_events.contains(&event_id) {
             return Ok(());
         }
-        processed_events.insert(event_id.clone());
+        processed_events.insert(event_id);
 
-        if !message.outgoing() {
-            // Handle incoming message
-            let sender = message.sender().unwrap();
-            let sender_id = sender.id().to_string();
-            let display_name = sender.name().to_string();
-
-            // Get avatar (not directly supported in grammers, would need additional implementation)
-            let avatar = None; // Placeholder for avatar implementation
-
-            // Create event
-            let event = Event {
-                id: event_id,
-                timestamp: message.date().timestamp() as u64,
-                kind: EventKind::Message(message.text().to_owned()),
-            };
+        self.add_message(&self.event_groups, message);
+        Ok(())
+    }
 
-            // Update event groups
-            let mut event_groups = self.event_groups.lock();
+    /// Add a message to the group of its sender, or a group of its own for channel posts
+    fn add_message(&self, event_groups: &Mutex<Vec<EventGroup>>, message: &GrammersMessage) {
+        let blocked = message
+            .sender()
+            .is_some_and(|sender| self.blocked.lock().contains(&sender.id()));
+        if blocked {
+            return;
+        }
 
-            // Try to find an existing group for this sender
-            let mut found = false;
-            for group in event_groups.iter_mut() {
-                if group.user_id == sender_id {
-                    group.events.push(event.clone());
-                    found = true;
-                    break;
+        let kind = match message.action() {
+            Some(action) => {
+                let sender_id = message.sender().map(|sender| sender.id());
+                match self.service_event(action, sender_id) {
+                    Some(event) => EventKind::System(event),
+                    None => return, // Skip service messages that aren't displayed
                 }
             }
+            None => EventKind::Message(message.text().to_owned()),
+        };
+        let is_system = matches!(kind, EventKind::System(_));
+        let formatted = message
+            .fmt_entities()
+            .and_then(|entities| rich_text(message.text(), entities));
+        let mut mentions = formatted
+            .as_ref()
+            .map(RichText::mentions)
+            .unwrap_or_default();
+        if message.mentioned() {
+            mentions.push(self.user_id.lock().to_string());
+        }
+        // Silent messages and our own don't notify; Telegram has no separate sound flag
+        let notify = !message.outgoing() && !message.silent();
+        let notification = Notification {
+            notify,
+            highlight: message.mentioned(),
+            sound: notify,
+        };
 
-            // Create a new group if needed
-            if !found {
-                event_groups.push(EventGroup {
-                    user_id: sender_id,
-                    display_name,
-                    avatar,
-                    events: vec![event.clone()],
-                    from_self: false,
-                });
+        // Channel posts are signed by an admin at most, so they show the channel
+        let post = message.post().then(|| PostInfo {
+            signature: message.post_author().map(ToOwned::to_owned),
+            views: message
+                .view_count()
+                .and_then(|views| u64::try_from(views).ok()),
+        });
+        // Comments on posts and replies in supergroups open as a thread
+        let thread = match &message.raw.replies {
+            Some(tl::enums::MessageReplies::Replies(replies)) if replies.replies > 0 => {
+                Some(ThreadSummary {
+                    reply_count: replies.replies as u64,
+                    latest_reply: None,
+                })
             }
+            _ => None,
+        };
+
+        let from_self = message.outgoing() && post.is_none();
+        let (user_id, display_name, avatar) = if from_self {
+            (self.user_id.lock().to_string(), "You".to_owned(), None)
         } else {
-            // Handle outgoing messages
-            let self_id = self.user_id.lock().clone();
-
-            // Create event
-            let event = Event {
-                id: event_id,
-                timestamp: message.date().timestamp() as u64,
-                kind: EventKind::Message(message.text().to_owned()),
-            };
+            let sender = message.sender().unwrap_or_else(|| message.chat());
+            self.remember_chat(&sender);
+            self.user_names
+                .lock()
+                .insert(sender.id(), sender.name().to_owned());
+            (
+                sender.id().to_string(),
+                sender.name().to_owned(),
+                self.avatar(&sender),
+            )
+        };
 
-            // Update event groups
-            let mut event_groups = self.event_groups.lock();
+        let event = Event {
+            id: message.id().to_string(),
+            timestamp: message.date().timestamp() as u64,
+            kind,
+            thread,
+            read_by: Vec::new(),
+            formatted,
+            mentions,
+            mentions_everyone: false,
+            notification,
+            post: post.clone(),
+        };
 
-            // Try to find an existing group for self
-            let mut found = false;
-            for group in event_groups.iter_mut() {
-                if group.from_self {
-                    group.events.push(event.clone());
-                    found = true;
-                    break;
-                }
-            }
+        let mut event_groups = event_groups.lock();
 
-            // Create a new group if needed
-            if !found {
-                event_groups.push(EventGroup {
-                    user_id: self_id.to_string(),
-                    display_name: "You".to_owned(),
-                    avatar: None,
-                    events: vec![event.clone()],
-                    from_self: true,
-                });
-            }
-        }
+        // Each post stands on its own, other messages join their sender's group
+        let group = event_groups.iter_mut().find(|group| {
+            post.is_none()
+                && group.user_id == user_id
+                && group.from_self == from_self
+                && group.is_system() == is_system
+                && !group.events.iter().any(|event| event.post.is_some())
+        });
 
-        Ok(())
+        match group {
+            Some(group) => group.events.push(event),
+            None => event_groups.push(EventGroup {
+                user_id,
+                display_name,
+                avatar,
+                events: vec![event],
+                from_self,
+                power_level: None,
+            }),
+        }
     }
 
     /// Process Telegram updates into event groups for display
     async fn process_update(&self, update: Update) -> Result<()> {
         match update {
             Update::NewMessage(message) => {
-                self.process_message(&message).await?;
+                // Only the selected chat's timeline is kept in memory
+                let selected = self.selected_chat.lock().await.clone();
+                let chat_id = message.chat().id().to_string();
+                if self.is_bot {
+                    self.remember_bot_message(&message, selected.as_ref() != Some(&chat_id));
+                } else {
+                    // New chats and newly unread ones need the dialogs listed again
+                    let unread = !message.outgoing() && selected.as_ref() != Some(&chat_id);
+                    let mut dialogs = self.dialogs.lock();
+                    match dialogs.unread.get(&chat_id) {
+                        Some(listed) if *listed || !unread => {}
+                        _ => dialogs.changed = true,
+                    }
+                }
+                self.update_forum_topics(&message, selected.as_deref());
+
+                // A forum group shows all of its topics, each topic only its own
+                let topic_id = message_topic(&message).map(|topic| topic_chat_id(&chat_id, topic));
+                if selected == Some(chat_id) || (topic_id.is_some() && selected == topic_id) {
+                    self.process_message(&message).await?;
+                }
+            }
+            Update::Raw(tl::enums::Update::Channel(update)) => {
+                // Forum settings or topics may have changed, fetch them again
+                let mut forum_topics = self.forum_topics.lock();
+                if forum_topics.by_forum.remove(&update.channel_id).is_some() {
+                    forum_topics.changed = true;
+                }
+            }
+            Update::Raw(
+                tl::enums::Update::ReadHistoryInbox(_)
+                | tl::enums::Update::ReadChannelInbox(_)
+                | tl::enums::Update::DialogUnreadMark(_),
+            ) => {
+                // Read on another device or marked unread
+                self.dialogs.lock().changed = true;
+            }
+            Update::Raw(tl::enums::Update::UserStatus(update)) => {
+                self.presence
+                    .lock()
+                    .insert(update.user_id.to_string(), presence_info(&update.status));
             }
+            Update::Raw(update) => self.process_typing(update),
             _ => (), // Ignore other update types
         }
 
         Ok(())
     }
 
-    /// Load chat history for a given chat
-    async fn load_chat_history(&self, chat: &GrammersChat, limit: i32) -> Result<()> {
-        let client = self.client.lock().await;
-
-        // Get message history - convert limit from i32 to usize
-        let limit_usize = limit.try_into().unwrap();
-
-        // Fetch messages first
-        let mut messages_vec = Vec::new();
-        let mut iter = client.iter_messages(chat).limit(limit_usize);
+    /// Record typing actions from raw `UserTyping` style updates
+    fn process_typing(&self, update: tl::enums::Update) {
+        let (chat_id, user_id, action) = match update {
+            tl::enums::Update::UserTyping(typing) => {
+                (typing.user_id, typing.user_id, typing.action)
+            }
+            tl::enums::Update::ChatUserTyping(typing) => {
+                (typing.chat_id, peer_id(&typing.from_id), typing.action)
+            }
+            tl::enums::Update::ChannelUserTyping(typing) => {
+                (typing.channel_id, peer_id(&typing.from_id), typing.action)
+            }
+            _ => return,
+        };
 
-        while let Some(message) = iter.next().await? {
-            messages_vec.push(message);
+        let mut typing_since = self.typing_since.lock();
+        let users = typing_since.entry(chat_id.to_string()).or_default();
+        match action {
+            tl::enums::SendMessageAction::SendMessageTypingAction => {
+                users.insert(user_id, chrono::Utc::now().timestamp_millis());
+            }
+            tl::enums::SendMessageAction::SendMessageCancelAction => {
+                users.remove(&user_id);
+            }
+            _ => {} // Uploads, voice recording and the like aren't shown
         }
+    }
 
-        // We can now drop the client lock
-        drop(client);
+    /// Drop expired typing actions and publish who is still typing
+    fn publish_typing(&self) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let user_names = self.user_names.lock();
+        let mut typing = self.typing.lock();
+        typing.clear();
+
+        self.typing_since.lock().retain(|chat_id, users| {
+            users.retain(|_, since| now - *since < TYPING_TIMEOUT.as_millis() as i64);
+            if !users.is_empty() {
+                let names = users
+                    .keys()
+                    .map(|id| {
+                        user_names
+                            .get(id)
+                            .cloned()
+                            .unwrap_or_else(|| id.to_string())
+                    })
+                    .collect();
+                typing.insert(chat_id.clone(), names);
+            }
+            !users.is_empty()
+        });
+    }
+
+    /// Load chat history for a given chat or forum topic
+    async fn load_chat_history(&self, chat_id: &str, limit: usize) -> Result<()> {
+        let messages_vec = self.fetch_history(chat_id, 0, limit).await?;
 
         // Process messages without holding the lock
         for message in messages_vec {
@@ -447,56 +1050,469 @@ impl TelegramClient {
         Ok(())
     }
 
-    /// Find chat by ID
-    async fn find_chat(&self, chat_id: &str) -> Result<GrammersChat> {
-        let client = self.client.lock().await;
-        let mut dialogs = client.iter_dialogs();
+    /// Latest messages of a chat or forum topic, before `offset_id` unless it is 0
+    async fn fetch_history(
+        &self,
+        chat_id: &str,
+        offset_id: i32,
+        limit: usize,
+    ) -> Result<Vec<GrammersMessage>> {
+        let chat = self.find_chat(chat_id).await?;
 
-        while let Some(dialog) = dialogs.next().await? {
-            let chat = dialog.chat();
-            if chat.id().to_string() == chat_id {
-                return Ok(chat.clone());
-            }
+        // A topic's messages are replies to the message that created it
+        if let (_, Some(topic)) = split_topic(chat_id) {
+            return self
+                .fetch_replies(chat.pack(), topic, offset_id, limit)
+                .await;
         }
 
-        Err(anyhow!("Chat not found: {}", chat_id))
+        let client = self.client.lock().await;
+        let mut messages = Vec::new();
+        let mut iter = client
+            .iter_messages(&chat)
+            .offset_id(offset_id)
+            .limit(limit);
+        while let Some(message) = iter.next().await? {
+            messages.push(message);
+        }
+        Ok(messages)
     }
-}
 
-#[async_trait]
-impl Client for TelegramClient {
-    fn client_name(&self) -> &str {
-        "telegram"
+    /// Replies to a message: the messages of a forum topic or the comments on a post
+    async fn fetch_replies(
+        &self,
+        chat: PackedChat,
+        msg_id: i32,
+        offset_id: i32,
+        limit: usize,
+    ) -> Result<Vec<GrammersMessage>> {
+        let client = self.client.lock().await;
+        let replies = client
+            .invoke(&tl::functions::messages::GetReplies {
+                peer: chat.to_input_peer(),
+                msg_id,
+                offset_id,
+                offset_date: 0,
+                add_offset: 0,
+                limit: limit as i32,
+                max_id: 0,
+                min_id: 0,
+                hash: 0,
+            })
+            .await?;
+        Ok(messages_from_raw(&client, replies))
     }
 
-    /// Synchronize with Telegram to get latest messages
-    async fn sync(&self) -> Result<()> {
-        let client = self.client.lock().await;
+    /// Count a new message against its cached topic, dropping the topics when one is
+    /// created or edited
+    fn update_forum_topics(&self, message: &GrammersMessage, selected: Option<&str>) {
+        let forum_id = message.chat().id();
+        let mut forum_topics = self.forum_topics.lock();
+
+        if matches!(
+            message.action(),
+            Some(tl::enums::MessageAction::TopicCreate(_) | tl::enums::MessageAction::TopicEdit(_))
+        ) {
+            if forum_topics.by_forum.remove(&forum_id).is_some() {
+                forum_topics.changed = true;
+            }
+            return;
+        }
+
+        let Some(topic_id) = message_topic(message) else {
+            return;
+        };
+        let topic_id = topic_chat_id(&forum_id.to_string(), topic_id);
+        if message.outgoing() || selected == Some(topic_id.as_str()) {
+            return;
+        }
+        let topic = forum_topics
+            .by_forum
+            .get_mut(&forum_id)
+            .and_then(|topics| topics.iter_mut().find(|topic| topic.id == topic_id));
+        if let Some(topic) = topic {
+            topic.unread += 1;
+        }
+    }
 
-        // Get updates - assuming we need to use a different method since iter_updates doesn't exist
-        // Placeholder: Replace with the correct method for getting updates
-        let mut messages_vec = Vec::new();
+    /// Topics of a forum group, except General which is the group's own timeline
+    async fn fetch_forum_topics(
+        &self,
+        client: &GrammersClient,
+        chat: &GrammersChat,
+    ) -> Result<Vec<Topic>> {
+        let Some(channel) = chat.pack().try_to_input_channel() else {
+            return Ok(Vec::new());
+        };
 
-        // This would need to be replaced with the actual method to get updates
-        // For now, we'll just handle any new messages in chats we're monitoring
-        if let Some(chat_id) = self.selected_chat.lock().await.clone() {
-            if let Ok(chat) = self.find_chat(&chat_id).await {
-                let mut iter = client.iter_messages(&chat).limit(10_usize);
-                while let Some(message) = iter.next().await? {
-                    messages_vec.push(message);
+        let tl::enums::messages::ForumTopics::Topics(topics) = client
+            .invoke(&tl::functions::channels::GetForumTopics {
+                channel,
+                q: None,
+                offset_date: 0,
+                offset_id: 0,
+                offset_topic: 0,
+                limit: FORUM_TOPIC_LIMIT,
+            })
+            .await?;
+
+        Ok(topics
+            .topics
+            .into_iter()
+            .filter_map(|topic| match topic {
+                tl::enums::ForumTopic::Topic(topic) if topic.id != GENERAL_TOPIC => Some(Topic {
+                    id: topic_chat_id(&chat.id().to_string(), topic.id),
+                    name: topic.title,
+                    icon_color: u32::try_from(topic.icon_color).ok(),
+                    unread: u64::try_from(topic.unread_count).unwrap_or_default(),
+                }),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Record a message received by a bot, listing its chat the first time it's seen
+    fn remember_bot_message(&self, message: &GrammersMessage, unread: bool) {
+        let chat = message.chat();
+        let chat_id = chat.id().to_string();
+        self.remember_chat(&chat);
+
+        let mut guard = self.bot_chats.lock();
+        let bot_chats = &mut *guard;
+        match bot_chats.chats.iter_mut().find(|c| c.id == chat_id) {
+            Some(known) => {
+                if unread && !message.outgoing() && !known.unread {
+                    known.unread = true;
+                    bot_chats.changed = true;
                 }
             }
+            None => {
+                bot_chats.chats.push(Chat {
+                    id: chat_id.clone(),
+                    name: Some(chat.name().to_owned()),
+                    avatar: self.avatar(&chat),
+                    unread: unread && !message.outgoing(),
+                    direct_user: matches!(chat, GrammersChat::User(_)).then(|| chat_id.clone()),
+                    mentions: 0,
+                    replaced_by: None,
+                    kind: chat_kind(&chat),
+                    tag: None,
+                    topics: Vec::new(),
+                });
+                bot_chats.changed = true;
+            }
         }
 
-        // Drop the lock before processing
-        drop(client);
+        let history = bot_chats.history.entry(chat_id).or_default();
+        if !history.iter().any(|m| m.id() == message.id()) {
+            history.push(message.clone());
+            if history.len() > BOT_HISTORY_LIMIT {
+                history.remove(0);
+            }
+        }
+    }
 
-        // Process each message without holding the lock
-        for message in messages_vec {
-            self.process_update(Update::NewMessage(message)).await?;
+    /// Downloaded profile photo of a user or chat, queueing the download if it hasn't been
+    fn avatar(&self, chat: &GrammersChat) -> Option<Arc<[u8]>> {
+        let mut avatars = self.avatars.lock();
+        if let Some(photo) = avatars.photos.get(&chat.id()) {
+            return photo.clone();
+        }
+        if !avatars.queue.iter().any(|queued| queued.id() == chat.id()) {
+            avatars.queue.push(chat.clone());
         }
+        None
+    }
 
-        Ok(())
+    /// Start downloading queued profile photos, unless that is already under way
+    fn download_avatars(&self, client: GrammersClient) {
+        {
+            let mut avatars = self.avatars.lock();
+            if avatars.downloading || avatars.queue.is_empty() {
+                return;
+            }
+            avatars.downloading = true;
+        }
+
+        let avatars = self.avatars.clone();
+        let chat_avatars = self.chat_avatars.clone();
+        let event_groups = self.event_groups.clone();
+        task::spawn(async move {
+            loop {
+                let chat = {
+                    let mut avatars = avatars.lock();
+                    if avatars.queue.is_empty() {
+                        avatars.downloading = false;
+                        break;
+                    }
+                    avatars.queue.remove(0)
+                };
+
+                let photo = match download_photo(&client, &chat).await {
+                    Ok(photo) => photo,
+                    Err(e) => {
+                        log::warn!("Failed to download avatar of {}: {}", chat.id(), e);
+                        continue;
+                    }
+                };
+                if let Some(photo) = &photo {
+                    let id = chat.id().to_string();
+                    chat_avatars.lock().insert(id.clone(), photo.clone());
+
+                    // Senders already on screen get their avatar right away
+                    for group in event_groups.lock().iter_mut() {
+                        if group.user_id == id && !group.from_self {
+                            group.avatar = Some(photo.clone());
+                        }
+                    }
+                }
+                avatars.lock().photos.insert(chat.id(), photo);
+            }
+        });
+    }
+
+    /// Member list entry for a user, remembered so "Message privately" can find them
+    fn member(&self, user: &GrammersUser, role: MemberRole) -> Member {
+        let chat = GrammersChat::User(user.clone());
+        self.remember_chat(&chat);
+
+        Member {
+            profile: UserProfile {
+                id: user.id().to_string(),
+                display_name: Some(user.full_name()),
+                avatar: self.avatar(&chat),
+            },
+            role,
+            power_level: None,
+        }
+    }
+
+    /// Remember a chat so it can be found before it shows up in the dialogs
+    fn remember_chat(&self, chat: &GrammersChat) {
+        self.known_chats
+            .lock()
+            .insert(chat.id().to_string(), chat.pack());
+    }
+
+    /// Describe a service message, naming affected users when they are known
+    fn service_event(
+        &self,
+        action: &tl::enums::MessageAction,
+        sender_id: Option<i64>,
+    ) -> Option<SystemEvent> {
+        let name = |user_id: i64| {
+            self.user_names
+                .lock()
+                .get(&user_id)
+                .cloned()
+                .unwrap_or_else(|| user_id.to_string())
+        };
+
+        let event = match action {
+            tl::enums::MessageAction::ChatCreate(_)
+            | tl::enums::MessageAction::ChannelCreate(_) => SystemEvent::Created,
+            tl::enums::MessageAction::ChatEditTitle(edit) => {
+                SystemEvent::Renamed(edit.title.clone())
+            }
+            tl::enums::MessageAction::ChatEditPhoto(_)
+            | tl::enums::MessageAction::ChatDeletePhoto => SystemEvent::AvatarChanged,
+            tl::enums::MessageAction::ChatAddUser(add) => match add.users.as_slice() {
+                [user_id] if Some(*user_id) == sender_id => SystemEvent::Joined,
+                users => SystemEvent::Added(
+                    users
+                        .iter()
+                        .map(|&id| name(id))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            },
+            tl::enums::MessageAction::ChatDeleteUser(delete) => {
+                if Some(delete.user_id) == sender_id {
+                    SystemEvent::Left
+                } else {
+                    SystemEvent::Removed(name(delete.user_id))
+                }
+            }
+            tl::enums::MessageAction::ChatJoinedByLink(_)
+            | tl::enums::MessageAction::ChatJoinedByRequest => SystemEvent::Joined,
+            tl::enums::MessageAction::PinMessage => SystemEvent::Pinned,
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    /// Remember a raw user returned by a TL request, returning its profile
+    fn remember_user(&self, user: &tl::enums::User) -> Option<UserProfile> {
+        let tl::enums::User::User(user) = user else {
+            return None;
+        };
+
+        let packed = PackedChat {
+            ty: if user.bot {
+                PackedType::Bot
+            } else {
+                PackedType::User
+            },
+            id: user.id,
+            access_hash: user.access_hash,
+        };
+        self.known_chats.lock().insert(user.id.to_string(), packed);
+
+        let name = [user.first_name.as_deref(), user.last_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !name.is_empty() {
+            self.user_names.lock().insert(user.id, name.clone());
+        }
+        if let Some(status) = &user.status {
+            self.presence
+                .lock()
+                .insert(user.id.to_string(), presence_info(status));
+        }
+
+        Some(UserProfile {
+            id: user.id.to_string(),
+            display_name: Some(name).filter(|n| !n.is_empty()),
+            avatar: None,
+        })
+    }
+
+    /// Fetch the whole block list, remembering the blocked users
+    async fn fetch_blocked(&self) -> Result<Vec<UserProfile>> {
+        const PAGE: i32 = 100;
+
+        let client = self.client.lock().await;
+        let mut ids = HashSet::default();
+        let mut users = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            let (blocked, page_users) = match client
+                .invoke(&tl::functions::contacts::GetBlocked {
+                    my_stories_from: false,
+                    offset,
+                    limit: PAGE,
+                })
+                .await?
+            {
+                tl::enums::contacts::Blocked::Blocked(page) => (page.blocked, page.users),
+                tl::enums::contacts::Blocked::Slice(page) => (page.blocked, page.users),
+            };
+
+            let count = blocked.len();
+            for entry in blocked {
+                let tl::enums::PeerBlocked::Blocked(entry) = entry;
+                if let tl::enums::Peer::User(peer) = entry.peer_id {
+                    ids.insert(peer.user_id);
+                }
+            }
+            users.extend(
+                page_users
+                    .iter()
+                    .filter_map(|user| self.remember_user(user)),
+            );
+
+            if count < PAGE as usize {
+                break;
+            }
+            offset += PAGE;
+        }
+
+        users.retain(|user| user.id.parse().is_ok_and(|id| ids.contains(&id)));
+        *self.blocked.lock() = ids;
+        Ok(users)
+    }
+
+    /// Upload a small file in parts so it can be referenced by other requests
+    async fn upload_file(
+        client: &GrammersClient,
+        name: &str,
+        data: &[u8],
+    ) -> Result<tl::enums::InputFile> {
+        let file_id: i64 = rand::random();
+        let parts = data.chunks(UPLOAD_PART_SIZE);
+        let part_count = parts.len();
+
+        for (index, part) in parts.enumerate() {
+            client
+                .invoke(&tl::functions::upload::SaveFilePart {
+                    file_id,
+                    file_part: index as i32,
+                    bytes: part.to_vec(),
+                })
+                .await?;
+        }
+
+        Ok(tl::types::InputFile {
+            id: file_id,
+            parts: part_count as i32,
+            name: name.to_owned(),
+            md5_checksum: String::new(),
+        }
+        .into())
+    }
+
+    /// Find chat by ID, or the forum a topic is in
+    async fn find_chat(&self, chat_id: &str) -> Result<GrammersChat> {
+        let (chat_id, _) = split_topic(chat_id);
+        let client = self.client.lock().await;
+
+        // Chats without a dialog yet (new DMs, search results) are remembered by ID
+        let known = self.known_chats.lock().get(chat_id).cloned();
+        if let Some(packed) = known {
+            return Ok(client.unpack_chat(packed).await?);
+        }
+        if self.is_bot {
+            return Err(anyhow!("Chat not found: {}", chat_id));
+        }
+
+        let mut dialogs = client.iter_dialogs();
+
+        while let Some(dialog) = dialogs.next().await? {
+            let chat = dialog.chat();
+            if chat.id().to_string() == chat_id {
+                return Ok(chat.clone());
+            }
+        }
+
+        Err(anyhow!("Chat not found: {}", chat_id))
+    }
+}
+
+#[async_trait]
+impl Client for TelegramClient {
+    fn client_name(&self) -> &str {
+        "telegram"
+    }
+
+    /// Synchronize with Telegram to get latest messages
+    async fn sync(&self) -> Result<bool> {
+        // Clone the handle so waiting for updates doesn't block other requests
+        let client = self.client.lock().await.clone();
+
+        // New messages of the selected chat arrive as updates, no need to poll its history
+        // Wait a moment for the first update, then take whatever else is queued
+        let mut wait = UPDATE_WAIT;
+        while let Ok(update) = time::timeout(wait, client.next_update()).await {
+            self.process_update(update?).await?;
+            wait = Duration::ZERO;
+        }
+        self.publish_typing();
+
+        // Photos are handed over through `chat_avatars`, without listing the chats again
+        self.download_avatars(client);
+
+        // Bots learn about chats from updates, which are cheap to list again
+        if self.is_bot {
+            return Ok(std::mem::take(&mut self.bot_chats.lock().changed));
+        }
+
+        // Dialogs are only listed again when an update changed them, as that is a
+        // network round trip
+        let dialogs_changed = std::mem::take(&mut self.dialogs.lock().changed);
+        Ok(std::mem::take(&mut self.forum_topics.lock().changed) || dialogs_changed)
     }
 
     /// Save current session state to storage
@@ -528,105 +1544,1845 @@ impl Client for TelegramClient {
 
     /// Get list of available chats
     async fn chats(&self) -> Result<Vec<Chat>> {
+        // Bots can't list dialogs, only the chats they received messages in
+        if self.is_bot {
+            let mut chats = self.bot_chats.lock().chats.clone();
+            let avatars = self.avatars.lock();
+            for chat in &mut chats {
+                if let Some(photo) = chat.id.parse().ok().and_then(|id| avatars.photos.get(&id)) {
+                    chat.avatar = photo.clone();
+                }
+            }
+            return Ok(chats);
+        }
+
         let client = self.client.lock().await;
         let mut dialogs = client.iter_dialogs();
         let mut chats = Vec::new();
+        let mut forums = Vec::new();
 
         while let Some(dialog) = dialogs.next().await? {
             let chat_entity = dialog.chat();
 
+            let (unread, mentions) = match &dialog.raw {
+                tl::enums::Dialog::Dialog(raw) => (
+                    raw.unread_count > 0 || raw.unread_mark,
+                    u64::try_from(raw.unread_mentions_count).unwrap_or_default(),
+                ),
+                tl::enums::Dialog::Folder(_) => (false, 0),
+            };
+
+            // Pinned dialogs are favourites, archived ones low priority
+            let tag = match &dialog.raw {
+                tl::enums::Dialog::Dialog(raw) if raw.pinned => Some(ChatTag::Favourite),
+                tl::enums::Dialog::Dialog(raw) if raw.folder_id == Some(ARCHIVE_FOLDER) => {
+                    Some(ChatTag::LowPriority)
+                }
+                _ => None,
+            };
+
+            let kind = chat_kind(chat_entity);
+
+            // Forum topics are filled in below, without holding up the dialog listing
+            if is_forum(chat_entity) {
+                forums.push(chat_entity.clone());
+            }
+
+            // Private chats share the ID of the user on the other side
+            let direct_user = match chat_entity {
+                GrammersChat::User(user) => {
+                    self.presence
+                        .lock()
+                        .insert(user.id().to_string(), presence_info(user.status()));
+                    Some(user.id().to_string())
+                }
+                _ => None,
+            };
+
             chats.push(Chat {
                 id: chat_entity.id().to_string(),
                 name: Some(chat_entity.name().to_owned()),
-                avatar: None, // Placeholder for avatar implementation
+                avatar: self.avatar(chat_entity),
+                unread,
+                direct_user,
+                mentions,
+                replaced_by: None,
+                kind,
+                tag,
+                topics: Vec::new(),
             });
         }
+        drop(dialogs);
+        drop(client);
+
+        *self.dialogs.lock() = Dialogs {
+            unread: chats
+                .iter()
+                .map(|chat| (chat.id.clone(), chat.unread))
+                .collect(),
+            changed: false,
+        };
+
+        // Topics are cached per forum, only new forums are asked for theirs
+        let client = self.client.lock().await.clone();
+        for forum in forums {
+            let cached = self.forum_topics.lock().by_forum.get(&forum.id()).cloned();
+            let topics = match cached {
+                Some(topics) => topics,
+                None => match self.fetch_forum_topics(&client, &forum).await {
+                    Ok(topics) => {
+                        self.forum_topics
+                            .lock()
+                            .by_forum
+                            .insert(forum.id(), topics.clone());
+                        topics
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to fetch topics of {}: {}", forum.id(), e);
+                        Vec::new()
+                    }
+                },
+            };
+
+            let forum_id = forum.id().to_string();
+            if let Some(chat) = chats.iter_mut().find(|chat| chat.id == forum_id) {
+                chat.topics = topics;
+            }
+        }
 
         Ok(chats)
     }
 
-    /// Select a chat and load its messages
-    async fn select_chat(&self, chat_id: &str) -> Result<()> {
-        // Find the requested chat
-        let chat = self.find_chat(chat_id).await?;
+    /// Telegram has no space hierarchy
+    async fn spaces(&self) -> Result<Vec<Space>> {
+        Ok(Vec::new())
+    }
 
-        // Reset state
-        self.event_groups.lock().clear();
-        self.processed_events.lock().await.clear();
+    /// Telegram has no space hierarchy
+    async fn space_children(&self, _space_id: &str) -> Result<Vec<RoomPreview>> {
+        Err(anyhow!("Telegram does not support spaces"))
+    }
 
-        // Set selected chat
-        *self.selected_chat.lock().await = Some(chat_id.to_owned());
+    /// Join a chat by public username or invite link, returning the chat ID
+    async fn join_chat(&self, target: &str, _via: &[String]) -> Result<String> {
+        let client = self.client.lock().await;
+
+        // Invite links carry a hash instead of a username
+        let invite_hash = target
+            .split_once("/+")
+            .or_else(|| target.split_once("joinchat/"))
+            .map(|(_, hash)| hash.trim_end_matches('/'));
+        let chat = if let Some(hash) = invite_hash {
+            let updates = client
+                .invoke(&tl::functions::messages::ImportChatInvite {
+                    hash: hash.to_owned(),
+                })
+                .await?;
+            let chats = match updates {
+                tl::enums::Updates::Updates(updates) => updates.chats,
+                tl::enums::Updates::Combined(updates) => updates.chats,
+                _ => Vec::new(),
+            };
+            chats.into_iter().next().map(GrammersChat::from_raw)
+        } else {
+            let username = target
+                .trim_start_matches("https://")
+                .trim_start_matches("t.me/")
+                .trim_start_matches('@');
+            let chat = client
+                .resolve_username(username)
+                .await?
+                .ok_or_else(|| anyhow!("Chat not found: {}", target))?;
+            client.join_chat(&chat).await?.or(Some(chat))
+        };
+
+        chat.map(|c| c.id().to_string())
+            .ok_or_else(|| anyhow!("Failed to join chat: {}", target))
+    }
 
-        // Load initial messages (20 by default)
-        self.load_chat_history(&chat, 20).await?;
+    /// Preview a public chat by username
+    async fn preview_chat(&self, target: &str) -> Result<RoomPreview> {
+        let username = target
+            .trim()
+            .trim_start_matches("https://")
+            .trim_start_matches("t.me/")
+            .trim_start_matches('@');
 
-        Ok(())
+        let client = self.client.lock().await;
+        let chat = client
+            .resolve_username(username)
+            .await?
+            .ok_or_else(|| anyhow!("Chat not found: {}", target))?;
+
+        Ok(RoomPreview {
+            id: chat.id().to_string(),
+            alias: chat.username().map(|u| format!("@{}", u)),
+            name: Some(chat.name().to_owned()),
+            topic: None,
+            avatar: None,
+            member_count: 0,
+            is_space: false,
+            joined: false,
+            via: Vec::new(),
+        })
     }
 
-    /// Load more historical events
-    async fn load_more_events(&self) -> Result<()> {
-        if let Some(chat_id) = self.selected_chat.lock().await.clone() {
-            let chat = self.find_chat(&chat_id).await?;
+    /// Create a new supergroup; Telegram groups are never end-to-end encrypted
+    async fn create_chat(&self, options: &NewChat) -> Result<String> {
+        let client = self.client.lock().await;
 
-            // Get count of existing events to know how many to skip
-            let event_count = self
-                .event_groups
-                .lock()
+        let updates = client
+            .invoke(&tl::functions::channels::CreateChannel {
+                broadcast: false,
+                megagroup: true,
+                for_import: false,
+                forum: false,
+                title: options.name.trim().to_owned(),
+                about: options.topic.trim().to_owned(),
+                geo_point: None,
+                address: None,
+                ttl_period: None,
+            })
+            .await?;
+
+        let channel = match updates {
+            tl::enums::Updates::Updates(updates) => {
+                updates.chats.into_iter().find_map(|chat| match chat {
+                    tl::enums::Chat::Channel(channel) => Some(channel),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Telegram did not return the created group"))?;
+
+        // Public groups are the ones with a username
+        if options.public && !options.alias.trim().is_empty() {
+            client
+                .invoke(&tl::functions::channels::UpdateUsername {
+                    channel: tl::types::InputChannel {
+                        channel_id: channel.id,
+                        access_hash: channel.access_hash.unwrap_or_default(),
+                    }
+                    .into(),
+                    username: options.alias.trim().trim_start_matches('@').to_owned(),
+                })
+                .await?;
+        }
+
+        self.known_chats.lock().insert(
+            channel.id.to_string(),
+            PackedChat {
+                ty: PackedType::Megagroup,
+                id: channel.id,
+                access_hash: channel.access_hash,
+            },
+        );
+
+        Ok(channel.id.to_string())
+    }
+
+    /// Resolve a username or phone number to a private chat
+    async fn create_direct_chat(&self, user: &str) -> Result<String> {
+        let user = user.trim();
+
+        // Already known from a user search
+        if self.known_chats.lock().contains_key(user) {
+            return Ok(user.to_owned());
+        }
+
+        let client = self.client.lock().await;
+
+        if user.starts_with('+') || user.chars().all(|c| c.is_ascii_digit()) {
+            // Phone numbers have to be imported as contacts first
+            let imported = client
+                .invoke(&tl::functions::contacts::ImportContacts {
+                    contacts: vec![
+                        tl::types::InputPhoneContact {
+                            client_id: 0,
+                            phone: user.to_owned(),
+                            first_name: user.to_owned(),
+                            last_name: String::new(),
+                        }
+                        .into(),
+                    ],
+                })
+                .await?;
+
+            let tl::enums::contacts::ImportedContacts::Contacts(imported) = imported;
+            imported
+                .users
                 .iter()
-                .map(|group| group.events.len())
-                .sum::<usize>();
+                .find_map(|u| self.remember_user(u))
+                .map(|profile| profile.id)
+                .ok_or_else(|| anyhow!("No Telegram account for {}", user))
+        } else {
+            let chat = client
+                .resolve_username(user.trim_start_matches('@'))
+                .await?
+                .ok_or_else(|| anyhow!("User not found: {}", user))?;
 
-            // Get client and fetch messages
-            let client = self.client.lock().await;
+            self.remember_chat(&chat);
+            Ok(chat.id().to_string())
+        }
+    }
+
+    /// Add a user to a basic group or supergroup
+    async fn invite_user(&self, chat_id: &str, user_id: &str) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?.pack();
+        let user = self
+            .find_chat(user_id)
+            .await?
+            .pack()
+            .try_to_input_user()
+            .ok_or_else(|| anyhow!("Not a user: {}", user_id))?;
+
+        let client = self.client.lock().await;
+
+        if let Some(channel) = chat.try_to_input_channel() {
+            client
+                .invoke(&tl::functions::channels::InviteToChannel {
+                    channel,
+                    users: vec![user],
+                })
+                .await?;
+        } else if let Some(chat_id) = chat.try_to_chat_id() {
+            client
+                .invoke(&tl::functions::messages::AddChatUser {
+                    chat_id,
+                    user_id: user,
+                    fwd_limit: 100,
+                })
+                .await?;
+        } else {
+            return Err(anyhow!("Users can only be invited to groups"));
+        }
+
+        Ok(())
+    }
+
+    /// Get a page of chat participants
+    async fn members(
+        &self,
+        chat_id: &str,
+        query: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<MemberPage> {
+        let chat = self.find_chat(chat_id).await?;
+        let client = self.client.lock().await;
 
-            // Create message iterator
-            let mut messages_vec = Vec::new();
-            let mut iter = client
-                .iter_messages(&chat)
-                .offset_id(event_count as i32)
-                .limit(20_usize);
+        // Supergroups and channels search and page on the server
+        if let Some(channel) = chat.pack().try_to_input_channel() {
+            let filter = if query.is_empty() {
+                tl::enums::ChannelParticipantsFilter::ChannelParticipantsRecent
+            } else {
+                tl::types::ChannelParticipantsSearch {
+                    q: query.to_owned(),
+                }
+                .into()
+            };
+            let response = client
+                .invoke(&tl::functions::channels::GetParticipants {
+                    channel,
+                    filter,
+                    offset: offset as i32,
+                    limit: limit as i32,
+                    hash: 0,
+                })
+                .await?;
+            let tl::enums::channels::ChannelParticipants::Participants(response) = response else {
+                return Ok(MemberPage::default());
+            };
 
-            // Fetch messages while holding the lock
-            while let Some(message) = iter.next().await? {
-                messages_vec.push(message);
+            let total = response.count as usize;
+            let has_more = offset + response.participants.len() < total;
+            let users: HashMap<i64, GrammersUser> = response
+                .users
+                .into_iter()
+                .map(GrammersUser::from_raw)
+                .map(|user| (user.id(), user))
+                .collect();
+
+            let mut members = Vec::with_capacity(response.participants.len());
+            for participant in response.participants {
+                let (user_id, role) = match participant {
+                    tl::enums::ChannelParticipant::Creator(p) => (p.user_id, MemberRole::Owner),
+                    tl::enums::ChannelParticipant::Admin(p) => (p.user_id, MemberRole::Admin),
+                    tl::enums::ChannelParticipant::Participant(p) => {
+                        (p.user_id, MemberRole::Member)
+                    }
+                    tl::enums::ChannelParticipant::ParticipantSelf(p) => {
+                        (p.user_id, MemberRole::Member)
+                    }
+                    _ => continue,
+                };
+                if let Some(user) = users.get(&user_id) {
+                    members.push(self.member(user, role));
+                }
             }
 
-            // Release the lock
-            drop(client);
+            return Ok(MemberPage {
+                members,
+                total: Some(total),
+                has_more,
+            });
+        }
 
-            // Process the messages without holding the lock
-            for message in messages_vec {
-                self.process_message(&message).await?;
+        // Basic groups return all of their (at most a few hundred) members at once
+        let query = query.to_lowercase();
+        let mut participants = client.iter_participants(&chat);
+        let mut matching = Vec::new();
+        while let Some(participant) = participants.next().await? {
+            let user = &participant.user;
+            if user.full_name().to_lowercase().contains(&query)
+                || user
+                    .username()
+                    .is_some_and(|name| name.to_lowercase().contains(&query))
+            {
+                matching.push(participant);
             }
         }
 
-        Ok(())
+        let total = matching.len();
+        let members = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|participant| {
+                let role = match participant.role {
+                    Role::Creator { .. } => MemberRole::Owner,
+                    Role::Admin { .. } => MemberRole::Admin,
+                    _ => MemberRole::Member,
+                };
+                self.member(&participant.user, role)
+            })
+            .collect();
+
+        Ok(MemberPage {
+            members,
+            total: Some(total),
+            has_more: offset + limit < total,
+        })
     }
 
-    /// Delete an event
-    async fn delete_event(&self, message_id: &str) -> Result<()> {
-        if let Some(chat_id) = self.selected_chat.lock().await.clone() {
-            // Find the chat
-            let chat = self.find_chat(&chat_id).await?;
+    /// Read the group's title and description
+    async fn chat_settings(&self, chat_id: &str) -> Result<ChatSettings> {
+        let chat = self.find_chat(chat_id).await?;
+        let packed = chat.pack();
+        let client = self.client.lock().await;
 
-            // Convert message_id to integer
-            let msg_id: i32 = message_id.parse()?;
+        let full = if let Some(channel) = packed.try_to_input_channel() {
+            Some(
+                client
+                    .invoke(&tl::functions::channels::GetFullChannel { channel })
+                    .await?,
+            )
+        } else if let Some(chat_id) = packed.try_to_chat_id() {
+            Some(
+                client
+                    .invoke(&tl::functions::messages::GetFullChat { chat_id })
+                    .await?,
+            )
+        } else {
+            None
+        };
 
-            // Delete the message - delete_messages takes the chat and messages as arguments
-            let client = self.client.lock().await;
-            client.delete_messages(&chat, &[msg_id]).await?;
+        let topic = match full {
+            Some(tl::enums::messages::ChatFull::Full(full)) => match full.full_chat {
+                tl::enums::ChatFull::Full(group) => group.about,
+                tl::enums::ChatFull::ChannelFull(channel) => channel.about,
+            },
+            None => String::new(),
+        };
+
+        Ok(ChatSettings {
+            name: chat.name().to_owned(),
+            topic,
+            avatar: None,
+            join_rule: None,
+            history_visibility: None,
+            encrypted: None,
+        })
+    }
+
+    /// Change the group title
+    async fn set_chat_name(&self, chat_id: &str, name: &str) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
+
+        if let Some(channel) = packed.try_to_input_channel() {
+            client
+                .invoke(&tl::functions::channels::EditTitle {
+                    channel,
+                    title: name.to_owned(),
+                })
+                .await?;
+        } else if let Some(chat_id) = packed.try_to_chat_id() {
+            client
+                .invoke(&tl::functions::messages::EditChatTitle {
+                    chat_id,
+                    title: name.to_owned(),
+                })
+                .await?;
+        } else {
+            return Err(anyhow!("Only groups and channels can be renamed"));
         }
 
         Ok(())
     }
 
-    /// Get current event groups
-    fn event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
-        Ok(self.event_groups.clone())
-    }
+    /// Change the group description
+    async fn set_chat_topic(&self, chat_id: &str, topic: &str) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
 
-    /// Get current user ID
-    fn self_id(&self) -> Arc<String> {
+        client
+            .invoke(&tl::functions::messages::EditChatAbout {
+                peer: packed.to_input_peer(),
+                about: topic.to_owned(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Upload a new group photo
+    async fn set_chat_avatar(&self, chat_id: &str, image: Vec<u8>) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
+
+        let file = Self::upload_file(&client, "avatar.jpg", &image).await?;
+        let photo: tl::enums::InputChatPhoto = tl::types::InputChatUploadedPhoto {
+            file: Some(file),
+            video: None,
+            video_start_ts: None,
+            video_emoji_markup: None,
+        }
+        .into();
+
+        if let Some(channel) = packed.try_to_input_channel() {
+            client
+                .invoke(&tl::functions::channels::EditPhoto { channel, photo })
+                .await?;
+        } else if let Some(chat_id) = packed.try_to_chat_id() {
+            client
+                .invoke(&tl::functions::messages::EditChatPhoto { chat_id, photo })
+                .await?;
+        } else {
+            return Err(anyhow!("Only groups and channels have a photo"));
+        }
+
+        Ok(())
+    }
+
+    /// Telegram groups have no join rules
+    async fn set_join_rule(&self, _chat_id: &str, _rule: JoinRule) -> Result<()> {
+        Err(anyhow!("Telegram does not support join rules"))
+    }
+
+    /// Telegram groups have no history visibility setting
+    async fn set_history_visibility(
+        &self,
+        _chat_id: &str,
+        _visibility: HistoryVisibility,
+    ) -> Result<()> {
+        Err(anyhow!("Telegram does not support history visibility"))
+    }
+
+    /// Telegram groups are never end-to-end encrypted
+    async fn enable_encryption(&self, _chat_id: &str) -> Result<()> {
+        Err(anyhow!("Telegram groups can't be end-to-end encrypted"))
+    }
+
+    /// Get moderation capabilities from our admin rights in the chat
+    async fn permissions(&self, chat_id: &str) -> Result<Permissions> {
+        let chat = self.find_chat(chat_id).await?;
+
+        // Private chats have nothing to moderate
+        if matches!(chat, GrammersChat::User(_)) {
+            return Ok(Permissions::default());
+        }
+
+        let client = self.client.lock().await;
+        let me = client.get_me().await?;
+        let rights = client.get_permissions(&chat, &me).await?;
+
+        // Channel admins have their own rights, creators may do anything and in basic
+        // groups only the creator appoints admins
+        let (ban_users, add_admins) = match &rights {
+            ParticipantPermissions::Channel(tl::enums::ChannelParticipant::Admin(admin)) => {
+                let tl::enums::ChatAdminRights::Rights(rights) = &admin.admin_rights;
+                (rights.ban_users, rights.add_admins)
+            }
+            _ => (rights.is_admin(), rights.is_creator()),
+        };
+
+        Ok(Permissions {
+            kick: ban_users,
+            ban: ban_users,
+            mute: ban_users,
+            change_power_levels: add_admins,
+            power_level: None,
+        })
+    }
+
+    /// Remove a user from a group; Telegram does not record a reason
+    async fn kick_user(&self, chat_id: &str, user_id: &str, _reason: Option<&str>) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?;
+        let user = self.find_chat(user_id).await?;
+        let client = self.client.lock().await;
+
+        client.kick_participant(&chat, &user).await?;
+        Ok(())
+    }
+
+    /// Ban a user by revoking their right to view messages
+    async fn ban_user(&self, chat_id: &str, user_id: &str, _reason: Option<&str>) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?;
+        let user = self.find_chat(user_id).await?;
+        let client = self.client.lock().await;
+
+        client
+            .set_banned_rights(&chat, &user)
+            .view_messages(false)
+            .await?;
+        Ok(())
+    }
+
+    /// Restore all default rights of a banned user
+    async fn unban_user(&self, chat_id: &str, user_id: &str, _reason: Option<&str>) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?;
+        let user = self.find_chat(user_id).await?;
+        let client = self.client.lock().await;
+
+        client.set_banned_rights(&chat, &user).await?;
+        Ok(())
+    }
+
+    /// Restrict or restore a user's right to send messages
+    async fn mute_user(&self, chat_id: &str, user_id: &str, muted: bool) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?;
+        let user = self.find_chat(user_id).await?;
+        let client = self.client.lock().await;
+
+        client
+            .set_banned_rights(&chat, &user)
+            .send_messages(!muted)
+            .await?;
+        Ok(())
+    }
+
+    /// Telegram has no power levels: moderator level and above promotes to admin
+    async fn set_power_level(&self, chat_id: &str, user_id: &str, level: i64) -> Result<()> {
+        let chat = self.find_chat(chat_id).await?;
+        let user = self.find_chat(user_id).await?;
+        let client = self.client.lock().await;
+
+        let promote = level >= 50;
+        client
+            .set_admin_rights(&chat, &user)
+            .delete_messages(promote)
+            .ban_users(promote)
+            .invite_users(promote)
+            .pin_messages(promote)
+            .await?;
+        Ok(())
+    }
+
+    /// Telegram adds users to groups directly, and requests to join chats we
+    /// manage aren't listed
+    async fn invites(&self) -> Result<Vec<Invite>> {
+        Ok(Vec::new())
+    }
+
+    async fn accept_invite(&self, _chat_id: &str) -> Result<()> {
+        Err(anyhow!(
+            "Invites and join requests aren't supported on Telegram"
+        ))
+    }
+
+    async fn decline_invite(&self, _chat_id: &str, _block_inviter: bool) -> Result<()> {
+        Err(anyhow!(
+            "Invites and join requests aren't supported on Telegram"
+        ))
+    }
+
+    async fn ignored_users(&self) -> Result<Vec<UserProfile>> {
+        // Bots can't block, so they only hide users locally
+        if self.is_bot {
+            let names = self.user_names.lock();
+            return Ok(self
+                .blocked
+                .lock()
+                .iter()
+                .map(|id| UserProfile {
+                    id: id.to_string(),
+                    display_name: names.get(id).cloned(),
+                    avatar: None,
+                })
+                .collect());
+        }
+
+        self.fetch_blocked().await
+    }
+
+    /// Block the user, which also hides their messages in groups here
+    async fn ignore_user(&self, user_id: &str) -> Result<()> {
+        let user = self.find_chat(user_id).await?;
+        if self.is_bot {
+            self.blocked.lock().insert(user.id());
+            return Ok(());
+        }
+        let client = self.client.lock().await;
+
+        client
+            .invoke(&tl::functions::contacts::Block {
+                my_stories_from: false,
+                id: user.pack().to_input_peer(),
+            })
+            .await?;
+        self.blocked.lock().insert(user.id());
+        Ok(())
+    }
+
+    async fn unignore_user(&self, user_id: &str) -> Result<()> {
+        let user = self.find_chat(user_id).await?;
+        if self.is_bot {
+            self.blocked.lock().remove(&user.id());
+            return Ok(());
+        }
+        let client = self.client.lock().await;
+
+        client
+            .invoke(&tl::functions::contacts::Unblock {
+                my_stories_from: false,
+                id: user.pack().to_input_peer(),
+            })
+            .await?;
+        self.blocked.lock().remove(&user.id());
+        Ok(())
+    }
+
+    /// Search contacts and global users by name or username
+    async fn search_users(&self, query: &str) -> Result<Vec<UserProfile>> {
+        let client = self.client.lock().await;
+
+        let tl::enums::contacts::Found::Found(found) = client
+            .invoke(&tl::functions::contacts::Search {
+                q: query.to_owned(),
+                limit: 10,
+            })
+            .await?;
+
+        Ok(found
+            .users
+            .iter()
+            .filter_map(|user| self.remember_user(user))
+            .collect())
+    }
+
+    /// Telegram has no browsable directory, so only exact usernames are found
+    async fn search_directory(
+        &self,
+        query: &str,
+        _server: Option<&str>,
+        _since: Option<&str>,
+    ) -> Result<DirectoryPage> {
+        let rooms = match self.preview_chat(query).await {
+            Ok(preview) => vec![preview],
+            Err(_) => Vec::new(),
+        };
+
+        Ok(DirectoryPage {
+            rooms,
+            next_batch: None,
+            total_estimate: None,
+        })
+    }
+
+    /// Select a chat and load its messages
+    async fn select_chat(&self, chat_id: &str) -> Result<()> {
+        // Make sure the requested chat exists
+        self.find_chat(chat_id).await?;
+
+        // Reset state
+        self.event_groups.lock().clear();
+        self.processed_events.lock().await.clear();
+
+        // Blocked users are still listed in groups, so their messages are filtered here
+        if !self.is_bot
+            && let Err(e) = self.fetch_blocked().await
+        {
+            log::warn!("Failed to fetch blocked users: {}", e);
+        }
+
+        // Set selected chat
+        *self.selected_chat.lock().await = Some(chat_id.to_owned());
+
+        // Bots show what they received since signing in
+        if self.is_bot {
+            let history = {
+                let mut bot_chats = self.bot_chats.lock();
+                if let Some(chat) = bot_chats.chats.iter_mut().find(|c| c.id == chat_id) {
+                    chat.unread = false;
+                }
+                bot_chats.history.get(chat_id).cloned().unwrap_or_default()
+            };
+            for message in &history {
+                self.process_message(message).await?;
+            }
+            return Ok(());
+        }
+
+        // Load initial messages
+        self.load_chat_history(chat_id, HISTORY_PAGE).await?;
+
+        Ok(())
+    }
+
+    /// Load more historical events
+    async fn load_more_events(&self) -> Result<()> {
+        // Bots can't read history beyond what they received
+        if self.is_bot {
+            return Ok(());
+        }
+
+        if let Some(chat_id) = self.selected_chat.lock().await.clone() {
+            // History pages start before the oldest message loaded so far
+            let offset_id = self
+                .event_groups
+                .lock()
+                .iter()
+                .flat_map(|group| &group.events)
+                .filter_map(|event| event.id.parse::<i32>().ok())
+                .min()
+                .unwrap_or(0);
+
+            let messages_vec = self
+                .fetch_history(&chat_id, offset_id, HISTORY_PAGE)
+                .await?;
+
+            // Process the messages without holding the lock
+            for message in messages_vec {
+                self.process_message(&message).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete an event
+    async fn delete_event(&self, message_id: &str) -> Result<()> {
+        if let Some(chat_id) = self.selected_chat.lock().await.clone() {
+            // Find the chat
+            let chat = self.find_chat(&chat_id).await?;
+
+            // Convert message_id to integer
+            let msg_id: i32 = message_id.parse()?;
+
+            // Delete the message - delete_messages takes the chat and messages as arguments
+            let client = self.client.lock().await;
+            client.delete_messages(&chat, &[msg_id]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get current event groups
+    fn event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
+        Ok(self.event_groups.clone())
+    }
+
+    /// Send a plain text message, into the topic when one is selected
+    async fn send_message(&self, chat_id: &str, text: &str) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let (_, topic) = split_topic(chat_id);
+        let client = self.client.lock().await;
+        client
+            .send_message(packed, InputMessage::text(text).reply_to(topic))
+            .await?;
+        Ok(())
+    }
+
+    /// Send a typing action, or cancel it
+    async fn set_typing(&self, chat_id: &str, typing: bool) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let (_, topic) = split_topic(chat_id);
+        let client = self.client.lock().await;
+
+        let action = if typing {
+            tl::enums::SendMessageAction::SendMessageTypingAction
+        } else {
+            tl::enums::SendMessageAction::SendMessageCancelAction
+        };
+        client
+            .invoke(&tl::functions::messages::SetTyping {
+                peer: packed.to_input_peer(),
+                top_msg_id: topic,
+                action,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Get who is typing, as of the last sync
+    fn typing(&self) -> Result<TypingUsers> {
+        Ok(self.typing.clone())
+    }
+
+    /// Get statuses from dialogs, fetched users and status updates
+    fn presence(&self) -> Result<Arc<Mutex<HashMap<String, PresenceInfo>>>> {
+        Ok(self.presence.clone())
+    }
+
+    /// Get chat photos downloaded since the chats were listed
+    fn chat_avatars(&self) -> Result<ChatAvatars> {
+        Ok(self.chat_avatars.clone())
+    }
+
+    /// Hide or show our last-seen time through the privacy settings, keeping the
+    /// exceptions and any contacts-only choice already made
+    async fn share_presence(&self, share: bool) -> Result<()> {
+        // Bots have neither privacy settings nor a last-seen time
+        if self.is_bot {
+            return Ok(());
+        }
+
+        let client = self.client.lock().await;
+
+        let tl::enums::account::PrivacyRules::Rules(current) = client
+            .invoke(&tl::functions::account::GetPrivacy {
+                key: tl::enums::InputPrivacyKey::StatusTimestamp,
+            })
+            .await?;
+        if let Some(rules) = privacy_rules(current, share) {
+            client
+                .invoke(&tl::functions::account::SetPrivacy {
+                    key: tl::enums::InputPrivacyKey::StatusTimestamp,
+                    rules,
+                })
+                .await?;
+        }
+        client
+            .invoke(&tl::functions::account::UpdateStatus { offline: !share })
+            .await?;
+        Ok(())
+    }
+
+    /// Update our status only, as the privacy settings belong to the account
+    async fn set_online(&self, online: bool) -> Result<()> {
+        if self.is_bot {
+            return Ok(());
+        }
+
+        let client = self.client.lock().await;
+        client
+            .invoke(&tl::functions::account::UpdateStatus { offline: !online })
+            .await?;
+        Ok(())
+    }
+
+    /// Mark history as read with `read_history` and clear the unread mark
+    async fn mark_read(&self, chat_id: &str, event_id: Option<&str>) -> Result<()> {
+        // Bots have no read state on the server
+        if self.is_bot {
+            if let Some(chat) = self
+                .bot_chats
+                .lock()
+                .chats
+                .iter_mut()
+                .find(|c| c.id == chat_id)
+            {
+                chat.unread = false;
+            }
+            return Ok(());
+        }
+
+        let packed = self.find_chat(chat_id).await?.pack();
+        // A max ID of 0 reads everything
+        let max_id = event_id.map(str::parse).transpose()?.unwrap_or(0);
+        let client = self.client.lock().await;
+
+        // Topics are read like a discussion, up to the newest message without an ID
+        if let (_, Some(topic)) = split_topic(chat_id) {
+            client
+                .invoke(&tl::functions::messages::ReadDiscussion {
+                    peer: packed.to_input_peer(),
+                    msg_id: topic,
+                    read_max_id: if max_id == 0 { i32::MAX } else { max_id },
+                })
+                .await?;
+
+            let mut forum_topics = self.forum_topics.lock();
+            let topic = forum_topics
+                .by_forum
+                .get_mut(&packed.id)
+                .and_then(|topics| topics.iter_mut().find(|topic| topic.id == chat_id));
+            if let Some(topic) = topic {
+                topic.unread = 0;
+            }
+            return Ok(());
+        }
+
+        if let Some(channel) = packed.try_to_input_channel() {
+            client
+                .invoke(&tl::functions::channels::ReadHistory { channel, max_id })
+                .await?;
+        } else {
+            client
+                .invoke(&tl::functions::messages::ReadHistory {
+                    peer: packed.to_input_peer(),
+                    max_id,
+                })
+                .await?;
+        }
+
+        if event_id.is_none() {
+            client
+                .invoke(&tl::functions::messages::ReadMentions {
+                    peer: packed.to_input_peer(),
+                    top_msg_id: None,
+                })
+                .await?;
+            client
+                .invoke(&tl::functions::messages::MarkDialogUnread {
+                    unread: false,
+                    peer: tl::types::InputDialogPeer {
+                        peer: packed.to_input_peer(),
+                    }
+                    .into(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Set or clear the dialog's unread mark
+    async fn set_unread(&self, chat_id: &str, unread: bool) -> Result<()> {
+        if self.is_bot {
+            if let Some(chat) = self
+                .bot_chats
+                .lock()
+                .chats
+                .iter_mut()
+                .find(|c| c.id == chat_id)
+            {
+                chat.unread = unread;
+            }
+            return Ok(());
+        }
+
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
+
+        client
+            .invoke(&tl::functions::messages::MarkDialogUnread {
+                unread,
+                peer: tl::types::InputDialogPeer {
+                    peer: packed.to_input_peer(),
+                }
+                .into(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Pin the dialog for favourites and archive it for low priority
+    async fn set_chat_tag(&self, chat_id: &str, tag: Option<ChatTag>) -> Result<()> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
+
+        client
+            .invoke(&tl::functions::messages::ToggleDialogPin {
+                pinned: tag == Some(ChatTag::Favourite),
+                peer: tl::types::InputDialogPeer {
+                    peer: packed.to_input_peer(),
+                }
+                .into(),
+            })
+            .await?;
+        client
+            .invoke(&tl::functions::folders::EditPeerFolders {
+                folder_peers: vec![
+                    tl::types::InputFolderPeer {
+                        peer: packed.to_input_peer(),
+                        folder_id: if tag == Some(ChatTag::LowPriority) {
+                            ARCHIVE_FOLDER
+                        } else {
+                            0
+                        },
+                    }
+                    .into(),
+                ],
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Last incoming message the user has read, from the dialog
+    async fn read_marker(&self, chat_id: &str) -> Result<Option<String>> {
+        let packed = self.find_chat(chat_id).await?.pack();
+        let client = self.client.lock().await;
+
+        let tl::enums::messages::PeerDialogs::Dialogs(dialogs) = client
+            .invoke(&tl::functions::messages::GetPeerDialogs {
+                peers: vec![
+                    tl::types::InputDialogPeer {
+                        peer: packed.to_input_peer(),
+                    }
+                    .into(),
+                ],
+            })
+            .await?;
+
+        Ok(dialogs.dialogs.into_iter().find_map(|dialog| match dialog {
+            tl::enums::Dialog::Dialog(raw) if raw.read_inbox_max_id > 0 => {
+                Some(raw.read_inbox_max_id.to_string())
+            }
+            _ => None,
+        }))
+    }
+
+    /// Loaded posts with comments and messages with replies
+    async fn threads(&self, _chat_id: &str, _from: Option<&str>) -> Result<ThreadPage> {
+        let threads = self
+            .event_groups
+            .lock()
+            .iter()
+            .flat_map(|group| {
+                group
+                    .events
+                    .iter()
+                    .filter(|event| event.thread.is_some())
+                    .map(|event| ThreadRoot {
+                        sender: group.display_name.clone(),
+                        root: event.clone(),
+                    })
+            })
+            .collect();
+
+        // Only loaded messages are known, so there is never a next page
+        Ok(ThreadPage {
+            threads,
+            next_batch: None,
+        })
+    }
+
+    /// Load the latest comments on a post, or replies to a message
+    async fn open_thread(&self, root_id: &str) -> Result<()> {
+        let chat_id = self
+            .selected_chat
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| anyhow!("No chat selected"))?;
+        let chat = self.find_chat(&chat_id).await?.pack();
+        let root = root_id.parse()?;
+
+        self.thread_groups.lock().clear();
+        *self.open_thread.lock() = Some((chat, root));
+
+        let replies = self.fetch_replies(chat, root, 0, HISTORY_PAGE).await?;
+        for reply in &replies {
+            self.add_message(&self.thread_groups, reply);
+        }
+        Ok(())
+    }
+
+    /// Load older comments of the open thread
+    async fn load_more_thread_events(&self) -> Result<bool> {
+        let Some((chat, root)) = *self.open_thread.lock() else {
+            return Ok(false);
+        };
+        let oldest = self
+            .thread_groups
+            .lock()
+            .iter()
+            .flat_map(|group| &group.events)
+            .filter_map(|event| event.id.parse::<i32>().ok())
+            .min();
+        let Some(oldest) = oldest else {
+            return Ok(false);
+        };
+
+        let replies = self.fetch_replies(chat, root, oldest, HISTORY_PAGE).await?;
+        for reply in &replies {
+            self.add_message(&self.thread_groups, reply);
+        }
+        Ok(replies.len() == HISTORY_PAGE)
+    }
+
+    fn thread_event_groups(&self) -> Result<Arc<Mutex<Vec<EventGroup>>>> {
+        Ok(self.thread_groups.clone())
+    }
+
+    /// Comment on a post through its discussion group, or reply in a supergroup
+    async fn send_thread_reply(&self, root_id: &str, text: &str) -> Result<()> {
+        let chat = match *self.open_thread.lock() {
+            Some((chat, _)) => chat,
+            None => return Err(anyhow!("No thread open")),
+        };
+        let root = root_id.parse()?;
+        let client = self.client.lock().await;
+
+        // Comments live in the linked group, under a copy of the post
+        let tl::enums::messages::DiscussionMessage::Message(discussion) = client
+            .invoke(&tl::functions::messages::GetDiscussionMessage {
+                peer: chat.to_input_peer(),
+                msg_id: root,
+            })
+            .await?;
+        let chats = ChatMap::new(discussion.users, discussion.chats);
+        let top = discussion
+            .messages
+            .into_iter()
+            .find_map(|message| GrammersMessage::from_raw(&client, message, &chats))
+            .ok_or_else(|| anyhow!("Discussion not found"))?;
+
+        let sent = client
+            .send_message(
+                top.chat(),
+                InputMessage::text(text).reply_to(Some(top.id())),
+            )
+            .await?;
+        drop(client);
+
+        self.add_message(&self.thread_groups, &sent);
+        Ok(())
+    }
+
+    /// Accept a `tg://login` link shown by a device signing in
+    async fn approve_login(&self, code: &str, progress: Arc<Mutex<LoginApproval>>) -> Result<()> {
+        let token = code
+            .trim()
+            .strip_prefix("tg://login?token=")
+            .ok_or_else(|| anyhow!("Not a Telegram login link"))?;
+        let token = URL_SAFE_NO_PAD.decode(token)?;
+
+        let client = self.client.lock().await;
+        client
+            .invoke(&tl::functions::auth::AcceptLoginToken { token })
+            .await?;
+
+        *progress.lock() = LoginApproval::Done;
+        Ok(())
+    }
+
+    fn approves_logins(&self) -> bool {
+        true
+    }
+
+    /// Telegram has no keyword notifications
+    async fn keywords(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    async fn add_keyword(&self, _keyword: &str) -> Result<()> {
+        Err(anyhow!(
+            "Keyword notifications are not supported for Telegram"
+        ))
+    }
+
+    async fn remove_keyword(&self, _keyword: &str) -> Result<()> {
+        Err(anyhow!(
+            "Keyword notifications are not supported for Telegram"
+        ))
+    }
+
+    async fn chat_notify_mode(&self, _chat_id: &str) -> Result<Option<NotifyMode>> {
+        Err(anyhow!(
+            "Notification settings are not supported for Telegram chats"
+        ))
+    }
+
+    async fn set_chat_notify_mode(&self, _chat_id: &str, _mode: Option<NotifyMode>) -> Result<()> {
+        Err(anyhow!(
+            "Notification settings are not supported for Telegram chats"
+        ))
+    }
+
+    /// List the account's authorizations
+    async fn sessions(&self) -> Result<Vec<Session>> {
+        let client = self.client.lock().await;
+        let tl::enums::account::Authorizations::Authorizations(result) = client
+            .invoke(&tl::functions::account::GetAuthorizations {})
+            .await?;
+
+        Ok(result
+            .authorizations
+            .into_iter()
+            .map(|tl::enums::Authorization::Authorization(auth)| Session {
+                id: auth.hash.to_string(),
+                name: format!(
+                    "{} {} on {}",
+                    auth.app_name, auth.app_version, auth.device_model
+                ),
+                last_seen: u64::try_from(auth.date_active).ok(),
+                last_ip: Some(auth.ip).filter(|ip| !ip.is_empty()),
+                current: auth.current,
+                verified: None,
+            })
+            .collect())
+    }
+
+    async fn rename_session(&self, _session_id: &str, _name: &str) -> Result<()> {
+        Err(anyhow!("Telegram sessions can't be renamed"))
+    }
+
+    /// Terminate other authorizations; Telegram needs no password for this
+    async fn sign_out_sessions(
+        &self,
+        session_ids: &[String],
+        _password: Option<&str>,
+    ) -> Result<()> {
+        let client = self.client.lock().await;
+        for session_id in session_ids {
+            let hash = session_id.parse()?;
+            client
+                .invoke(&tl::functions::account::ResetAuthorization { hash })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Log out on the server and remove the session file
+    async fn logout(&self) -> Result<()> {
+        let client = self.client.lock().await;
+        client.sign_out().await?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.client_session.session_path.exists() {
+            std::fs::remove_file(&self.client_session.session_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get current user ID
+    fn self_id(&self) -> Arc<String> {
         self.user_id.lock().clone()
     }
 }
+
+/// Block a run of formatted Telegram text belongs to
+#[derive(PartialEq)]
+enum EntityBlock {
+    Paragraph,
+    Code(Option<String>),
+    Quote,
+}
+
+/// Build rich text from a message's entities, whose ranges count UTF-16 units
+fn rich_text(text: &str, entities: &[tl::enums::MessageEntity]) -> Option<RichText> {
+    use tl::enums::MessageEntity as Entity;
+
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let ranges: Vec<(usize, usize, &Entity)> = entities
+        .iter()
+        .filter_map(|entity| {
+            let (offset, length) = entity_range(entity)?;
+            Some((offset, (offset + length).min(units.len()), entity))
+        })
+        .collect();
+
+    // Split the text wherever an entity starts or ends
+    let mut bounds = vec![0, units.len()];
+    bounds.extend(ranges.iter().flat_map(|(start, end, _)| [*start, *end]));
+    bounds.retain(|bound| *bound <= units.len());
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut blocks = Vec::new();
+    let mut current: Option<(EntityBlock, Vec<Span>)> = None;
+    for window in bounds.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let mut style = SpanStyle::default();
+        let mut block = EntityBlock::Paragraph;
+
+        for (entity_start, entity_end, entity) in &ranges {
+            if *entity_start > start || end > *entity_end {
+                continue;
+            }
+            let entity_text = || String::from_utf16_lossy(&units[*entity_start..*entity_end]);
+            match entity {
+                Entity::Bold(_) => style.bold = true,
+                Entity::Italic(_) => style.italic = true,
+                Entity::Underline(_) => style.underline = true,
+                Entity::Strike(_) => style.strikethrough = true,
+                Entity::Code(_) => style.code = true,
+                Entity::Spoiler(_) => style.spoiler = true,
+                Entity::TextUrl(url) => {
+                    style.link = Some(url.url.clone()).filter(|url| is_safe_link(url))
+                }
+                Entity::Url(_) => {
+                    // Telegram also detects bare domains
+                    let url = entity_text();
+                    style.link = Some(if url.contains("://") {
+                        url
+                    } else {
+                        format!("https://{}", url)
+                    })
+                    .filter(|url| is_safe_link(url));
+                }
+                Entity::Email(_) => style.link = Some(format!("mailto:{}", entity_text())),
+                Entity::Pre(pre) => {
+                    block = EntityBlock::Code(Some(pre.language.clone()).filter(|l| !l.is_empty()))
+                }
+                Entity::Blockquote(_) => block = EntityBlock::Quote,
+                Entity::MentionName(mention) => style.mention = Some(mention.user_id.to_string()),
+                Entity::Mention(_) => style.mention = Some(entity_text()),
+                _ => {}
+            }
+        }
+
+        let span = Span {
+            text: String::from_utf16_lossy(&units[start..end]),
+            style,
+        };
+        match &mut current {
+            Some((kind, spans)) if *kind == block => spans.push(span),
+            _ => {
+                blocks.extend(current.take().and_then(entity_block));
+                current = Some((block, vec![span]));
+            }
+        }
+    }
+    blocks.extend(current.and_then(entity_block));
+
+    let rich_text = RichText { blocks };
+    (!rich_text.is_plain()).then_some(rich_text)
+}
+
+/// UTF-16 offset and length of the entities that affect formatting
+fn entity_range(entity: &tl::enums::MessageEntity) -> Option<(usize, usize)> {
+    use tl::enums::MessageEntity as Entity;
+
+    let (offset, length) = match entity {
+        Entity::Bold(e) => (e.offset, e.length),
+        Entity::Italic(e) => (e.offset, e.length),
+        Entity::Underline(e) => (e.offset, e.length),
+        Entity::Strike(e) => (e.offset, e.length),
+        Entity::Code(e) => (e.offset, e.length),
+        Entity::Spoiler(e) => (e.offset, e.length),
+        Entity::TextUrl(e) => (e.offset, e.length),
+        Entity::Url(e) => (e.offset, e.length),
+        Entity::Email(e) => (e.offset, e.length),
+        Entity::Pre(e) => (e.offset, e.length),
+        Entity::Blockquote(e) => (e.offset, e.length),
+        Entity::MentionName(e) => (e.offset, e.length),
+        Entity::Mention(e) => (e.offset, e.length),
+        _ => return None,
+    };
+    Some((usize::try_from(offset).ok()?, usize::try_from(length).ok()?))
+}
+
+/// Turn a run of spans into a block; text between blocks loses its edge newlines
+fn entity_block((kind, mut spans): (EntityBlock, Vec<Span>)) -> Option<Block> {
+    if let Some(first) = spans.first_mut() {
+        first.text = first.text.trim_start_matches('\n').to_owned();
+    }
+    if let Some(last) = spans.last_mut() {
+        last.text = last.text.trim_end_matches('\n').to_owned();
+    }
+    spans.retain(|span| !span.text.is_empty());
+    if spans.is_empty() {
+        return None;
+    }
+
+    Some(match kind {
+        EntityBlock::Paragraph => Block::Paragraph(spans),
+        EntityBlock::Code(language) => Block::Code {
+            language,
+            code: spans.into_iter().map(|span| span.text).collect(),
+        },
+        EntityBlock::Quote => Block::Quote(vec![Block::Paragraph(spans)]),
+    })
+}
+
+/// Download the small profile photo of a user or chat, if it has one
+async fn download_photo(client: &GrammersClient, chat: &GrammersChat) -> Result<Option<Arc<[u8]>>> {
+    let Some(downloadable) = chat.photo_downloadable(false) else {
+        return Ok(None);
+    };
+    let mut download = client.iter_download(&downloadable);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = download.next().await? {
+        bytes.extend(chunk);
+    }
+    Ok(Some(Arc::from(bytes)))
+}
+
+/// Wait until another app accepts the exported login token
+async fn wait_for_login_token(client: &GrammersClient) -> Result<()> {
+    loop {
+        if let Update::Raw(tl::enums::Update::LoginToken) = client.next_update().await? {
+            return Ok(());
+        }
+    }
+}
+
+/// Whether signing in stopped at the two-step verification password
+fn is_password_needed(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<InvocationError>().is_some_and(
+        |e| matches!(e, InvocationError::Rpc(rpc) if rpc.is("SESSION_PASSWORD_NEEDED")),
+    )
+}
+
+/// Forum topics are selected like chats, as `<chat ID>/<topic ID>`
+fn topic_chat_id(chat_id: &str, topic: i32) -> String {
+    format!("{}/{}", chat_id, topic)
+}
+
+/// Split a chat ID into the chat and the forum topic, if it names one
+fn split_topic(chat_id: &str) -> (&str, Option<i32>) {
+    match chat_id.split_once('/') {
+        Some((chat_id, topic)) => (chat_id, topic.parse().ok()),
+        None => (chat_id, None),
+    }
+}
+
+/// Topic a forum message was posted in; `None` for General and other chats
+fn message_topic(message: &GrammersMessage) -> Option<i32> {
+    match &message.raw.reply_to {
+        Some(tl::enums::MessageReplyHeader::Header(header)) if header.forum_topic => {
+            header.reply_to_top_id.or(header.reply_to_msg_id)
+        }
+        _ => None,
+    }
+}
+
+fn is_forum(chat: &GrammersChat) -> bool {
+    matches!(
+        chat,
+        GrammersChat::Group(group)
+            if matches!(&group.raw, tl::enums::Chat::Channel(channel) if channel.forum)
+    )
+}
+
+/// Wrap the messages of a raw history response
+fn messages_from_raw(
+    client: &GrammersClient,
+    messages: tl::enums::messages::Messages,
+) -> Vec<GrammersMessage> {
+    let (messages, users, chats) = match messages {
+        tl::enums::messages::Messages::Messages(page) => (page.messages, page.users, page.chats),
+        tl::enums::messages::Messages::Slice(page) => (page.messages, page.users, page.chats),
+        tl::enums::messages::Messages::ChannelMessages(page) => {
+            (page.messages, page.users, page.chats)
+        }
+        tl::enums::messages::Messages::NotModified(_) => return Vec::new(),
+    };
+
+    let chats = ChatMap::new(users, chats);
+    messages
+        .into_iter()
+        .filter_map(|message| GrammersMessage::from_raw(client, message, &chats))
+        .collect()
+}
+
+fn chat_kind(chat: &GrammersChat) -> ChatKind {
+    match chat {
+        GrammersChat::User(user) if user.is_bot() => ChatKind::Bot,
+        GrammersChat::User(_) => ChatKind::Direct,
+        GrammersChat::Group(_) => ChatKind::Group,
+        GrammersChat::Channel(_) => ChatKind::Channel,
+    }
+}
+
+/// Who a privacy setting applies to before its exceptions
+#[derive(Debug, PartialEq)]
+enum PrivacyBase {
+    Everybody,
+    /// Contacts only, sent as "allow contacts" followed by "disallow all"
+    Contacts,
+    Nobody,
+}
+
+impl PrivacyBase {
+    fn of(rules: &[tl::enums::PrivacyRule]) -> Self {
+        use tl::enums::PrivacyRule as Rule;
+
+        if rules
+            .iter()
+            .any(|rule| matches!(rule, Rule::PrivacyValueAllowAll))
+        {
+            Self::Everybody
+        } else if rules
+            .iter()
+            .any(|rule| matches!(rule, Rule::PrivacyValueAllowContacts))
+        {
+            Self::Contacts
+        } else {
+            Self::Nobody
+        }
+    }
+}
+
+/// Rules to set for last-seen time, or `None` when `current` already matches `share`.
+///
+/// Hiding turns the base rule into "nobody" and showing turns "nobody" into
+/// "everybody"; a contacts-only base already shows, and the exceptions always stay.
+fn privacy_rules(
+    current: tl::types::account::PrivacyRules,
+    share: bool,
+) -> Option<Vec<tl::enums::InputPrivacyRule>> {
+    use tl::enums::{InputPrivacyRule as Input, PrivacyRule as Rule};
+
+    let visible = PrivacyBase::of(&current.rules) != PrivacyBase::Nobody;
+    if visible == share {
+        return None;
+    }
+
+    let users: HashMap<i64, tl::enums::InputUser> = current
+        .users
+        .into_iter()
+        .map(GrammersUser::from_raw)
+        .filter_map(|user| Some((user.id(), user.pack().try_to_input_user()?)))
+        .collect();
+    let input_users = |ids: Vec<i64>| -> Vec<tl::enums::InputUser> {
+        ids.iter().filter_map(|id| users.get(id).cloned()).collect()
+    };
+
+    let mut rules: Vec<Input> = current
+        .rules
+        .into_iter()
+        .filter_map(|rule| match rule {
+            Rule::PrivacyValueAllowUsers(rule) => Some(Input::InputPrivacyValueAllowUsers(
+                tl::types::InputPrivacyValueAllowUsers {
+                    users: input_users(rule.users),
+                },
+            )),
+            Rule::PrivacyValueDisallowUsers(rule) => Some(Input::InputPrivacyValueDisallowUsers(
+                tl::types::InputPrivacyValueDisallowUsers {
+                    users: input_users(rule.users),
+                },
+            )),
+            Rule::PrivacyValueAllowChatParticipants(rule) => {
+                Some(Input::InputPrivacyValueAllowChatParticipants(
+                    tl::types::InputPrivacyValueAllowChatParticipants { chats: rule.chats },
+                ))
+            }
+            Rule::PrivacyValueDisallowChatParticipants(rule) => {
+                Some(Input::InputPrivacyValueDisallowChatParticipants(
+                    tl::types::InputPrivacyValueDisallowChatParticipants { chats: rule.chats },
+                ))
+            }
+            Rule::PrivacyValueAllowContacts if share => Some(Input::InputPrivacyValueAllowContacts),
+            Rule::PrivacyValueDisallowContacts if share => {
+                Some(Input::InputPrivacyValueDisallowContacts)
+            }
+            Rule::PrivacyValueAllowCloseFriends if share => {
+                Some(Input::InputPrivacyValueAllowCloseFriends)
+            }
+            Rule::PrivacyValueAllowPremium if share => Some(Input::InputPrivacyValueAllowPremium),
+            // The base rule is replaced below
+            _ => None,
+        })
+        .collect();
+
+    rules.push(if share {
+        Input::InputPrivacyValueAllowAll
+    } else {
+        Input::InputPrivacyValueDisallowAll
+    });
+    Some(rules)
+}
+
+/// Convert a Telegram user status; vague ones like "recently" have no timestamp
+fn presence_info(status: &tl::enums::UserStatus) -> PresenceInfo {
+    match status {
+        tl::enums::UserStatus::Online(online) => PresenceInfo {
+            presence: Presence::Online,
+            last_seen: Some(online.expires as u64),
+        },
+        tl::enums::UserStatus::Offline(offline) => PresenceInfo {
+            presence: Presence::Offline,
+            last_seen: Some(offline.was_online as u64),
+        },
+        _ => PresenceInfo::default(),
+    }
+}
+
+/// Bare ID of a user, group or channel peer
+fn peer_id(peer: &tl::enums::Peer) -> i64 {
+    match peer {
+        tl::enums::Peer::User(user) => user.user_id,
+        tl::enums::Peer::Chat(chat) => chat.chat_id,
+        tl::enums::Peer::Channel(channel) => channel.channel_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tl::enums::{InputPrivacyRule as Input, PrivacyRule as Rule};
+
+    fn current(rules: Vec<Rule>) -> tl::types::account::PrivacyRules {
+        tl::types::account::PrivacyRules {
+            rules,
+            chats: Vec::new(),
+            users: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn privacy_base_classifies_rules() {
+        assert_eq!(
+            PrivacyBase::of(&[Rule::PrivacyValueAllowAll]),
+            PrivacyBase::Everybody
+        );
+        assert_eq!(
+            PrivacyBase::of(&[
+                Rule::PrivacyValueAllowContacts,
+                Rule::PrivacyValueDisallowAll
+            ]),
+            PrivacyBase::Contacts
+        );
+        assert_eq!(
+            PrivacyBase::of(&[Rule::PrivacyValueDisallowAll]),
+            PrivacyBase::Nobody
+        );
+        assert_eq!(PrivacyBase::of(&[]), PrivacyBase::Nobody);
+    }
+
+    #[test]
+    fn privacy_rules_for_everybody() {
+        let rules = || current(vec![Rule::PrivacyValueAllowAll]);
+        assert_eq!(privacy_rules(rules(), true), None);
+        assert_eq!(
+            privacy_rules(rules(), false),
+            Some(vec![Input::InputPrivacyValueDisallowAll])
+        );
+    }
+
+    #[test]
+    fn privacy_rules_keep_contacts_when_sharing() {
+        let rules = || {
+            current(vec![
+                Rule::PrivacyValueAllowContacts,
+                Rule::PrivacyValueDisallowAll,
+            ])
+        };
+        assert_eq!(privacy_rules(rules(), true), None);
+        assert_eq!(
+            privacy_rules(rules(), false),
+            Some(vec![Input::InputPrivacyValueDisallowAll])
+        );
+    }
+
+    #[test]
+    fn privacy_rules_for_nobody() {
+        let rules = || current(vec![Rule::PrivacyValueDisallowAll]);
+        assert_eq!(
+            privacy_rules(rules(), true),
+            Some(vec![Input::InputPrivacyValueAllowAll])
+        );
+        assert_eq!(privacy_rules(rules(), false), None);
+    }
+
+    #[test]
+    fn privacy_rules_keep_exceptions() {
+        let rules = current(vec![
+            Rule::PrivacyValueAllowChatParticipants(tl::types::PrivacyValueAllowChatParticipants {
+                chats: vec![42],
+            }),
+            Rule::PrivacyValueDisallowAll,
+        ]);
+        assert_eq!(
+            privacy_rules(rules, true),
+            Some(vec![
+                Input::InputPrivacyValueAllowChatParticipants(
+                    tl::types::InputPrivacyValueAllowChatParticipants { chats: vec![42] },
+                ),
+                Input::InputPrivacyValueAllowAll,
+            ])
+        );
+    }
+
+    #[test]
+    fn rich_text_counts_utf16_units() {
+        use tl::enums::MessageEntity as Entity;
+
+        // The emoji takes two UTF-16 units, so "bold" starts at offset 3
+        let entities = [Entity::Bold(tl::types::MessageEntityBold {
+            offset: 3,
+            length: 4,
+        })];
+        let text = rich_text("😀 bold text", &entities).unwrap();
+        assert_eq!(
+            text.blocks,
+            vec![Block::Paragraph(vec![
+                Span {
+                    text: "😀 ".to_owned(),
+                    style: SpanStyle::default(),
+                },
+                Span {
+                    text: "bold".to_owned(),
+                    style: SpanStyle {
+                        bold: true,
+                        ..Default::default()
+                    },
+                },
+                Span {
+                    text: " text".to_owned(),
+                    style: SpanStyle::default(),
+                },
+            ])]
+        );
+    }
+
+    #[test]
+    fn rich_text_splits_code_blocks_and_links_bare_domains() {
+        use tl::enums::MessageEntity as Entity;
+
+        let entities = [
+            Entity::Url(tl::types::MessageEntityUrl {
+                offset: 4,
+                length: 11,
+            }),
+            Entity::Pre(tl::types::MessageEntityPre {
+                offset: 16,
+                length: 6,
+                language: "sh".to_owned(),
+            }),
+        ];
+        let text = rich_text("see example.org\nls -la", &entities).unwrap();
+        assert_eq!(
+            text.blocks,
+            vec![
+                Block::Paragraph(vec![
+                    Span {
+                        text: "see ".to_owned(),
+                        style: SpanStyle::default(),
+                    },
+                    Span {
+                        text: "example.org".to_owned(),
+                        style: SpanStyle {
+                            link: Some("https://example.org".to_owned()),
+                            ..Default::default()
+                        },
+                    },
+                ]),
+                Block::Code {
+                    language: Some("sh".to_owned()),
+                    code: "ls -la".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rich_text_is_none_without_formatting() {
+        assert_eq!(rich_text("plain text", &[]), None);
+    }
+
+    #[test]
+    fn split_topic_reads_forum_topics() {
+        assert_eq!(split_topic("-100123"), ("-100123", None));
+        assert_eq!(
+            split_topic(&topic_chat_id("-100123", 42)),
+            ("-100123", Some(42))
+        );
+        assert_eq!(split_topic("-100123/general"), ("-100123", None));
+    }
+}
@@ -0,0 +1,331 @@
+use std::{borrow::Cow, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::{
+    clients::{Chat, Client, NewChat, Space, UserProfile},
+    directory::refresh_and_select,
+};
+
+/// How long typing has to pause before a search is sent
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Holds back searching while the user is still typing in a search field
+#[derive(Default)]
+pub struct SearchDebounce {
+    /// UI time of the last edit, in seconds
+    edited_at: f64,
+}
+
+impl SearchDebounce {
+    /// Note edits from the field's response and tell whether a search for a new
+    /// query is due, asking for a repaint when the pause will be over
+    pub fn ready(&mut self, ui: &egui::Ui, response: &egui::Response, new_query: bool) -> bool {
+        let now = ui.input(|i| i.time);
+        if response.changed() {
+            self.edited_at = now;
+        }
+        if !new_query {
+            return false;
+        }
+
+        let remaining = SEARCH_DEBOUNCE.as_secs_f64() - (now - self.edited_at);
+        if remaining > 0.0 {
+            ui.ctx()
+                .request_repaint_after(Duration::from_secs_f64(remaining));
+            return false;
+        }
+        true
+    }
+}
+
+/// Text field with user directory autocomplete
+#[derive(Default)]
+struct UserSearch {
+    query: String,
+    /// Query of the latest search, results for older ones are dropped
+    last_query: Arc<Mutex<String>>,
+    debounce: SearchDebounce,
+    results: Arc<Mutex<Vec<UserProfile>>>,
+}
+
+impl UserSearch {
+    /// Show the search field and suggestions, returning a picked user
+    fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        hint: &str,
+    ) -> Option<UserProfile> {
+        let response = ui.add(egui::TextEdit::singleline(&mut self.query).hint_text(hint));
+
+        // Search again once the query settled on something new
+        let query = self.query.trim();
+        let new_query = query.len() >= 2 && *self.last_query.lock() != query;
+        if self.debounce.ready(ui, &response, new_query) {
+            *self.last_query.lock() = query.to_owned();
+
+            let client = client.clone();
+            let last_query = self.last_query.clone();
+            let results = self.results.clone();
+            let query = query.to_owned();
+            let ctx = ui.ctx().clone();
+
+            rt.spawn(async move {
+                match client.search_users(&query).await {
+                    // A slow response may arrive after a newer query was sent
+                    Ok(users) if *last_query.lock() == query => *results.lock() = users,
+                    Ok(_) => {}
+                    Err(e) => log::error!("Failed to search users: {}", e),
+                }
+                ctx.request_repaint();
+            });
+        }
+
+        let mut picked = None;
+        for user in self.results.lock().iter() {
+            ui.horizontal(|ui| {
+                if let Some(avatar) = &user.avatar {
+                    ui.add(
+                        egui::Image::new((
+                            Cow::Owned("user-avatar-".to_owned() + user.id.as_str()),
+                            avatar.clone(),
+                        ))
+                        .fit_to_exact_size(egui::Vec2::splat(24.0))
+                        .corner_radius(12.0),
+                    );
+                }
+
+                let label = match &user.display_name {
+                    Some(name) => format!("{} ({})", name, user.id),
+                    None => user.id.clone(),
+                };
+                if ui.selectable_label(false, label).clicked() {
+                    picked = Some(user.clone());
+                }
+            });
+        }
+
+        picked
+    }
+}
+
+/// Which kind of chat the create dialog is making
+#[derive(PartialEq)]
+enum CreateMode {
+    Group,
+    Direct,
+}
+
+/// Dialog for creating group chats and starting direct messages
+pub struct CreateChatDialog {
+    mode: CreateMode,
+    options: NewChat,
+    user: UserSearch,
+    busy: Arc<Mutex<bool>>,
+    error: Arc<Mutex<Option<String>>>,
+    open: Arc<Mutex<bool>>,
+}
+
+impl Default for CreateChatDialog {
+    fn default() -> Self {
+        Self {
+            mode: CreateMode::Group,
+            options: NewChat {
+                encrypted: true,
+                ..Default::default()
+            },
+            user: UserSearch::default(),
+            busy: Arc::default(),
+            error: Arc::default(),
+            open: Arc::new(Mutex::new(true)),
+        }
+    }
+}
+
+impl CreateChatDialog {
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+        spaces: &Arc<Mutex<Vec<Space>>>,
+        selected_chat: &Arc<Mutex<Option<String>>>,
+    ) -> bool {
+        let mut open = *self.open.lock();
+        let mut create = None;
+
+        egui::Window::new("New chat")
+            .open(&mut open)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.mode, CreateMode::Group, "Group");
+                    ui.selectable_value(&mut self.mode, CreateMode::Direct, "Direct message");
+                });
+                ui.separator();
+
+                match self.mode {
+                    CreateMode::Group => {
+                        ui.label("Name:");
+                        ui.text_edit_singleline(&mut self.options.name);
+                        ui.label("Topic:");
+                        ui.text_edit_multiline(&mut self.options.topic);
+
+                        ui.checkbox(&mut self.options.public, "Public");
+                        if self.options.public {
+                            ui.label("Address:");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.options.alias)
+                                    .hint_text("my-room"),
+                            );
+                        }
+                        ui.checkbox(&mut self.options.encrypted, "End-to-end encryption")
+                            .on_hover_text("Only applies to Matrix rooms");
+
+                        ui.add_space(8.0);
+                        let valid = !self.options.name.trim().is_empty();
+                        if ui.add_enabled(valid, egui::Button::new("Create")).clicked() {
+                            create = Some(None);
+                        }
+                    }
+                    CreateMode::Direct => {
+                        ui.label("User ID, username or phone number:");
+                        if let Some(user) = self.user.show(ui, rt, client, "@alice:example.org") {
+                            create = Some(Some(user.id));
+                        }
+
+                        ui.add_space(8.0);
+                        let valid = !self.user.query.trim().is_empty();
+                        if ui
+                            .add_enabled(valid, egui::Button::new("Start chat"))
+                            .clicked()
+                        {
+                            create = Some(Some(self.user.query.trim().to_owned()));
+                        }
+                    }
+                }
+
+                if *self.busy.lock() {
+                    ui.spinner();
+                }
+                if let Some(error) = &*self.error.lock() {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+        if let Some(direct_user) = create.filter(|_| !*self.busy.lock()) {
+            let client = client.clone();
+            let chats = chats.clone();
+            let spaces = spaces.clone();
+            let selected_chat = selected_chat.clone();
+            let options = self.options.clone();
+            let busy = self.busy.clone();
+            let error = self.error.clone();
+            let dialog_open = self.open.clone();
+            let ctx = ctx.clone();
+
+            *busy.lock() = true;
+            *error.lock() = None;
+            rt.spawn(async move {
+                let result = match &direct_user {
+                    Some(user) => client.create_direct_chat(user).await,
+                    None => client.create_chat(&options).await,
+                };
+
+                let result = match result {
+                    Ok(chat_id) => {
+                        refresh_and_select(&client, &chat_id, &chats, &spaces, &selected_chat).await
+                    }
+                    Err(e) => Err(e),
+                };
+
+                match result {
+                    Ok(()) => *dialog_open.lock() = false,
+                    Err(e) => *error.lock() = Some(format!("Failed to create chat: {}", e)),
+                }
+                *busy.lock() = false;
+                ctx.request_repaint();
+            });
+        }
+
+        // Keep a close from the window's "x" unless the task already closed it
+        let still_open = open && *self.open.lock();
+        *self.open.lock() = still_open;
+        still_open
+    }
+}
+
+/// Dialog for inviting users to a chat
+pub struct InviteDialog {
+    chat_id: String,
+    user: UserSearch,
+    invited: Arc<Mutex<Vec<String>>>,
+    error: Arc<Mutex<Option<String>>>,
+    open: bool,
+}
+
+impl InviteDialog {
+    /// Create an invite dialog for the given chat
+    pub fn new(chat_id: &str) -> Self {
+        Self {
+            chat_id: chat_id.to_owned(),
+            user: UserSearch::default(),
+            invited: Arc::default(),
+            error: Arc::default(),
+            open: true,
+        }
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(&mut self, ctx: &egui::Context, rt: &Runtime, client: &Arc<dyn Client>) -> bool {
+        let mut open = self.open;
+        let mut invite = None;
+
+        egui::Window::new("Invite users")
+            .open(&mut open)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                ui.label("Search users:");
+                if let Some(user) = self.user.show(ui, rt, client, "Name or user ID") {
+                    invite = Some(user.id);
+                }
+
+                let valid = !self.user.query.trim().is_empty();
+                if ui.add_enabled(valid, egui::Button::new("Invite")).clicked() {
+                    invite = Some(self.user.query.trim().to_owned());
+                }
+
+                for user_id in self.invited.lock().iter() {
+                    ui.label(egui::RichText::new(format!("✔ Invited {}", user_id)).weak());
+                }
+                if let Some(error) = &*self.error.lock() {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+        if let Some(user_id) = invite {
+            let client = client.clone();
+            let chat_id = self.chat_id.clone();
+            let invited = self.invited.clone();
+            let error = self.error.clone();
+            let ctx = ctx.clone();
+
+            *error.lock() = None;
+            rt.spawn(async move {
+                match client.invite_user(&chat_id, &user_id).await {
+                    Ok(()) => invited.lock().push(user_id),
+                    Err(e) => *error.lock() = Some(format!("Failed to invite: {}", e)),
+                }
+                ctx.request_repaint();
+            });
+        }
+
+        self.open = open;
+        self.open
+    }
+}
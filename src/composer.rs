@@ -0,0 +1,102 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::runtime::Runtime;
+
+use crate::clients::Client;
+
+/// Stop the typing notice after this many seconds without edits
+const TYPING_IDLE: f64 = 5.0;
+
+/// Repeat the typing notice this often while the user keeps typing
+const TYPING_REFRESH: f64 = 3.0;
+
+/// Message input that keeps the chat's typing notice in step with the draft
+#[derive(Default)]
+pub struct Composer {
+    text: String,
+    /// Chat the draft belongs to
+    chat_id: String,
+    /// Chat a typing notice was last sent to, and when
+    typing_sent: Option<(String, f64)>,
+    last_edit: f64,
+}
+
+impl Composer {
+    /// Show the input and send button, returning the text once submitted
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        hint: &str,
+    ) -> Option<String> {
+        let now = ui.input(|i| i.time);
+        let mut submitted = None;
+
+        // A draft doesn't follow the user into another chat
+        if self.chat_id != chat_id {
+            self.chat_id = chat_id.to_owned();
+            self.text.clear();
+        }
+
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.text)
+                    .hint_text(hint)
+                    .desired_width(ui.available_width() - 60.0),
+            );
+            if response.changed() {
+                self.last_edit = now;
+            }
+
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            let valid = !self.text.trim().is_empty();
+            if (ui.add_enabled(valid, egui::Button::new("Send")).clicked() || entered) && valid {
+                submitted = Some(self.text.trim().to_owned());
+                self.text.clear();
+                response.request_focus();
+            }
+        });
+
+        let typing = !self.text.trim().is_empty() && now - self.last_edit < TYPING_IDLE;
+        match self.typing_sent.take() {
+            // Still typing in the same chat and the notice is fresh enough
+            Some((sent_chat, at))
+                if typing && sent_chat == chat_id && now - at < TYPING_REFRESH =>
+            {
+                self.typing_sent = Some((sent_chat, at));
+            }
+            sent => {
+                if let Some((sent_chat, _)) =
+                    sent.filter(|(sent_chat, _)| !typing || sent_chat != chat_id)
+                {
+                    send_typing(rt, client, &sent_chat, false);
+                }
+                if typing {
+                    send_typing(rt, client, chat_id, true);
+                    self.typing_sent = Some((chat_id.to_owned(), now));
+                }
+            }
+        }
+
+        // Wake up to notice the user went idle
+        if self.typing_sent.is_some() {
+            ui.ctx().request_repaint_after(Duration::from_secs(1));
+        }
+
+        submitted
+    }
+}
+
+/// Send a typing notice in the background
+fn send_typing(rt: &Runtime, client: &Arc<dyn Client>, chat_id: &str, typing: bool) {
+    let client = client.clone();
+    let chat_id = chat_id.to_owned();
+
+    rt.spawn(async move {
+        if let Err(e) = client.set_typing(&chat_id, typing).await {
+            log::error!("Failed to send typing notice: {}", e);
+        }
+    });
+}
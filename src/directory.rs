@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::{
+    clients::{Chat, Client, RoomPreview, Space},
+    spaces::render_room_preview,
+};
+
+/// Refresh the sidebar and select a newly joined or created chat
+pub async fn refresh_and_select(
+    client: &Arc<dyn Client>,
+    chat_id: &str,
+    chats: &Arc<Mutex<Vec<Chat>>>,
+    spaces: &Arc<Mutex<Vec<Space>>>,
+    selected_chat: &Arc<Mutex<Option<String>>>,
+) -> Result<()> {
+    match client.chats().await {
+        Ok(client_chats) => *chats.lock() = client_chats,
+        Err(e) => log::error!("Failed to fetch chats: {}", e),
+    }
+    match client.spaces().await {
+        Ok(client_spaces) => *spaces.lock() = client_spaces,
+        Err(e) => log::error!("Failed to fetch spaces: {}", e),
+    }
+
+    client.select_chat(chat_id).await?;
+    *selected_chat.lock() = Some(chat_id.to_owned());
+    Ok(())
+}
+
+/// Join a chat, refresh the sidebar and select the joined chat
+pub async fn join_and_refresh(
+    client: &Arc<dyn Client>,
+    target: &str,
+    via: &[String],
+    chats: &Arc<Mutex<Vec<Chat>>>,
+    spaces: &Arc<Mutex<Vec<Space>>>,
+    selected_chat: &Arc<Mutex<Option<String>>>,
+) -> Result<String> {
+    let chat_id = client.join_chat(target, via).await?;
+    refresh_and_select(client, &chat_id, chats, spaces, selected_chat).await?;
+    Ok(chat_id)
+}
+
+/// Current state of the directory search results
+#[derive(Default)]
+struct SearchState {
+    rooms: Vec<RoomPreview>,
+    next_batch: Option<String>,
+    total_estimate: Option<u64>,
+    loading: bool,
+    error: Option<String>,
+    /// Bumped by every new search, pages of older searches are dropped
+    generation: u64,
+}
+
+/// Current state of the alias / link preview
+#[derive(Default)]
+struct PreviewState {
+    /// Alias, ID or link being previewed, results for older ones are dropped
+    target: String,
+    preview: Option<RoomPreview>,
+    loading: bool,
+    error: Option<String>,
+}
+
+/// "Join / Explore" window: public directory search and join by alias, ID or link
+pub struct DirectoryDialog {
+    query: String,
+    server: String,
+    target: String,
+    search: Arc<Mutex<SearchState>>,
+    preview: Arc<Mutex<PreviewState>>,
+    joining: Arc<Mutex<Option<String>>>,
+    open: bool,
+}
+
+impl Default for DirectoryDialog {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            server: String::new(),
+            target: String::new(),
+            search: Arc::default(),
+            preview: Arc::default(),
+            joining: Arc::default(),
+            open: true,
+        }
+    }
+}
+
+impl DirectoryDialog {
+    /// Run a directory search, appending to the results when `since` is set
+    fn search(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        since: Option<String>,
+    ) {
+        let client = client.clone();
+        let search = self.search.clone();
+        let query = self.query.clone();
+        let server = self.server.clone();
+        let ctx = ctx.clone();
+
+        let generation = {
+            let mut state = search.lock();
+            if since.is_none() {
+                *state = SearchState {
+                    generation: state.generation + 1,
+                    ..Default::default()
+                };
+            }
+            state.loading = true;
+            state.error = None;
+            state.generation
+        };
+
+        rt.spawn(async move {
+            let result = client
+                .search_directory(&query, Some(&server), since.as_deref())
+                .await;
+
+            let mut state = search.lock();
+            // A slow page may arrive after a newer search was started
+            if state.generation != generation {
+                return;
+            }
+            state.loading = false;
+            match result {
+                Ok(page) => {
+                    state.rooms.extend(page.rooms);
+                    state.next_batch = page.next_batch;
+                    state.total_estimate = page.total_estimate;
+                }
+                Err(e) => state.error = Some(e.to_string()),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Preview the room entered in the alias / link field
+    fn preview(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let client = client.clone();
+        let preview = self.preview.clone();
+        let target = self.target.clone();
+        let ctx = ctx.clone();
+
+        *preview.lock() = PreviewState {
+            target: target.clone(),
+            loading: true,
+            ..Default::default()
+        };
+
+        rt.spawn(async move {
+            let result = client.preview_chat(&target).await;
+
+            let mut state = preview.lock();
+            if state.target != target {
+                return;
+            }
+            state.loading = false;
+            match result {
+                Ok(room) => state.preview = Some(room),
+                Err(e) => state.error = Some(e.to_string()),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+        spaces: &Arc<Mutex<Vec<Space>>>,
+        selected_chat: &Arc<Mutex<Option<String>>>,
+    ) -> bool {
+        let mut open = self.open;
+        let mut search = None;
+        let mut preview = false;
+        let mut join = None;
+
+        egui::Window::new("Join / Explore")
+            .open(&mut open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                let joining = self.joining.lock().clone();
+
+                // Join by alias, room ID or link
+                ui.label("Room alias, ID or link:");
+                ui.horizontal(|ui| {
+                    let edit = ui.add(
+                        egui::TextEdit::singleline(&mut self.target).hint_text("#room:example.org"),
+                    );
+                    let enter = edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Preview").clicked() || enter) && !self.target.is_empty() {
+                        preview = true;
+                    }
+                });
+
+                {
+                    let state = self.preview.lock();
+                    if state.loading {
+                        ui.spinner();
+                    }
+                    if let Some(error) = &state.error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    if let Some(room) = &state.preview
+                        && render_room_preview(ui, room, joining.as_deref())
+                    {
+                        join = Some(room.clone());
+                    }
+                }
+
+                ui.separator();
+
+                // Public room directory
+                ui.label("Search public rooms:");
+                let loading = self.search.lock().loading;
+                ui.horizontal(|ui| {
+                    let edit = ui
+                        .add(egui::TextEdit::singleline(&mut self.query).hint_text("Search term"));
+                    let enter = edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.server)
+                            .hint_text("Server (optional)")
+                            .desired_width(120.0),
+                    );
+                    let clicked = ui
+                        .add_enabled(!loading, egui::Button::new("Search"))
+                        .clicked();
+                    if (clicked || enter) && !loading {
+                        search = Some(None);
+                    }
+                });
+
+                let state = self.search.lock();
+                if let Some(total) = state.total_estimate {
+                    ui.label(egui::RichText::new(format!("About {} rooms", total)).weak());
+                }
+                if let Some(error) = &state.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for room in &state.rooms {
+                        if render_room_preview(ui, room, joining.as_deref()) {
+                            join = Some(room.clone());
+                        }
+                        ui.separator();
+                    }
+
+                    if state.loading {
+                        ui.spinner();
+                    } else if let Some(next_batch) = &state.next_batch
+                        && ui.button("Load more").clicked()
+                    {
+                        search = Some(Some(next_batch.clone()));
+                    }
+                });
+            });
+
+        if let Some(since) = search {
+            self.search(rt, ctx, client, since);
+        }
+        if preview {
+            self.preview(rt, ctx, client);
+        }
+        if let Some(room) = join {
+            let client = client.clone();
+            let chats = chats.clone();
+            let spaces = spaces.clone();
+            let selected_chat = selected_chat.clone();
+            let search = self.search.clone();
+            let preview = self.preview.clone();
+            let joining = self.joining.clone();
+            let ctx = ctx.clone();
+
+            // Aliases resolve on every network, IDs need `via` servers on Matrix
+            let target = room.alias.clone().unwrap_or_else(|| room.id.clone());
+
+            *joining.lock() = Some(room.id.clone());
+            rt.spawn(async move {
+                match join_and_refresh(&client, &target, &room.via, &chats, &spaces, &selected_chat)
+                    .await
+                {
+                    Ok(chat_id) => {
+                        for room in search.lock().rooms.iter_mut() {
+                            if room.id == chat_id {
+                                room.joined = true;
+                            }
+                        }
+                        if let Some(room) = &mut preview.lock().preview
+                            && room.id == chat_id
+                        {
+                            room.joined = true;
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to join chat: {}", e);
+                        preview.lock().error = Some(format!("Failed to join: {}", e));
+                    }
+                }
+                *joining.lock() = None;
+                ctx.request_repaint();
+            });
+        }
+
+        self.open = open;
+        self.open
+    }
+}
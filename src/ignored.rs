@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::clients::{Client, UserProfile};
+
+/// Ignore a user in the background, then reload the open chat without their messages
+pub fn ignore_user(
+    rt: &Runtime,
+    ctx: &egui::Context,
+    client: &Arc<dyn Client>,
+    user_id: String,
+    chat_id: Option<String>,
+) {
+    let client = client.clone();
+    let ctx = ctx.clone();
+
+    rt.spawn(async move {
+        if let Err(e) = client.ignore_user(&user_id).await {
+            log::error!("Failed to ignore user: {}", e);
+            return;
+        }
+
+        if let Some(chat_id) = chat_id
+            && let Err(e) = client.select_chat(&chat_id).await
+        {
+            log::error!("Failed to reload chat: {}", e);
+        }
+        ctx.request_repaint();
+    });
+}
+
+/// Ignored users and progress shared with background tasks
+#[derive(Default)]
+struct IgnoredState {
+    users: Vec<UserProfile>,
+    busy: bool,
+    error: Option<String>,
+}
+
+/// Dialog listing ignored users, letting them be seen again
+pub struct IgnoredUsersDialog {
+    state: Arc<Mutex<IgnoredState>>,
+}
+
+impl IgnoredUsersDialog {
+    /// Create the dialog and load the ignored users
+    pub fn new(rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) -> Self {
+        let dialog = Self {
+            state: Arc::default(),
+        };
+        dialog.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = dialog.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.ignored_users().await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(users) => state.users = users,
+                Err(e) => state.error = Some(format!("Failed to load ignored users: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+
+        dialog
+    }
+
+    fn unignore(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        user_id: String,
+    ) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.unignore_user(&user_id).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => state.users.retain(|user| user.id != user_id),
+                Err(e) => state.error = Some(format!("Failed to unignore user: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(&mut self, ctx: &egui::Context, rt: &Runtime, client: &Arc<dyn Client>) -> bool {
+        let mut open = true;
+        let mut unignore = None;
+
+        let (users, busy, error) = {
+            let state = self.state.lock();
+            (state.users.clone(), state.busy, state.error.clone())
+        };
+
+        egui::Window::new("Ignored users")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(320.0)
+            .show(ctx, |ui| {
+                if let Some(error) = &error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if busy {
+                    ui.spinner();
+                }
+                if users.is_empty() && !busy {
+                    ui.label(egui::RichText::new("You aren't ignoring anyone").weak());
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for user in &users {
+                            ui.horizontal(|ui| {
+                                ui.vertical(|ui| {
+                                    ui.label(user.display_name.as_deref().unwrap_or(&user.id));
+                                    ui.label(egui::RichText::new(&user.id).weak().size(12.0));
+                                });
+                                ui.with_layout(
+                                    egui::Layout::right_to_left(egui::Align::Center),
+                                    |ui| {
+                                        if ui
+                                            .add_enabled(!busy, egui::Button::new("Unignore"))
+                                            .clicked()
+                                        {
+                                            unignore = Some(user.id.clone());
+                                        }
+                                    },
+                                );
+                            });
+                            ui.separator();
+                        }
+                    });
+            });
+
+        if let Some(user_id) = unignore {
+            self.unignore(rt, ctx, client, user_id);
+        }
+
+        open
+    }
+}
@@ -1,7 +1,19 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod chat_settings;
 mod clients;
+mod compose;
+mod composer;
+mod directory;
+mod ignored;
+mod members;
 pub mod message;
+mod moderation;
+mod notifications;
+mod qr;
+mod security;
+mod spaces;
+mod threads;
 
 pub use app::EChat;
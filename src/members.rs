@@ -0,0 +1,343 @@
+use std::{borrow::Cow, sync::Arc};
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::{
+    clients::{Chat, Client, Member, MemberRole, Permissions, Space},
+    compose::SearchDebounce,
+    directory::refresh_and_select,
+    ignored::ignore_user,
+    moderation::{ModerationRequest, moderation_menu},
+};
+
+/// Number of members fetched per page
+const MEMBERS_PAGE_SIZE: usize = 50;
+
+/// Members loaded so far for the panel's chat
+#[derive(Default)]
+struct MembersState {
+    /// Search the members were loaded for, pages of older searches are dropped
+    query: String,
+    members: Vec<Member>,
+    total: Option<usize>,
+    has_more: bool,
+    loading: bool,
+    error: Option<String>,
+}
+
+/// Right-side panel listing the members of a chat
+pub struct MembersPanel {
+    chat_id: String,
+    search: String,
+    search_debounce: SearchDebounce,
+    state: Arc<Mutex<MembersState>>,
+    profile: Option<Member>,
+    permissions: Permissions,
+}
+
+impl MembersPanel {
+    /// Create a panel for the given chat and load the first page of members
+    pub fn new(rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>, chat_id: &str) -> Self {
+        let panel = Self {
+            chat_id: chat_id.to_owned(),
+            search: String::new(),
+            search_debounce: SearchDebounce::default(),
+            state: Arc::new(Mutex::new(MembersState {
+                has_more: true,
+                ..Default::default()
+            })),
+            profile: None,
+            permissions: Permissions::default(),
+        };
+        panel.load_more(rt, ctx, client);
+        panel
+    }
+
+    /// ID of the chat whose members are shown
+    pub fn chat_id(&self) -> &str {
+        &self.chat_id
+    }
+
+    /// Update what the current user is allowed to do to members
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// Start over with the members matching a new search
+    fn search(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        *self.state.lock() = MembersState {
+            query: self.search.trim().to_owned(),
+            has_more: true,
+            ..Default::default()
+        };
+        self.load_more(rt, ctx, client);
+    }
+
+    /// Fetch the next page of members
+    fn load_more(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let (query, offset) = {
+            let mut state = self.state.lock();
+            if state.loading || !state.has_more {
+                return;
+            }
+            state.loading = true;
+            (state.query.clone(), state.members.len())
+        };
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let chat_id = self.chat_id.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client
+                .members(&chat_id, &query, offset, MEMBERS_PAGE_SIZE)
+                .await;
+
+            let mut state = state.lock();
+            if state.query != query {
+                return;
+            }
+            state.loading = false;
+            match result {
+                Ok(page) => {
+                    state.members.extend(page.members);
+                    state.total = page.total;
+                    state.has_more = page.has_more;
+                }
+                Err(e) => {
+                    state.has_more = false;
+                    state.error = Some(e.to_string());
+                }
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the panel; must be called before the central panel.
+    /// Returns a moderation action picked from a member's profile.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+        spaces: &Arc<Mutex<Vec<Space>>>,
+        selected_chat: &Arc<Mutex<Option<String>>>,
+    ) -> Option<ModerationRequest> {
+        let mut load_more = false;
+        let mut search = false;
+        let mut moderation = None;
+
+        egui::SidePanel::right("members_panel")
+            .resizable(true)
+            .default_width(220.0)
+            .width_range(180.0..=320.0)
+            .show(ctx, |ui| {
+                let state = self.state.lock();
+
+                match state.total {
+                    Some(total) => ui.heading(format!("Members ({})", total)),
+                    None => ui.heading("Members"),
+                };
+                let response = ui
+                    .add(egui::TextEdit::singleline(&mut self.search).hint_text("Search members"));
+                ui.separator();
+
+                // Search once typing paused on something new
+                let new_query = self.search.trim() != state.query;
+                search = self.search_debounce.ready(ui, &response, new_query);
+
+                if let Some(error) = &state.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for member in &state.members {
+                        if render_member(ui, member).clicked() {
+                            self.profile = Some(member.clone());
+                        }
+                    }
+
+                    if state.loading {
+                        ui.spinner();
+                    } else if state.has_more {
+                        // Load the next page once the end of the list scrolls into view
+                        let response = ui.button("Load more");
+                        if response.clicked() || ui.is_rect_visible(response.rect) {
+                            load_more = true;
+                        }
+                    }
+                });
+            });
+
+        if search {
+            self.search(rt, ctx, client);
+        } else if load_more {
+            self.load_more(rt, ctx, client);
+        }
+
+        if let Some(member) = &self.profile {
+            let mut open = true;
+            let mut message = false;
+            let mut ignore = false;
+
+            egui::Window::new("Profile")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        if let Some(avatar) = &member.profile.avatar {
+                            ui.add(
+                                egui::Image::new((
+                                    Cow::Owned(
+                                        "user-avatar-".to_owned() + member.profile.id.as_str(),
+                                    ),
+                                    avatar.clone(),
+                                ))
+                                .fit_to_exact_size(egui::Vec2::splat(96.0))
+                                .corner_radius(48.0),
+                            );
+                        }
+
+                        let name = member
+                            .profile
+                            .display_name
+                            .as_deref()
+                            .unwrap_or(&member.profile.id);
+                        ui.heading(name);
+                        ui.label(egui::RichText::new(&member.profile.id).weak());
+
+                        if let Some(role) = role_label(member.role) {
+                            ui.label(role);
+                        }
+                        if let Some(level) = member.power_level {
+                            ui.label(egui::RichText::new(format!("Power level {}", level)).weak());
+                        }
+
+                        if *client.self_id() == member.profile.id {
+                            return;
+                        }
+
+                        ui.add_space(8.0);
+                        ui.horizontal(|ui| {
+                            if ui.button("Message privately").clicked() {
+                                message = true;
+                            }
+                            if ui
+                                .button("Ignore")
+                                .on_hover_text("Hide their messages everywhere")
+                                .clicked()
+                            {
+                                ignore = true;
+                            }
+                        });
+
+                        ui.horizontal_wrapped(|ui| {
+                            if let Some(action) =
+                                moderation_menu(ui, &self.permissions, member.power_level)
+                            {
+                                moderation = Some(ModerationRequest {
+                                    user_id: member.profile.id.clone(),
+                                    display_name: name.to_owned(),
+                                    action,
+                                });
+                            }
+                        });
+                    });
+                });
+
+            if message {
+                let client = client.clone();
+                let user_id = member.profile.id.clone();
+                let chats = chats.clone();
+                let spaces = spaces.clone();
+                let selected_chat = selected_chat.clone();
+                let ctx = ctx.clone();
+
+                rt.spawn(async move {
+                    let result = match client.create_direct_chat(&user_id).await {
+                        Ok(chat_id) => {
+                            refresh_and_select(&client, &chat_id, &chats, &spaces, &selected_chat)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        log::error!("Failed to open direct chat: {}", e);
+                    }
+                    ctx.request_repaint();
+                });
+            }
+
+            if ignore {
+                let chat_id = Some(self.chat_id.clone());
+                ignore_user(rt, ctx, client, member.profile.id.clone(), chat_id);
+            }
+
+            if !open || message || ignore || moderation.is_some() {
+                self.profile = None;
+            }
+        }
+
+        moderation
+    }
+}
+
+/// Human readable role badge; plain members have none
+fn role_label(role: MemberRole) -> Option<&'static str> {
+    match role {
+        MemberRole::Owner => Some("Owner"),
+        MemberRole::Admin => Some("Admin"),
+        MemberRole::Moderator => Some("Moderator"),
+        MemberRole::Member => None,
+    }
+}
+
+/// Render one member row
+fn render_member(ui: &mut egui::Ui, member: &Member) -> egui::Response {
+    let (rect, response) =
+        ui.allocate_exact_size(egui::vec2(ui.available_width(), 32.0), egui::Sense::click());
+
+    if response.hovered() {
+        ui.painter().rect_filled(
+            rect,
+            4.0,
+            egui::Color32::from_rgba_premultiplied(100, 100, 100, 15),
+        );
+    }
+
+    ui.allocate_new_ui(egui::UiBuilder::default().max_rect(rect), |ui| {
+        ui.horizontal(|ui| {
+            if let Some(avatar) = &member.profile.avatar {
+                ui.add(
+                    egui::Image::new((
+                        Cow::Owned("user-avatar-".to_owned() + member.profile.id.as_str()),
+                        avatar.clone(),
+                    ))
+                    .fit_to_exact_size(egui::Vec2::splat(24.0))
+                    .corner_radius(12.0),
+                );
+            } else {
+                ui.add(egui::Label::new("👤").selectable(false));
+            }
+
+            let name = member
+                .profile
+                .display_name
+                .as_deref()
+                .unwrap_or(&member.profile.id);
+            ui.add(egui::Label::new(name).selectable(false).truncate());
+
+            if let Some(role) = role_label(member.role) {
+                ui.add(
+                    egui::Label::new(egui::RichText::new(role).weak().size(11.0)).selectable(false),
+                );
+            }
+        });
+    });
+
+    response
+}
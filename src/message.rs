@@ -1,6 +1,17 @@
-use crate::clients::{Event, EventGroup, EventKind};
+use crate::{
+    clients::{
+        Event, EventGroup, EventKind, Permissions, PostInfo, Presence, PresenceInfo, SystemEvent,
+        ThreadSummary, UserProfile,
+        rich_text::{Block, Span, SpanStyle},
+    },
+    moderation::{ModerationAction, ModerationRequest, moderation_menu},
+};
 use chrono::{TimeZone, Utc};
-use egui::{Align, Color32, Layout, Stroke, Ui, Vec2, Widget, epaint::CornerRadiusF32};
+use egui::{
+    Align, Color32, Layout, Stroke, TextFormat, Ui, Vec2, Widget, epaint::CornerRadiusF32,
+    text::LayoutJob,
+};
+use std::cell::Cell;
 
 /// Defines which side messages should appear on
 #[derive(Clone, PartialEq, Default)]
@@ -59,20 +70,87 @@ impl MessageStyle {
     }
 }
 
+/// Something the user asked for from a message bubble
+#[derive(Debug, Clone)]
+pub enum MessageAction {
+    Moderate(ModerationRequest),
+    OpenThread(Event),
+    /// Hide all messages from the user with this ID
+    Ignore(String),
+}
+
 /// Widget for rendering message bubbles
 pub struct MessageWidget {
     style: MessageStyle,
     group: EventGroup,
+    permissions: Permissions,
+    moderation: Cell<Option<ModerationAction>>,
+    opened_thread: Cell<Option<Event>>,
+    ignore: Cell<bool>,
+    divider_after: Option<String>,
+    last_visible: Cell<Option<String>>,
+    presence: Option<PresenceInfo>,
+    /// User whose mentions are highlighted
+    self_id: Option<String>,
+    scroll_to: Option<String>,
 }
 
 impl MessageWidget {
     /// Create a new message widget with the given style and event group
     pub fn new(style: MessageStyle, group: EventGroup) -> Self {
-        Self { style, group }
+        Self {
+            style,
+            group,
+            permissions: Permissions::default(),
+            moderation: Cell::new(None),
+            opened_thread: Cell::new(None),
+            ignore: Cell::new(false),
+            divider_after: None,
+            last_visible: Cell::new(None),
+            presence: None,
+            self_id: None,
+            scroll_to: None,
+        }
     }
 
-    /// Display the message group in the UI
-    pub fn show(&self, ui: &mut Ui) {
+    /// Offer moderation of the sender in the bubble context menu
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Draw a "New messages" divider below the event with this ID
+    pub fn divider_after(mut self, event_id: Option<&str>) -> Self {
+        self.divider_after = event_id.map(ToOwned::to_owned);
+        self
+    }
+
+    /// Mark the sender's avatar with their presence
+    pub fn presence(mut self, presence: Option<PresenceInfo>) -> Self {
+        self.presence = presence;
+        self
+    }
+
+    /// Highlight bubbles that mention this user
+    pub fn highlight_mentions(mut self, user_id: &str) -> Self {
+        self.self_id = Some(user_id.to_owned());
+        self
+    }
+
+    /// Scroll the event with this ID into view
+    pub fn scroll_to(mut self, event_id: Option<&str>) -> Self {
+        self.scroll_to = event_id.map(ToOwned::to_owned);
+        self
+    }
+
+    /// ID of the newest event that was on screen during the last `show`
+    pub fn last_visible(&self) -> Option<String> {
+        self.last_visible.take()
+    }
+
+    /// Display the message group in the UI, returning a moderation action picked
+    /// from a bubble's context menu or a thread to open
+    pub fn show(&self, ui: &mut Ui) -> Option<MessageAction> {
         let event_count = self.group.events.len();
 
         ui.vertical(|ui| {
@@ -80,15 +158,45 @@ impl MessageWidget {
                 let is_first = idx == 0;
                 let is_last = idx == event_count - 1;
 
-                self.render_message_row(ui, event, is_first, is_last);
+                let row = self.render_message_row(ui, event, is_first, is_last);
+                if self.scroll_to.as_deref() == Some(event.id.as_str()) {
+                    ui.scroll_to_rect(row.rect, Some(Align::Center));
+                }
+                if ui.is_rect_visible(row.rect) {
+                    self.last_visible.set(Some(event.id.clone()));
+                }
+
+                if self.divider_after.as_deref() == Some(event.id.as_str()) {
+                    render_divider(ui);
+                }
             }
             ui.add_space(self.style.group_spacing);
         });
+
+        if let Some(root) = self.opened_thread.take() {
+            return Some(MessageAction::OpenThread(root));
+        }
+        if self.ignore.take() {
+            return Some(MessageAction::Ignore(self.group.user_id.clone()));
+        }
+        self.moderation.take().map(|action| {
+            MessageAction::Moderate(ModerationRequest {
+                user_id: self.group.user_id.clone(),
+                display_name: self.group.display_name.clone(),
+                action,
+            })
+        })
     }
 
     // Rendering methods ---------------------------------------------------
 
-    fn render_message_row(&self, ui: &mut Ui, event: &Event, is_first: bool, is_last: bool) {
+    fn render_message_row(
+        &self,
+        ui: &mut Ui,
+        event: &Event,
+        is_first: bool,
+        is_last: bool,
+    ) -> egui::Response {
         ui.horizontal(|ui| {
             let side = if self.group.from_self {
                 &self.style.self_message_side
@@ -100,7 +208,8 @@ impl MessageWidget {
                 MessageSide::Right => self.render_right_aligned(ui, event, is_first, is_last),
                 MessageSide::Left => self.render_left_aligned(ui, event, is_first, is_last),
             }
-        });
+        })
+        .response
     }
 
     fn render_right_aligned(&self, ui: &mut Ui, event: &Event, is_first: bool, is_last: bool) {
@@ -138,13 +247,42 @@ impl MessageWidget {
 
         let rounding = self.calculate_bubble_rounding(is_first, is_last);
 
-        egui::Frame::new()
+        let mentioned = !self.group.from_self
+            && self
+                .self_id
+                .as_deref()
+                .is_some_and(|user_id| event.mentions_user(user_id));
+        let stroke = if mentioned {
+            Stroke::new(1.5, ui.visuals().warn_fg_color)
+        } else {
+            self.style.stroke
+        };
+
+        let response = egui::Frame::new()
             .fill(bg_color)
             .inner_margin(self.style.bubble_margin)
             .corner_radius(rounding)
-            .stroke(self.style.stroke)
+            .stroke(stroke)
             .show(ui, |ui| self.render_bubble_content(ui, event, is_first))
             .response
+            .interact(egui::Sense::click());
+
+        if !self.group.from_self {
+            response.context_menu(|ui| {
+                ui.label(egui::RichText::new(&self.group.display_name).strong());
+                if let Some(action) = moderation_menu(ui, &self.permissions, self.group.power_level)
+                {
+                    self.moderation.set(Some(action));
+                    ui.close_menu();
+                }
+                if ui.button("Ignore").clicked() {
+                    self.ignore.set(true);
+                    ui.close_menu();
+                }
+            });
+        }
+
+        response
     }
 
     fn render_bubble_content(&self, ui: &mut Ui, event: &Event, is_first: bool) {
@@ -154,15 +292,87 @@ impl MessageWidget {
             }
 
             match &event.kind {
-                EventKind::Message(content) => {
-                    ui.label(egui::RichText::new(content).color(self.style.text_color));
+                EventKind::Message(content) => match &event.formatted {
+                    Some(formatted) => render_blocks(
+                        ui,
+                        &formatted.blocks,
+                        self.style.text_color,
+                        egui::Id::new(("rich-text", &event.id)),
+                    ),
+                    None => {
+                        ui.label(egui::RichText::new(content).color(self.style.text_color));
+                    }
+                },
+                EventKind::System(system) => {
+                    let text = system_text(&self.group.display_name, system);
+                    ui.label(egui::RichText::new(text).italics().weak());
                 }
             }
 
-            self.render_timestamp(ui, event.timestamp);
+            if let Some(thread) = &event.thread {
+                self.render_thread_summary(ui, event, thread);
+            }
+
+            if !event.read_by.is_empty() {
+                self.render_read_by(ui, &event.read_by);
+            }
+
+            match &event.post {
+                Some(post) => self.render_post_footer(ui, post, event.timestamp),
+                None => self.render_timestamp(ui, event.timestamp),
+            }
         });
     }
 
+    fn render_thread_summary(&self, ui: &mut Ui, event: &Event, thread: &ThreadSummary) {
+        // Replies to channel posts are comments
+        let label = match (thread.reply_count, event.post.is_some()) {
+            (1, false) => "💬 1 reply".to_owned(),
+            (count, false) => format!("💬 {} replies", count),
+            (1, true) => "💬 1 comment".to_owned(),
+            (count, true) => format!("💬 {} comments", count),
+        };
+
+        let response = ui.link(egui::RichText::new(label).size(12.0));
+        let response = match &thread.latest_reply {
+            Some(latest) => response.on_hover_text(latest),
+            None => response,
+        };
+        if response.clicked() {
+            self.opened_thread.set(Some(event.clone()));
+        }
+    }
+
+    fn render_read_by(&self, ui: &mut Ui, readers: &[UserProfile]) {
+        let names: Vec<&str> = readers
+            .iter()
+            .map(|user| user.display_name.as_deref().unwrap_or(&user.id))
+            .collect();
+
+        ui.horizontal(|ui| {
+            ui.spacing_mut().item_spacing.x = 2.0;
+            for user in readers {
+                match &user.avatar {
+                    Some(avatar) => {
+                        egui::Image::from_bytes(format!("user-avatar-{}", user.id), avatar.clone())
+                            .fit_to_exact_size(Vec2::splat(14.0))
+                            .corner_radius(7.0)
+                            .ui(ui);
+                    }
+                    None => {
+                        ui.label(
+                            egui::RichText::new("●")
+                                .color(self.style.time_color)
+                                .size(10.0),
+                        );
+                    }
+                }
+            }
+        })
+        .response
+        .on_hover_text(format!("Read by {}", names.join(", ")));
+    }
+
     fn render_username(&self, ui: &mut Ui) {
         ui.label(
             egui::RichText::new(&self.group.display_name)
@@ -182,16 +392,37 @@ impl MessageWidget {
         });
     }
 
+    /// Author signature and view count of a channel post, next to its time
+    fn render_post_footer(&self, ui: &mut Ui, post: &PostInfo, timestamp: u64) {
+        let mut parts = Vec::new();
+        if let Some(signature) = &post.signature {
+            parts.push(signature.clone());
+        }
+        if let Some(views) = post.views {
+            parts.push(format!("👁 {}", format_count(views)));
+        }
+        parts.push(format_time(timestamp));
+
+        ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
+            ui.label(
+                egui::RichText::new(parts.join(" · "))
+                    .color(self.style.time_color)
+                    .size(10.0),
+            );
+        });
+    }
+
     fn render_avatar(&self, ui: &mut Ui) {
         if let Some(avatar) = &self.group.avatar {
             let size = self.style.avatar_size;
-            egui::Image::from_bytes(
+            let response = egui::Image::from_bytes(
                 format!("user-avatar-{}", self.group.user_id),
                 avatar.clone(),
             )
             .fit_to_exact_size(Vec2::splat(size))
             .corner_radius(size / 2.0)
             .ui(ui);
+            paint_presence_dot(ui, response.rect, self.presence);
         } else {
             ui.add_space(self.style.avatar_size + 8.0);
         }
@@ -230,6 +461,317 @@ impl MessageWidget {
     }
 }
 
+/// Draw an online dot on the bottom-right of an avatar
+pub fn paint_presence_dot(ui: &Ui, avatar: egui::Rect, presence: Option<PresenceInfo>) {
+    let color = match presence.map(|info| info.presence) {
+        Some(Presence::Online) => Color32::from_rgb(67, 181, 129),
+        Some(Presence::Unavailable) => Color32::from_rgb(250, 166, 26),
+        Some(Presence::Offline) | None => return,
+    };
+    let radius = (avatar.width() / 7.0).max(3.0);
+    let center = avatar.right_bottom() - Vec2::splat(radius);
+    ui.painter().circle(
+        center,
+        radius,
+        color,
+        Stroke::new(1.5, ui.visuals().panel_fill),
+    );
+}
+
+/// Render formatted text; `id` tells apart the spoilers revealed in each paragraph
+fn render_blocks(ui: &mut Ui, blocks: &[Block], color: Color32, id: egui::Id) {
+    for (idx, block) in blocks.iter().enumerate() {
+        let id = id.with(idx);
+        match block {
+            Block::Paragraph(spans) => render_paragraph(ui, spans, color, id),
+            Block::Code { code, .. } => {
+                egui::Frame::new()
+                    .fill(ui.visuals().code_bg_color)
+                    .corner_radius(4.0)
+                    .inner_margin(6)
+                    .show(ui, |ui| {
+                        ui.label(egui::RichText::new(code).monospace().color(color));
+                    });
+            }
+            Block::Quote(blocks) => {
+                let rect = ui
+                    .horizontal(|ui| {
+                        ui.add_space(10.0);
+                        ui.vertical(|ui| render_blocks(ui, blocks, color, id));
+                    })
+                    .response
+                    .rect;
+                ui.painter().vline(
+                    rect.left() + 2.0,
+                    rect.y_range(),
+                    Stroke::new(3.0, ui.visuals().weak_text_color()),
+                );
+            }
+            Block::List {
+                ordered,
+                start,
+                items,
+            } => {
+                for (n, item) in items.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let marker = if *ordered {
+                            format!("{}.", start + n as u64)
+                        } else {
+                            "•".to_owned()
+                        };
+                        ui.label(egui::RichText::new(marker).color(color));
+                        ui.vertical(|ui| render_blocks(ui, item, color, id.with(n)));
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Lay out a paragraph as one `LayoutJob`, opening links and revealing
+/// spoilers when they are clicked
+fn render_paragraph(ui: &mut Ui, spans: &[Span], color: Color32, id: egui::Id) {
+    let revealed = ui.data(|d| d.get_temp::<bool>(id)).unwrap_or(false);
+
+    let mut job = LayoutJob::default();
+    job.wrap.max_width = ui.available_width();
+    for span in spans {
+        job.append(
+            &span.text,
+            0.0,
+            span_format(ui, &span.style, color, revealed),
+        );
+    }
+    let galley = ui.fonts(|fonts| fonts.layout_job(job));
+
+    // Plain paragraphs must not swallow the bubble's clicks
+    let interactive = spans
+        .iter()
+        .any(|span| span.style.link.is_some() || span.style.spoiler);
+    let sense = if interactive {
+        egui::Sense::click()
+    } else {
+        egui::Sense::hover()
+    };
+    let (rect, mut response) = ui.allocate_exact_size(galley.size(), sense);
+
+    let hovered = response
+        .hover_pos()
+        .map(|pos| galley.cursor_from_pos(pos - rect.min).ccursor.index)
+        .and_then(|index| span_at(spans, index));
+    if let Some(style) = hovered.map(|span| &span.style) {
+        let hidden = style.spoiler && !revealed;
+        if hidden || style.link.is_some() {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+        }
+        // Show where a link leads before it is followed
+        if let Some(link) = style.link.as_ref().filter(|_| !hidden) {
+            response = response.on_hover_text_at_pointer(link);
+        }
+        if response.clicked() {
+            if hidden {
+                ui.data_mut(|d| d.insert_temp(id, true));
+            } else if let Some(link) = &style.link {
+                ui.ctx().open_url(egui::OpenUrl::new_tab(link));
+            }
+        }
+    }
+
+    ui.painter().galley(rect.min, galley, color);
+}
+
+/// Span containing the character at `index`
+fn span_at(spans: &[Span], index: usize) -> Option<&Span> {
+    let mut end = 0;
+    spans.iter().find(|span| {
+        end += span.text.chars().count();
+        index < end
+    })
+}
+
+fn span_format(ui: &Ui, style: &SpanStyle, color: Color32, revealed: bool) -> TextFormat {
+    let text_style = if style.code {
+        egui::TextStyle::Monospace
+    } else {
+        egui::TextStyle::Body
+    };
+    let mut format = TextFormat {
+        font_id: text_style.resolve(ui.style()),
+        color,
+        italics: style.italic,
+        ..Default::default()
+    };
+
+    // The default fonts have no bold face, so bold text is drawn brighter
+    if style.bold {
+        format.color = ui.visuals().strong_text_color();
+    }
+    if style.link.is_some() {
+        format.color = ui.visuals().hyperlink_color;
+    }
+    if style.code {
+        format.background = ui.visuals().code_bg_color;
+    }
+    if style.underline || style.link.is_some() {
+        format.underline = Stroke::new(1.0, format.color);
+    }
+    if style.strikethrough {
+        format.strikethrough = Stroke::new(1.0, format.color);
+    }
+    if style.mention.is_some() {
+        format.color = ui.visuals().strong_text_color();
+        format.background = ui.visuals().selection.bg_fill;
+    }
+    if style.spoiler && !revealed {
+        format.background = format.color;
+    }
+
+    format
+}
+
+/// Runs of at least this many membership changes are collapsed into one line
+const COLLAPSE_MEMBERSHIP_RUN: usize = 3;
+
+/// Display consecutive system events as centered lines, collapsing runs of
+/// membership changes. Takes each event with its sender's display name and
+/// returns the ID of the newest event that was on screen.
+pub fn render_system_events(
+    ui: &mut Ui,
+    events: &[(&str, &Event)],
+    divider_after: Option<&str>,
+) -> Option<String> {
+    let mut last_visible = None;
+    let mut rest = events;
+
+    while let Some((name, event)) = rest.first() {
+        let EventKind::System(system) = &event.kind else {
+            rest = &rest[1..];
+            continue;
+        };
+
+        let run_len = rest
+            .iter()
+            .take_while(|(_, event)| {
+                matches!(&event.kind, EventKind::System(system) if system.is_membership())
+            })
+            .count();
+
+        let (shown, response) = if run_len >= COLLAPSE_MEMBERSHIP_RUN {
+            let (run, remaining) = rest.split_at(run_len);
+            rest = remaining;
+            let response = ui
+                .vertical_centered(|ui| {
+                    egui::CollapsingHeader::new(
+                        egui::RichText::new(membership_summary(run))
+                            .weak()
+                            .size(12.0),
+                    )
+                    .id_salt(("membership-run", &event.id))
+                    .show(ui, |ui| {
+                        for (name, event) in run {
+                            if let EventKind::System(system) = &event.kind {
+                                ui.label(
+                                    egui::RichText::new(system_text(name, system))
+                                        .weak()
+                                        .size(12.0),
+                                );
+                            }
+                        }
+                    });
+                })
+                .response;
+            (run, response)
+        } else {
+            let (single, remaining) = rest.split_at(1);
+            rest = remaining;
+            let response = ui
+                .vertical_centered(|ui| {
+                    ui.label(
+                        egui::RichText::new(system_text(name, system))
+                            .weak()
+                            .size(12.0),
+                    );
+                })
+                .response;
+            (single, response)
+        };
+
+        if ui.is_rect_visible(response.rect) {
+            last_visible = shown.last().map(|(_, event)| event.id.clone());
+        }
+        if shown
+            .iter()
+            .any(|(_, event)| divider_after == Some(event.id.as_str()))
+        {
+            render_divider(ui);
+        }
+    }
+
+    last_visible
+}
+
+/// One-line description of a system event sent by `actor`
+fn system_text(actor: &str, event: &SystemEvent) -> String {
+    match event {
+        SystemEvent::Joined => format!("{} joined", actor),
+        SystemEvent::Left => format!("{} left", actor),
+        SystemEvent::Invited(target) => format!("{} invited {}", actor, target),
+        SystemEvent::Added(target) => format!("{} added {}", actor, target),
+        SystemEvent::Removed(target) => format!("{} removed {}", actor, target),
+        SystemEvent::Banned(target) => format!("{} banned {}", actor, target),
+        SystemEvent::ProfileChanged => format!("{} updated their profile", actor),
+        SystemEvent::Created => format!("{} created the chat", actor),
+        SystemEvent::Renamed(name) => format!("{} renamed the chat to \"{}\"", actor, name),
+        SystemEvent::TopicChanged(topic) => {
+            format!("{} changed the topic to \"{}\"", actor, topic)
+        }
+        SystemEvent::AvatarChanged => format!("{} changed the chat avatar", actor),
+        SystemEvent::Pinned => format!("{} pinned a message", actor),
+        SystemEvent::Upgraded => format!("{} upgraded this room", actor),
+    }
+}
+
+/// Summary of a run of membership changes, e.g. "5 users joined, 2 left"
+fn membership_summary(run: &[(&str, &Event)]) -> String {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for (_, event) in run {
+        let verb = match &event.kind {
+            EventKind::System(SystemEvent::Joined | SystemEvent::Added(_)) => "joined",
+            EventKind::System(SystemEvent::Left) => "left",
+            EventKind::System(SystemEvent::Invited(_)) => "were invited",
+            EventKind::System(SystemEvent::Removed(_)) => "were removed",
+            EventKind::System(SystemEvent::Banned(_)) => "were banned",
+            _ => continue,
+        };
+        match counts.iter_mut().find(|(counted, _)| *counted == verb) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((verb, 1)),
+        }
+    }
+
+    counts
+        .iter()
+        .enumerate()
+        .map(|(idx, (verb, count))| match (idx, count) {
+            (0, 1) => format!("1 user {}", verb),
+            (0, count) => format!("{} users {}", count, verb),
+            (_, count) => format!("{} {}", count, verb),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Separator marking where unread messages start
+fn render_divider(ui: &mut Ui) {
+    let color = ui.visuals().error_fg_color;
+    ui.horizontal(|ui| {
+        let line_width = (ui.available_width() - 110.0).max(0.0) / 2.0;
+        ui.add_sized([line_width, 1.0], egui::Separator::default().horizontal());
+        ui.label(egui::RichText::new("New messages").color(color).size(11.0));
+        ui.add_sized([line_width, 1.0], egui::Separator::default().horizontal());
+    });
+}
+
 /// Format timestamp as HH:MM
 fn format_time(timestamp: u64) -> String {
     Utc.timestamp_opt(timestamp as i64, 0)
@@ -237,3 +779,12 @@ fn format_time(timestamp: u64) -> String {
         .format("%H:%M")
         .to_string()
 }
+
+/// Format a view count compactly, like 950, 12.3K or 1.2M
+fn format_count(count: u64) -> String {
+    match count {
+        0..1_000 => count.to_string(),
+        1_000..1_000_000 => format!("{:.1}K", count as f64 / 1_000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::clients::{Client, Permissions};
+
+/// A moderation action against a chat member
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModerationAction {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    SetPowerLevel(i64),
+}
+
+impl ModerationAction {
+    /// Label used for menu entries and the confirm button
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Kick => "Kick",
+            Self::Ban => "Ban",
+            Self::Unban => "Unban",
+            Self::Mute => "Mute",
+            Self::Unmute => "Unmute",
+            Self::SetPowerLevel(_) => "Change power level",
+        }
+    }
+
+    /// Whether the action can carry a reason
+    fn takes_reason(&self) -> bool {
+        matches!(self, Self::Kick | Self::Ban | Self::Unban)
+    }
+
+    /// Run the action through the client
+    async fn apply(
+        self,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        user_id: &str,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        match self {
+            Self::Kick => client.kick_user(chat_id, user_id, reason).await,
+            Self::Ban => client.ban_user(chat_id, user_id, reason).await,
+            Self::Unban => client.unban_user(chat_id, user_id, reason).await,
+            Self::Mute => client.mute_user(chat_id, user_id, true).await,
+            Self::Unmute => client.mute_user(chat_id, user_id, false).await,
+            Self::SetPowerLevel(level) => client.set_power_level(chat_id, user_id, level).await,
+        }
+    }
+}
+
+/// A moderation action requested from the UI, waiting for confirmation
+#[derive(Debug, Clone)]
+pub struct ModerationRequest {
+    pub user_id: String,
+    pub display_name: String,
+    pub action: ModerationAction,
+}
+
+/// Render moderation entries allowed by `permissions`, returning the picked action.
+/// `power_level` is the target's current level, if the network has power levels.
+pub fn moderation_menu(
+    ui: &mut egui::Ui,
+    permissions: &Permissions,
+    power_level: Option<i64>,
+) -> Option<ModerationAction> {
+    let mut picked = None;
+
+    // Members at or above our own level can't be moderated on Matrix
+    let outranked = match (permissions.power_level, power_level) {
+        (Some(own), Some(target)) => target >= own,
+        _ => false,
+    };
+    if outranked {
+        return None;
+    }
+
+    for (allowed, action) in [
+        (permissions.kick, ModerationAction::Kick),
+        (permissions.ban, ModerationAction::Ban),
+        (permissions.ban, ModerationAction::Unban),
+        (permissions.mute, ModerationAction::Mute),
+        (permissions.mute, ModerationAction::Unmute),
+    ] {
+        if allowed && ui.button(action.label()).clicked() {
+            picked = Some(action);
+        }
+    }
+
+    if permissions.change_power_levels {
+        let current = power_level.unwrap_or(0);
+        if ui
+            .button(ModerationAction::SetPowerLevel(current).label())
+            .clicked()
+        {
+            picked = Some(ModerationAction::SetPowerLevel(current));
+        }
+    }
+
+    picked
+}
+
+/// Confirmation dialog for a moderation action, with reason and power level inputs
+pub struct ModerationDialog {
+    chat_id: String,
+    request: ModerationRequest,
+    reason: String,
+    busy: Arc<Mutex<bool>>,
+    error: Arc<Mutex<Option<String>>>,
+    done: Arc<Mutex<bool>>,
+}
+
+impl ModerationDialog {
+    /// Create a dialog confirming `request` in the given chat
+    pub fn new(chat_id: &str, request: ModerationRequest) -> Self {
+        Self {
+            chat_id: chat_id.to_owned(),
+            request,
+            reason: String::new(),
+            busy: Arc::default(),
+            error: Arc::default(),
+            done: Arc::default(),
+        }
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        permissions: &Permissions,
+    ) -> bool {
+        let mut open = true;
+        let mut confirm = false;
+
+        egui::Window::new(self.request.action.label())
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} {} ({})?",
+                    self.request.action.label(),
+                    self.request.display_name,
+                    self.request.user_id
+                ));
+
+                if self.request.action.takes_reason() {
+                    ui.add(egui::TextEdit::singleline(&mut self.reason).hint_text("Reason"));
+                }
+
+                if let ModerationAction::SetPowerLevel(level) = &mut self.request.action {
+                    // Nobody can raise someone above their own level
+                    let max = permissions.power_level.unwrap_or(100);
+                    ui.add(egui::Slider::new(level, 0..=max).text("Power level"));
+                }
+
+                ui.horizontal(|ui| {
+                    let busy = *self.busy.lock();
+                    if ui
+                        .add_enabled(!busy, egui::Button::new(self.request.action.label()))
+                        .clicked()
+                    {
+                        confirm = true;
+                    }
+                    if busy {
+                        ui.spinner();
+                    }
+                });
+
+                if let Some(error) = &*self.error.lock() {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+
+        if confirm {
+            let client = client.clone();
+            let chat_id = self.chat_id.clone();
+            let user_id = self.request.user_id.clone();
+            let action = self.request.action;
+            let reason = Some(self.reason.trim().to_owned()).filter(|r| !r.is_empty());
+            let busy = self.busy.clone();
+            let error = self.error.clone();
+            let done = self.done.clone();
+            let ctx = ctx.clone();
+
+            *busy.lock() = true;
+            rt.spawn(async move {
+                match action
+                    .apply(&client, &chat_id, &user_id, reason.as_deref())
+                    .await
+                {
+                    Ok(()) => *done.lock() = true,
+                    Err(e) => *error.lock() = Some(e.to_string()),
+                }
+                *busy.lock() = false;
+                ctx.request_repaint();
+            });
+        }
+
+        open && !*self.done.lock()
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::clients::{Client, NotifyMode};
+
+/// Settings and progress shared with background tasks
+#[derive(Default)]
+struct NotificationState {
+    keywords: Vec<String>,
+    /// Override for the chat, once loaded
+    chat_mode: Option<Option<NotifyMode>>,
+    busy: bool,
+    error: Option<String>,
+}
+
+/// Dialog editing keyword rules and the selected chat's notification override
+pub struct NotificationSettingsDialog {
+    state: Arc<Mutex<NotificationState>>,
+    chat_id: Option<String>,
+    new_keyword: String,
+}
+
+impl NotificationSettingsDialog {
+    /// Create the dialog and load the current settings
+    pub fn new(
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: Option<&str>,
+    ) -> Self {
+        let dialog = Self {
+            state: Arc::default(),
+            chat_id: chat_id.map(str::to_owned),
+            new_keyword: String::new(),
+        };
+        dialog.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = dialog.state.clone();
+        let chat_id = dialog.chat_id.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let keywords = client.keywords().await;
+            let chat_mode = match &chat_id {
+                Some(chat_id) => Some(client.chat_notify_mode(chat_id).await),
+                None => None,
+            };
+
+            let mut state = state.lock();
+            state.busy = false;
+            match keywords {
+                Ok(keywords) => state.keywords = keywords,
+                Err(e) => state.error = Some(format!("Failed to load keywords: {}", e)),
+            }
+            match chat_mode {
+                Some(Ok(mode)) => state.chat_mode = Some(mode),
+                Some(Err(e)) => {
+                    state.error = Some(format!("Failed to load chat notifications: {}", e))
+                }
+                None => {}
+            }
+            ctx.request_repaint();
+        });
+
+        dialog
+    }
+
+    fn add_keyword(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        keyword: String,
+    ) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.add_keyword(&keyword).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => state.keywords.push(keyword),
+                Err(e) => state.error = Some(format!("Failed to add keyword: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    fn remove_keyword(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        keyword: String,
+    ) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.remove_keyword(&keyword).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => state.keywords.retain(|k| *k != keyword),
+                Err(e) => state.error = Some(format!("Failed to remove keyword: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    fn set_chat_mode(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: String,
+        mode: Option<NotifyMode>,
+    ) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.set_chat_notify_mode(&chat_id, mode).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => state.chat_mode = Some(mode),
+                Err(e) => state.error = Some(format!("Failed to change chat notifications: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(&mut self, ctx: &egui::Context, rt: &Runtime, client: &Arc<dyn Client>) -> bool {
+        let mut open = true;
+        let mut add = None;
+        let mut remove = None;
+        let mut chat_mode = None;
+
+        let (keywords, current_mode, busy, error) = {
+            let state = self.state.lock();
+            (
+                state.keywords.clone(),
+                state.chat_mode,
+                state.busy,
+                state.error.clone(),
+            )
+        };
+
+        egui::Window::new("Notifications")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                if let Some(error) = &error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if busy {
+                    ui.spinner();
+                }
+
+                ui.heading("Keywords");
+                ui.label(
+                    egui::RichText::new("Messages containing these words notify like a mention")
+                        .weak()
+                        .size(12.0),
+                );
+                for keyword in &keywords {
+                    ui.horizontal(|ui| {
+                        ui.label(keyword);
+                        if ui.add_enabled(!busy, egui::Button::new("✖")).clicked() {
+                            remove = Some(keyword.clone());
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    let response = ui.text_edit_singleline(&mut self.new_keyword);
+                    let entered =
+                        response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let keyword = self.new_keyword.trim();
+                    let valid = !keyword.is_empty() && !keywords.iter().any(|k| k == keyword);
+                    if (ui
+                        .add_enabled(valid && !busy, egui::Button::new("Add"))
+                        .clicked()
+                        || entered)
+                        && valid
+                    {
+                        add = Some(keyword.to_owned());
+                    }
+                });
+
+                if let (Some(chat_id), Some(mode)) = (&self.chat_id, current_mode) {
+                    ui.separator();
+                    ui.heading("This chat");
+                    let mut selected = mode;
+                    ui.add_enabled_ui(!busy, |ui| {
+                        ui.radio_value(&mut selected, None, "Default");
+                        ui.radio_value(
+                            &mut selected,
+                            Some(NotifyMode::AllMessages),
+                            "All messages",
+                        );
+                        ui.radio_value(
+                            &mut selected,
+                            Some(NotifyMode::MentionsOnly),
+                            "Mentions and keywords",
+                        );
+                        ui.radio_value(&mut selected, Some(NotifyMode::Mute), "Mute");
+                    });
+                    if selected != mode {
+                        chat_mode = Some((chat_id.clone(), selected));
+                    }
+                }
+            });
+
+        if let Some(keyword) = add {
+            self.new_keyword.clear();
+            self.add_keyword(rt, ctx, client, keyword);
+        }
+        if let Some(keyword) = remove {
+            self.remove_keyword(rt, ctx, client, keyword);
+        }
+        if let Some((chat_id, mode)) = chat_mode {
+            self.set_chat_mode(rt, ctx, client, chat_id, mode);
+        }
+
+        open
+    }
+}
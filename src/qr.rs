@@ -0,0 +1,44 @@
+use qrcode::{Color, QrCode};
+
+/// Empty modules around the code so scanners can find its edges
+const QUIET_ZONE: usize = 2;
+
+/// Draw `data` as a QR code, black on white regardless of the theme
+pub fn show_qr_code(ui: &mut egui::Ui, data: &[u8], size: f32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+
+    let code = match QrCode::new(data) {
+        Ok(code) => code,
+        Err(e) => {
+            ui.painter().text(
+                rect.center(),
+                egui::Align2::CENTER_CENTER,
+                format!("Failed to encode QR code: {}", e),
+                egui::FontId::default(),
+                ui.visuals().error_fg_color,
+            );
+            return response;
+        }
+    };
+
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::WHITE);
+
+    let width = code.width();
+    let module = size / (width + QUIET_ZONE * 2) as f32;
+    for (idx, color) in code.to_colors().into_iter().enumerate() {
+        if color != Color::Dark {
+            continue;
+        }
+        let x = (idx % width + QUIET_ZONE) as f32 * module;
+        let y = (idx / width + QUIET_ZONE) as f32 * module;
+        let min = rect.min + egui::vec2(x, y);
+        painter.rect_filled(
+            egui::Rect::from_min_size(min, egui::vec2(module, module)),
+            0.0,
+            egui::Color32::BLACK,
+        );
+    }
+
+    response
+}
@@ -0,0 +1,437 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::clients::{Client, LoginApproval, PasswordRequired, Session};
+
+/// Sessions and progress shared with background tasks
+#[derive(Default)]
+struct SecurityState {
+    sessions: Vec<Session>,
+    busy: bool,
+    error: Option<String>,
+    /// Sessions waiting for the account password before they can be signed out
+    password_for: Option<Vec<String>>,
+    logged_out: bool,
+}
+
+/// Per-account dialog listing sessions, with sign out and log out
+pub struct SecurityDialog {
+    state: Arc<Mutex<SecurityState>>,
+    /// Edited name of this device, once the sessions are loaded
+    device_name: Option<String>,
+    password: String,
+    confirm_logout: bool,
+    /// Code of a device asking to sign in
+    login_code: String,
+    approval: Option<Arc<Mutex<LoginApproval>>>,
+}
+
+impl SecurityDialog {
+    /// Create the dialog and load the account's sessions
+    pub fn new(rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) -> Self {
+        let dialog = Self {
+            state: Arc::default(),
+            device_name: None,
+            password: String::new(),
+            confirm_logout: false,
+            login_code: String::new(),
+            approval: None,
+        };
+        dialog.reload(rt, ctx, client);
+        dialog
+    }
+
+    /// Whether the account was logged out and should be removed
+    pub fn logged_out(&self) -> bool {
+        self.state.lock().logged_out
+    }
+
+    /// Fetch the session list again
+    fn reload(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.sessions().await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(sessions) => state.sessions = sessions,
+                Err(e) => state.error = Some(format!("Failed to load sessions: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    fn rename(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        session_id: String,
+        name: String,
+    ) {
+        self.state.lock().busy = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.rename_session(&session_id, &name).await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => {
+                    if let Some(session) = state.sessions.iter_mut().find(|s| s.id == session_id) {
+                        session.name = name;
+                    }
+                }
+                Err(e) => state.error = Some(format!("Failed to rename session: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Sign out sessions, asking for the password if the server requires it
+    fn sign_out(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        session_ids: Vec<String>,
+        password: Option<String>,
+    ) {
+        {
+            let mut state = self.state.lock();
+            state.busy = true;
+            state.error = None;
+            state.password_for = None;
+        }
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client
+                .sign_out_sessions(&session_ids, password.as_deref())
+                .await;
+            let sessions = match result {
+                Ok(()) => Some(client.sessions().await),
+                Err(e) if e.is::<PasswordRequired>() => {
+                    state.lock().password_for = Some(session_ids);
+                    None
+                }
+                Err(e) => {
+                    state.lock().error = Some(format!("Failed to sign out: {}", e));
+                    None
+                }
+            };
+
+            let mut state = state.lock();
+            state.busy = false;
+            match sessions {
+                Some(Ok(sessions)) => state.sessions = sessions,
+                Some(Err(e)) => state.error = Some(format!("Failed to load sessions: {}", e)),
+                None => {}
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Let the device showing `code` sign in, then refresh the session list
+    fn approve_login(
+        &mut self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        code: String,
+    ) {
+        self.state.lock().error = None;
+        let progress = Arc::new(Mutex::new(LoginApproval::Connecting));
+        self.approval = Some(progress.clone());
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.approve_login(&code, progress).await;
+            let sessions = match result {
+                Ok(()) => client.sessions().await,
+                Err(e) => {
+                    state.lock().error = Some(format!("Failed to approve sign-in: {}", e));
+                    ctx.request_repaint();
+                    return;
+                }
+            };
+
+            let mut state = state.lock();
+            match sessions {
+                Ok(sessions) => state.sessions = sessions,
+                Err(e) => state.error = Some(format!("Failed to load sessions: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    fn logout(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        {
+            let mut state = self.state.lock();
+            state.busy = true;
+            state.error = None;
+        }
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.logout().await;
+
+            let mut state = state.lock();
+            state.busy = false;
+            match result {
+                Ok(()) => state.logged_out = true,
+                Err(e) => state.error = Some(format!("Failed to log out: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the dialog, returning `false` once it has been closed
+    pub fn show(&mut self, ctx: &egui::Context, rt: &Runtime, client: &Arc<dyn Client>) -> bool {
+        let mut open = true;
+        let mut rename = None;
+        let mut sign_out = None;
+        let mut confirm_password = false;
+        let mut logout = false;
+        let mut approve = None;
+
+        let (sessions, busy, error, password_for) = {
+            let state = self.state.lock();
+            (
+                state.sessions.clone(),
+                state.busy,
+                state.error.clone(),
+                state.password_for.clone(),
+            )
+        };
+        let current = sessions.iter().find(|session| session.current);
+        let others: Vec<&Session> = sessions.iter().filter(|session| !session.current).collect();
+
+        if self.device_name.is_none() {
+            self.device_name = current.map(|session| session.name.clone());
+        }
+
+        egui::Window::new(format!("Sessions · {}", client.self_id()))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(true)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                if let Some(error) = &error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if busy {
+                    ui.spinner();
+                }
+
+                ui.heading("This device");
+                if let (Some(session), Some(name)) = (current, &mut self.device_name) {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(name);
+                        let changed = !name.trim().is_empty() && name.trim() != session.name;
+                        if ui
+                            .add_enabled(changed && !busy, egui::Button::new("Rename"))
+                            .clicked()
+                        {
+                            rename = Some((session.id.clone(), name.trim().to_owned()));
+                        }
+                    });
+                    render_details(ui, session);
+                }
+
+                ui.separator();
+                ui.heading("Other sessions");
+                if others.is_empty() && !busy {
+                    ui.label(egui::RichText::new("No other sessions").weak());
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for session in &others {
+                            ui.horizontal(|ui| {
+                                ui.vertical(|ui| {
+                                    ui.label(egui::RichText::new(&session.name).strong());
+                                    render_details(ui, session);
+                                });
+                                ui.with_layout(
+                                    egui::Layout::right_to_left(egui::Align::Center),
+                                    |ui| {
+                                        if ui
+                                            .add_enabled(!busy, egui::Button::new("Sign out"))
+                                            .clicked()
+                                        {
+                                            sign_out = Some(vec![session.id.clone()]);
+                                        }
+                                    },
+                                );
+                            });
+                            ui.separator();
+                        }
+                    });
+
+                if others.len() > 1
+                    && ui
+                        .add_enabled(!busy, egui::Button::new("Sign out all other sessions"))
+                        .clicked()
+                {
+                    sign_out = Some(others.iter().map(|session| session.id.clone()).collect());
+                }
+
+                if password_for.is_some() {
+                    ui.separator();
+                    ui.label("Confirm with your account password");
+                    ui.horizontal(|ui| {
+                        let response =
+                            ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+                        let entered =
+                            response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.button("Confirm").clicked() || entered {
+                            confirm_password = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.state.lock().password_for = None;
+                            self.password.clear();
+                        }
+                    });
+                }
+
+                if client.approves_logins() {
+                    ui.separator();
+                    ui.heading("Sign in a new device");
+                    match self
+                        .approval
+                        .as_ref()
+                        .map(|progress| progress.lock().clone())
+                    {
+                        None => {
+                            ui.label("Paste the code shown by the new device");
+                            ui.horizontal(|ui| {
+                                ui.text_edit_singleline(&mut self.login_code);
+                                let code = self.login_code.trim();
+                                if ui
+                                    .add_enabled(!code.is_empty(), egui::Button::new("Approve"))
+                                    .clicked()
+                                {
+                                    approve = Some(code.to_owned());
+                                }
+                            });
+                        }
+                        Some(LoginApproval::Connecting) => {
+                            ui.spinner();
+                        }
+                        Some(LoginApproval::Done) => {
+                            ui.label("The new device is signed in");
+                            if ui.button("OK").clicked() {
+                                self.approval = None;
+                                self.login_code.clear();
+                            }
+                        }
+                    }
+                    if self.approval.is_some()
+                        && error.is_some()
+                        && ui.button("Try again").clicked()
+                    {
+                        self.approval = None;
+                    }
+                }
+
+                ui.separator();
+                if self.confirm_logout {
+                    ui.label("Log out of this account? Its local data will be deleted.");
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!busy, egui::Button::new("Log out"))
+                            .clicked()
+                        {
+                            logout = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_logout = false;
+                        }
+                    });
+                } else if ui.button("Log out").clicked() {
+                    self.confirm_logout = true;
+                }
+            });
+
+        if let Some((session_id, name)) = rename {
+            self.rename(rt, ctx, client, session_id, name);
+        }
+        if let Some(session_ids) = sign_out {
+            self.sign_out(rt, ctx, client, session_ids, None);
+        }
+        if let (true, Some(session_ids)) = (confirm_password, password_for) {
+            let password = std::mem::take(&mut self.password);
+            self.sign_out(rt, ctx, client, session_ids, Some(password));
+        }
+        if logout {
+            self.logout(rt, ctx, client);
+        }
+        if let Some(code) = approve {
+            self.approve_login(rt, ctx, client, code);
+        }
+        // The approval reports its steps without waking the UI
+        if self.approval.is_some() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(250));
+        }
+
+        open
+    }
+}
+
+/// Last activity, address and verification of a session
+fn render_details(ui: &mut egui::Ui, session: &Session) {
+    let mut details = Vec::new();
+    if let Some(time) = session
+        .last_seen
+        .and_then(|last_seen| chrono::DateTime::from_timestamp(last_seen as i64, 0))
+    {
+        details.push(format!("Last active {}", time.format("%Y-%m-%d %H:%M")));
+    }
+    if let Some(ip) = &session.last_ip {
+        details.push(ip.clone());
+    }
+
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(details.join(" · ")).weak().size(12.0));
+        match session.verified {
+            Some(true) => {
+                ui.label(
+                    egui::RichText::new("✔ Verified")
+                        .color(egui::Color32::from_rgb(67, 181, 129))
+                        .size(12.0),
+                );
+            }
+            Some(false) => {
+                ui.label(
+                    egui::RichText::new("⚠ Unverified")
+                        .color(ui.visuals().warn_fg_color)
+                        .size(12.0),
+                );
+            }
+            None => {}
+        }
+    });
+}
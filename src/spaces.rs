@@ -0,0 +1,199 @@
+use std::{borrow::Cow, sync::Arc};
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::{
+    clients::{Chat, Client, RoomPreview, Space},
+    directory::join_and_refresh,
+};
+
+/// Result of loading space children: `None` while the request is in flight
+type ChildrenState = Arc<Mutex<Option<Result<Vec<RoomPreview>, String>>>>;
+
+/// Window for browsing the children of a space and joining the unjoined ones
+pub struct SpaceBrowser {
+    space_id: String,
+    title: String,
+    children: ChildrenState,
+    joining: Arc<Mutex<Option<String>>>,
+    open: bool,
+}
+
+impl SpaceBrowser {
+    /// Create a browser for the given space and start loading its children
+    pub fn new(
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        space_id: &str,
+        title: &str,
+    ) -> Self {
+        let browser = Self {
+            space_id: space_id.to_owned(),
+            title: title.to_owned(),
+            children: Arc::default(),
+            joining: Arc::default(),
+            open: true,
+        };
+        browser.reload(rt, ctx, client);
+        browser
+    }
+
+    /// Fetch the space children again
+    fn reload(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        let client = client.clone();
+        let children = self.children.clone();
+        let space_id = self.space_id.clone();
+        let ctx = ctx.clone();
+
+        *children.lock() = None;
+        rt.spawn(async move {
+            let result = client
+                .space_children(&space_id)
+                .await
+                .map_err(|e| e.to_string());
+            *children.lock() = Some(result);
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the browser window, returning `false` once it has been closed
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        rt: &Runtime,
+        client: &Arc<dyn Client>,
+        chats: &Arc<Mutex<Vec<Chat>>>,
+        spaces: &Arc<Mutex<Vec<Space>>>,
+        selected_chat: &Arc<Mutex<Option<String>>>,
+    ) -> bool {
+        let mut join: Option<RoomPreview> = None;
+        let mut reload = false;
+
+        egui::Window::new(format!("Browse {}", self.title))
+            .open(&mut self.open)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                if ui.button("⟳ Refresh").clicked() {
+                    reload = true;
+                }
+                ui.separator();
+
+                match &*self.children.lock() {
+                    None => {
+                        ui.spinner();
+                    }
+                    Some(Err(error)) => {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    Some(Ok(children)) if children.is_empty() => {
+                        ui.label("This space has no rooms");
+                    }
+                    Some(Ok(children)) => {
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for child in children {
+                                let joining = self.joining.lock().clone();
+                                if render_room_preview(ui, child, joining.as_deref()) {
+                                    join = Some(child.clone());
+                                }
+                                ui.separator();
+                            }
+                        });
+                    }
+                }
+            });
+
+        if let Some(child) = join {
+            let client = client.clone();
+            let chats = chats.clone();
+            let spaces = spaces.clone();
+            let selected_chat = selected_chat.clone();
+            let joining = self.joining.clone();
+            let children = self.children.clone();
+            let ctx = ctx.clone();
+
+            *joining.lock() = Some(child.id.clone());
+            rt.spawn(async move {
+                match join_and_refresh(
+                    &client,
+                    &child.id,
+                    &child.via,
+                    &chats,
+                    &spaces,
+                    &selected_chat,
+                )
+                .await
+                {
+                    Ok(_) => {
+                        // Mark the child as joined
+                        if let Some(Ok(children)) = &mut *children.lock()
+                            && let Some(c) = children.iter_mut().find(|c| c.id == child.id)
+                        {
+                            c.joined = true;
+                        }
+                    }
+                    Err(e) => log::error!("Failed to join room: {}", e),
+                }
+                *joining.lock() = None;
+                ctx.request_repaint();
+            });
+        } else if reload {
+            self.reload(rt, ctx, client);
+        }
+
+        self.open
+    }
+}
+
+/// Render one room preview row, returning `true` if "Join" was clicked
+pub fn render_room_preview(ui: &mut egui::Ui, child: &RoomPreview, joining: Option<&str>) -> bool {
+    let mut clicked = false;
+
+    ui.horizontal(|ui| {
+        let avatar_size = egui::Vec2::new(32.0, 32.0);
+
+        if let Some(avatar) = &child.avatar {
+            ui.add(
+                egui::Image::new((
+                    Cow::Owned("room-preview-avatar-".to_owned() + child.id.as_str()),
+                    avatar.clone(),
+                ))
+                .fit_to_exact_size(avatar_size)
+                .corner_radius(5.0),
+            );
+        } else {
+            ui.add(egui::Label::new(if child.is_space { "🗂" } else { "📝" }).selectable(false));
+        }
+
+        ui.vertical(|ui| {
+            let name = child.name.as_deref().unwrap_or(child.id.as_str());
+            ui.label(egui::RichText::new(name).strong());
+            if let Some(alias) = child.alias.as_ref().filter(|_| child.name.is_some()) {
+                ui.label(egui::RichText::new(alias).weak().size(12.0));
+            }
+            if let Some(topic) = &child.topic {
+                ui.label(egui::RichText::new(topic).weak().size(12.0));
+            }
+            if child.member_count > 0 {
+                ui.label(
+                    egui::RichText::new(format!("{} members", child.member_count))
+                        .weak()
+                        .size(12.0),
+                );
+            }
+        });
+
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            if child.joined {
+                ui.label("Joined");
+            } else if joining == Some(child.id.as_str()) {
+                ui.spinner();
+            } else if ui.button("Join").clicked() {
+                clicked = true;
+            }
+        });
+    });
+
+    clicked
+}
@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::runtime::Runtime;
+
+use crate::{
+    clients::{Client, Event, EventKind, ThreadRoot},
+    composer::Composer,
+    message::{MessageStyle, MessageWidget},
+};
+
+/// What the thread panel is showing
+enum ThreadView {
+    /// All threads in the chat
+    List,
+    /// Replies to one thread root
+    Thread(Box<Event>),
+}
+
+/// Loading state shared with background tasks
+#[derive(Default)]
+struct ThreadState {
+    threads: Vec<ThreadRoot>,
+    /// Where the next page of the thread list starts, `None` once all are listed
+    next_batch: Option<String>,
+    loading: bool,
+    has_more: bool,
+    error: Option<String>,
+}
+
+/// Right-side panel listing a chat's threads and showing a single thread
+pub struct ThreadPanel {
+    chat_id: String,
+    view: ThreadView,
+    state: Arc<Mutex<ThreadState>>,
+    composer: Composer,
+}
+
+impl ThreadPanel {
+    /// Create a panel listing every thread in the chat
+    pub fn list(
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+    ) -> Self {
+        let mut panel = Self::empty(chat_id);
+        panel.show_list(rt, ctx, client);
+        panel
+    }
+
+    /// Create a panel showing the replies to `root`
+    pub fn thread(
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        chat_id: &str,
+        root: Event,
+    ) -> Self {
+        let mut panel = Self::empty(chat_id);
+        panel.open(rt, ctx, client, root);
+        panel
+    }
+
+    fn empty(chat_id: &str) -> Self {
+        Self {
+            chat_id: chat_id.to_owned(),
+            view: ThreadView::List,
+            state: Arc::default(),
+            composer: Composer::default(),
+        }
+    }
+
+    /// ID of the chat whose threads are shown
+    pub fn chat_id(&self) -> &str {
+        &self.chat_id
+    }
+
+    /// Switch to the thread list and reload it
+    fn show_list(&mut self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        self.view = ThreadView::List;
+        {
+            let mut state = self.state.lock();
+            state.threads.clear();
+            state.next_batch = None;
+        }
+        self.load_threads(rt, ctx, client, None);
+    }
+
+    /// Fetch a page of the thread list, starting at `from`
+    fn load_threads(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        from: Option<String>,
+    ) {
+        self.state.lock().loading = true;
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let chat_id = self.chat_id.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.threads(&chat_id, from.as_deref()).await;
+
+            let mut state = state.lock();
+            state.loading = false;
+            match result {
+                Ok(page) => {
+                    state.threads.extend(page.threads);
+                    state.next_batch = page.next_batch;
+                }
+                Err(e) => state.error = Some(format!("Failed to load threads: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Switch to a thread and load its latest replies
+    fn open(&mut self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>, root: Event) {
+        let root_id = root.id.clone();
+        self.view = ThreadView::Thread(Box::new(root));
+        {
+            let mut state = self.state.lock();
+            state.loading = true;
+            state.has_more = false;
+            state.error = None;
+        }
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.open_thread(&root_id).await;
+
+            let mut state = state.lock();
+            state.loading = false;
+            match result {
+                Ok(()) => state.has_more = true,
+                Err(e) => state.error = Some(format!("Failed to load thread: {}", e)),
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Fetch older replies of the open thread
+    fn load_more(&self, rt: &Runtime, ctx: &egui::Context, client: &Arc<dyn Client>) {
+        {
+            let mut state = self.state.lock();
+            if state.loading || !state.has_more {
+                return;
+            }
+            state.loading = true;
+        }
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            let result = client.load_more_thread_events().await;
+
+            let mut state = state.lock();
+            state.loading = false;
+            match result {
+                Ok(has_more) => state.has_more = has_more,
+                Err(e) => {
+                    state.has_more = false;
+                    state.error = Some(e.to_string());
+                }
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Send a reply into the open thread
+    fn send_reply(
+        &self,
+        rt: &Runtime,
+        ctx: &egui::Context,
+        client: &Arc<dyn Client>,
+        text: String,
+    ) {
+        let ThreadView::Thread(root) = &self.view else {
+            return;
+        };
+
+        let client = client.clone();
+        let state = self.state.clone();
+        let root_id = root.id.clone();
+        let ctx = ctx.clone();
+
+        rt.spawn(async move {
+            if let Err(e) = client.send_thread_reply(&root_id, &text).await {
+                state.lock().error = Some(format!("Failed to send reply: {}", e));
+            }
+            ctx.request_repaint();
+        });
+    }
+
+    /// Display the panel; must be called before the central panel.
+    /// Returns `false` once it has been closed.
+    pub fn show(&mut self, ctx: &egui::Context, rt: &Runtime, client: &Arc<dyn Client>) -> bool {
+        let mut open = true;
+        let mut back = false;
+        let mut opened = None;
+        let mut load_more = false;
+        let mut more_threads = None;
+        let mut send = None;
+
+        egui::SidePanel::right("thread_panel")
+            .resizable(true)
+            .default_width(320.0)
+            .width_range(240.0..=480.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    match &self.view {
+                        ThreadView::List => ui.heading("Threads"),
+                        ThreadView::Thread(_) => {
+                            if ui.button("⬅").on_hover_text("All threads").clicked() {
+                                back = true;
+                            }
+                            ui.heading("Thread")
+                        }
+                    };
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.button("✖").clicked() {
+                            open = false;
+                        }
+                    });
+                });
+                ui.separator();
+
+                if let Some(error) = &self.state.lock().error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                match &self.view {
+                    ThreadView::List => {
+                        let state = self.state.lock();
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            if !state.loading && state.threads.is_empty() {
+                                ui.label(egui::RichText::new("No threads yet").weak());
+                            }
+
+                            for thread in &state.threads {
+                                if render_thread_item(ui, thread).clicked() {
+                                    opened = Some(thread.root.clone());
+                                }
+                            }
+
+                            if state.loading {
+                                ui.spinner();
+                            } else if let Some(next_batch) = &state.next_batch {
+                                // Load older threads once the end of the list scrolls into view
+                                let response = ui.button("Load more threads");
+                                if response.clicked() || ui.is_rect_visible(response.rect) {
+                                    more_threads = Some(next_batch.clone());
+                                }
+                            }
+                        });
+                    }
+                    ThreadView::Thread(root) => {
+                        egui::Frame::group(ui.style()).show(ui, |ui| {
+                            ui.set_width(ui.available_width());
+                            if let EventKind::Message(text) = &root.kind {
+                                ui.label(text);
+                            }
+                        });
+                        ui.separator();
+
+                        // Composer stays at the bottom, replies fill the space above it
+                        egui::TopBottomPanel::bottom("thread_composer").show_inside(ui, |ui| {
+                            send = self.composer.show(
+                                ui,
+                                rt,
+                                client,
+                                &self.chat_id,
+                                "Reply in thread",
+                            );
+                        });
+
+                        egui::ScrollArea::vertical()
+                            .stick_to_bottom(true)
+                            .show(ui, |ui| {
+                                let state = self.state.lock();
+                                if state.loading {
+                                    ui.spinner();
+                                } else if state.has_more {
+                                    // Load older replies once the top scrolls into view
+                                    let response = ui.button("Load earlier replies");
+                                    if response.clicked() || ui.is_rect_visible(response.rect) {
+                                        load_more = true;
+                                    }
+                                }
+                                drop(state);
+
+                                if let Ok(groups) = client.thread_event_groups() {
+                                    for group in groups.lock().iter() {
+                                        MessageWidget::new(MessageStyle::default(), group.clone())
+                                            .show(ui);
+                                    }
+                                }
+                            });
+                    }
+                }
+            });
+
+        if back {
+            self.show_list(rt, ctx, client);
+        } else if let Some(root) = opened {
+            self.open(rt, ctx, client, root);
+        }
+        if load_more {
+            self.load_more(rt, ctx, client);
+        }
+        if let Some(from) = more_threads {
+            self.load_threads(rt, ctx, client, Some(from));
+        }
+        if let Some(text) = send {
+            self.send_reply(rt, ctx, client, text);
+        }
+
+        open
+    }
+}
+
+/// Render one thread in the overview list
+fn render_thread_item(ui: &mut egui::Ui, thread: &ThreadRoot) -> egui::Response {
+    let text = match &thread.root.kind {
+        EventKind::Message(text) => text.as_str(),
+        EventKind::System(_) => "",
+    };
+    let replies = thread
+        .root
+        .thread
+        .as_ref()
+        .map_or(0, |summary| summary.reply_count);
+
+    let response = egui::Frame::new()
+        .inner_margin(egui::Margin::symmetric(4, 4))
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            ui.label(egui::RichText::new(&thread.sender).strong().size(12.0));
+            ui.add(egui::Label::new(text).truncate().selectable(false));
+            ui.label(
+                egui::RichText::new(format!("💬 {}", replies))
+                    .weak()
+                    .size(11.0),
+            );
+        })
+        .response
+        .interact(egui::Sense::click());
+
+    if response.hovered() {
+        ui.painter().rect_filled(
+            response.rect,
+            4.0,
+            egui::Color32::from_rgba_premultiplied(100, 100, 100, 15),
+        );
+    }
+    ui.separator();
+
+    response
+}